@@ -4,75 +4,41 @@ use clap::{App, Arg, ArgMatches};
 
 use embedded_hal::{
     blocking::delay::{DelayMs, DelayUs},
-    digital::v2::{InputPin, OutputPin},
+    digital::v2::OutputPin,
 };
 
 use rppal::gpio::{Gpio, IoPin, Mode};
 use spin_sleep;
+use std::convert::Infallible;
+use std::fmt::Debug;
 use std::time;
-use void;
 
+use tmledkey_hal_drv::pins::{FlexPin, OpenDrain};
 use tmledkey_hal_drv::{self as tm, demo};
 
 /**
  * Raspberry pi does not have open drain pins so we have to emulate it.
- * rppal unfortunately is not able to emulate such pins.
+ * Crate does it with `OpenDrain` adapter, we only tell it how to switch pin mode.
+ * Error type is the same as rppal output pins have.
  */
-struct OpenPin {
-    iopin: IoPin,
-    mode: Mode,
-}
+struct RpiPin(IoPin);
 
-impl OpenPin {
-    fn new(mut pin: IoPin) -> OpenPin {
-        pin.set_mode(Mode::Input);
-        OpenPin {
-            iopin: pin,
-            mode: Mode::Input,
-        }
-    }
+impl FlexPin for RpiPin {
+    type Error = Infallible;
 
-    fn switch_input(&mut self) {
-        if self.mode != Mode::Input {
-            self.mode = Mode::Input;
-            self.iopin.set_mode(Mode::Input);
-        }
-    }
-
-    fn switch_output(&mut self) {
-        if self.mode != Mode::Output {
-            self.mode = Mode::Output;
-            self.iopin.set_mode(Mode::Output);
-        }
-    }
-}
-
-impl InputPin for OpenPin {
-    type Error = void::Void;
-
-    fn is_high(&self) -> Result<bool, Self::Error> {
-        Ok(self.iopin.is_high())
-    }
-
-    /// Is the input pin low?
-    fn is_low(&self) -> Result<bool, Self::Error> {
-        Ok(self.iopin.is_low())
+    fn set_input(&mut self) -> Result<(), Self::Error> {
+        self.0.set_mode(Mode::Input);
+        Ok(())
     }
-}
 
-impl OutputPin for OpenPin {
-    type Error = void::Void;
-
-    fn set_low(&mut self) -> Result<(), Self::Error> {
-        self.switch_output();
-        self.iopin.set_low();
+    fn set_output_low(&mut self) -> Result<(), Self::Error> {
+        self.0.set_mode(Mode::Output);
+        self.0.set_low();
         Ok(())
     }
 
-    fn set_high(&mut self) -> Result<(), Self::Error> {
-        self.iopin.set_high();
-        self.switch_input();
-        Ok(())
+    fn is_line_high(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.0.is_high())
     }
 }
 
@@ -166,7 +132,7 @@ fn demo_2_wire_start(dio_num: u8, clk_num: u8) {
         .expect("Was not able to get CLK pin")
         .into_io(Mode::Input);
 
-    let mut tm_dio = OpenPin::new(dio);
+    let mut tm_dio = OpenDrain::new(RpiPin(dio));
     let mut delay = Delayer {};
 
     demo_2_wire_run(&mut tm_dio, &mut clk, &mut delay);
@@ -174,13 +140,14 @@ fn demo_2_wire_start(dio_num: u8, clk_num: u8) {
 
 fn demo_2_wire_run<DIO, CLK, D>(dio: &mut DIO, clk: &mut CLK, delay: &mut D)
 where
-    DIO: InputPin + OutputPin,
-    CLK: OutputPin,
+    DIO: tm::Dio,
+    DIO::Error: Debug,
+    CLK: OutputPin<Error = DIO::Error>,
     D: DelayMs<u16> + DelayUs<u16>,
 {
-    let delay_time = tm::TM1637_BUS_DELAY_US;
+    let delay_time = tm::TM1637_TIMING;
 
-    println!("Starting 3 wire demo (TM1637)");
+    println!("Starting 2 wire demo (TM1637)");
     let mut demo = demo::Demo::new(4);
     let init_res = demo.init_2wire(dio, clk, &mut |d: u16| delay.delay_us(d), delay_time);
     println!("Display initialized {:?}", init_res);
@@ -222,7 +189,7 @@ fn demo_3_wire_start(dio_num: u8, clk_num: u8, stb_num: u8) {
         .expect("Was not able to get STB pin")
         .into_output();
 
-    let mut tm_dio = OpenPin::new(dio);
+    let mut tm_dio = OpenDrain::new(RpiPin(dio));
 
     let mut delayer = Delayer {};
 
@@ -231,12 +198,13 @@ fn demo_3_wire_start(dio_num: u8, clk_num: u8, stb_num: u8) {
 
 fn demo_3_wire_run<DIO, CLK, STB, D>(dio: &mut DIO, clk: &mut CLK, stb: &mut STB, delay: &mut D)
 where
-    DIO: InputPin + OutputPin,
-    CLK: OutputPin,
-    STB: OutputPin,
+    DIO: tm::Dio,
+    DIO::Error: Debug,
+    CLK: OutputPin<Error = DIO::Error>,
+    STB: OutputPin<Error = DIO::Error>,
     D: DelayMs<u16> + DelayUs<u16>,
 {
-    let delay_time = tm::TM1638_BUS_DELAY_US;
+    let delay_time = tm::TM1638_TIMING;
 
     println!("Starting 3 wire demo (TM1638)");
     let mut demo = demo::Demo::new(8);
@@ -1,35 +1,19 @@
 #![no_std]
 #![no_main]
-#![feature(lang_items)]
 
 use panic_halt as _;
 
-use cortex_m_rt::{entry, heap_start};
+use cortex_m_rt::entry;
 use cortex_m_semihosting::hprintln;
 use stm32f1xx_hal::{delay::Delay, pac, prelude::*};
 
-use alloc_cortex_m::CortexMHeap;
-use core::alloc::Layout;
+use core::fmt::Debug;
 use embedded_hal::digital::v2::{InputPin, OutputPin};
 
 use tmledkey_hal_drv::{self as tm, demo};
 
-// Plug in the allocator crate
-extern crate alloc;
-extern crate alloc_cortex_m;
-
-#[global_allocator]
-static ALLOCATOR: CortexMHeap = CortexMHeap::empty();
-
-use alloc::{string::String, vec::Vec, format};
-
 #[entry]
 fn main() -> ! {
-    // Initialize the allocator BEFORE you use it
-    let start = heap_start() as usize;
-    let size = 1024; // in bytes
-    unsafe { ALLOCATOR.init(start, size) }
-
     // Get access to the core peripherals from the cortex-m crate
     let cp = cortex_m::Peripherals::take().unwrap();
     // Get access to the device specific peripherals from the peripheral access crate
@@ -71,10 +55,12 @@ fn main() -> ! {
 fn demo_2wire<LED, DIO, CLK>(delay: &mut Delay, led: &mut LED, dio: &mut DIO, clk: &mut CLK) -> !
 where
     LED: OutputPin,
-    DIO: InputPin + OutputPin,
+    DIO: InputPin<Error = <DIO as OutputPin>::Error> + OutputPin,
     CLK: OutputPin,
+    <DIO as OutputPin>::Error: Debug,
+    CLK::Error: Debug,
 {
-    let delay_time = tm::TM1637_BUS_DELAY_US;
+    let delay_time = tm::TM1637_TIMING;
 
     hprintln!("Starting 2 wire demo (TM1637)");
 
@@ -120,11 +106,14 @@ fn demo_3wire<LED, DIO, CLK, STB>(
 ) -> !
 where
     LED: OutputPin,
-    DIO: InputPin + OutputPin,
+    DIO: InputPin<Error = <DIO as OutputPin>::Error> + OutputPin,
     CLK: OutputPin,
     STB: OutputPin,
+    <DIO as OutputPin>::Error: Debug,
+    CLK::Error: Debug,
+    STB::Error: Debug,
 {
-    let delay_time = tm::TM1638_BUS_DELAY_US;
+    let delay_time = tm::TM1638_TIMING;
     hprintln!("Starting 2 wire demo (TM1638)");
 
     let mut demo = demo::Demo::new(8);
@@ -142,13 +131,11 @@ where
                 if bytes != last_read {
                     last_read = bytes;
                     hprintln!(
-                        "Key scan read: {}",
-                        last_read
-                            .clone()
-                            .into_iter()
-                            .map(|b| format!("{:04b}_{:04b}", b >> 4, b & 0xF))
-                            .collect::<Vec<String>>()
-                            .join(", ")
+                        "Key scan read: {:08b}, {:08b}, {:08b}, {:08b}",
+                        bytes[0],
+                        bytes[1],
+                        bytes[2],
+                        bytes[3]
                     );
                 }
             }
@@ -167,12 +154,3 @@ where
         iter += 1;
     }
 }
-
-// required: define how Out Of Memory (OOM) conditions should be handled
-// *if* no other crate has already defined `oom`
-#[lang = "oom"]
-#[no_mangle]
-pub fn rust_oom(layout: Layout) -> ! {
-    hprintln!("OOM happens {}", layout.size());
-    loop {}
-}
@@ -0,0 +1,1105 @@
+//! Async bus functions and drivers built on `embedded_hal_async::delay::DelayNs` (**async** feature).
+//!
+//! Bit banging here runs exactly the same bus sequences as blocking functions,
+//! but every bus delay is awaited, so executor could run other tasks meanwhile.
+//! Pins are embedded-hal 1.0 pins, switching pin level does not need to be async.
+//!
+//! There are async versions of [`Tm1637`], [`Tm1640`] and [`Tm1638`] drivers.
+use super::*;
+
+use super::bus::{self, Lines, Op, Sequence, Wait};
+use super::eh1::Pin;
+use embedded_hal_1::digital::{ErrorType, InputPin as InputPin1, OutputPin as OutputPin1};
+use embedded_hal_async::delay::DelayNs;
+
+/// Runs sequence awaiting every delay, the first error is returned after bus is released.
+async fn run<L, DL>(
+    lines: &mut L,
+    delay: &mut DL,
+    seq: &mut Sequence<'_>,
+) -> Result<(), bus::LinesError<L>>
+where
+    L: Lines,
+    DL: DelayNs,
+{
+    let mut result = Ok(());
+    while let Some(op) = seq.next_op() {
+        let step = match op {
+            Op::Delay(us) => {
+                delay.delay_us(us as u32).await;
+                Ok(())
+            }
+            Op::Wait(wait) => self::wait(lines, delay, &wait).await,
+            op => bus::apply(lines, seq, op),
+        };
+        if let Err(e) = step {
+            if result.is_ok() {
+                result = Err(e);
+            }
+            seq.abort();
+        }
+    }
+    result
+}
+
+async fn wait<L, DL>(lines: &mut L, delay: &mut DL, wait: &Wait) -> Result<(), bus::LinesError<L>>
+where
+    L: Lines,
+    DL: DelayNs,
+{
+    let mut failed = wait.error(0);
+    for _ in 0..wait.polls {
+        match bus::check(lines, wait) {
+            Err(e @ TmError::Ack { .. }) => {
+                failed = e;
+                delay.delay_us(wait.delay_us as u32).await;
+            }
+            ready => return ready,
+        }
+    }
+    Err(failed)
+}
+
+/// Repeats 2 wire transfer on ACK errors according to policy, see blocking retry functions.
+#[cfg(feature = "clkdio")]
+async fn run_2wire_retry<DIO, CLK, DL, E, EC>(
+    dio: &mut DIO,
+    clk: &mut CLK,
+    delay: &mut DL,
+    timing: &BusTiming,
+    policy: &RetryPolicy,
+    failed_attempts: &mut u32,
+    seq: &mut Sequence<'_>,
+) -> Result<(), TmError<E, EC>>
+where
+    DIO: InputPin1 + OutputPin1 + ErrorType<Error = E>,
+    CLK: OutputPin1 + ErrorType<Error = EC>,
+    DL: DelayNs,
+{
+    let mut lines = bus::Bus2 {
+        dio: &mut Pin::new(dio),
+        clk: &mut Pin::new(clk),
+    };
+    let mut attempt = 1;
+    loop {
+        let result = run(&mut lines, delay, seq).await;
+        if let Err(TmError::Ack { .. }) = result {
+            *failed_attempts = failed_attempts.saturating_add(1);
+            if attempt < policy.max_attempts {
+                if policy.recovery_pulses > 0 {
+                    let mut recover = Sequence::recover_2wire(timing, policy.recovery_pulses);
+                    run(&mut lines, delay, &mut recover).await?;
+                }
+                let backoff = policy.backoff_us.saturating_mul(attempt as u16);
+                delay.delay_us(backoff as u32).await;
+                attempt += 1;
+                seq.restart();
+                continue;
+            }
+        }
+        return result;
+    }
+}
+
+/// Async version of [`tm_send_bytes_2wire`](super::tm_send_bytes_2wire).
+///
+/// Arguments:
+///  - `dio`, `clk` - MCU interface pins
+///  - `delay` - async delay provider
+///  - `timing` - bus timing or single delay value in us
+///  - `bytes` - slice of bytes to send
+#[cfg(feature = "clkdio")]
+pub async fn tm_send_bytes_2wire<DIO, CLK, DL, T, E, EC>(
+    dio: &mut DIO,
+    clk: &mut CLK,
+    delay: &mut DL,
+    timing: T,
+    bytes: &[u8],
+) -> Result<(), TmError<E, EC>>
+where
+    DIO: InputPin1 + OutputPin1 + ErrorType<Error = E>,
+    CLK: OutputPin1 + ErrorType<Error = EC>,
+    DL: DelayNs,
+    T: Into<BusTiming>,
+{
+    let policy = RetryPolicy::default();
+    tm_send_bytes_2wire_retry(dio, clk, delay, timing, bytes, &policy, &mut 0).await
+}
+
+/// Async version of [`tm_send_bytes_2wire_retry`](super::tm_send_bytes_2wire_retry).
+///
+/// Arguments:
+///  - `dio`, `clk` - MCU interface pins
+///  - `delay` - async delay provider
+///  - `timing` - bus timing or single delay value in us
+///  - `bytes` - slice of bytes to send
+///  - `policy` - number of attempts, backoff and recovery settings
+///  - `failed_attempts` - incremented on every attempt that failed because of ACK
+#[cfg(feature = "clkdio")]
+pub async fn tm_send_bytes_2wire_retry<DIO, CLK, DL, T, E, EC>(
+    dio: &mut DIO,
+    clk: &mut CLK,
+    delay: &mut DL,
+    timing: T,
+    bytes: &[u8],
+    policy: &RetryPolicy,
+    failed_attempts: &mut u32,
+) -> Result<(), TmError<E, EC>>
+where
+    DIO: InputPin1 + OutputPin1 + ErrorType<Error = E>,
+    CLK: OutputPin1 + ErrorType<Error = EC>,
+    DL: DelayNs,
+    T: Into<BusTiming>,
+{
+    let timing = timing.into();
+    let data = [bytes];
+    let mut seq = Sequence::write_2wire(&timing, policy.ack_polls, &data).ok_or(TmError::Input)?;
+    run_2wire_retry(dio, clk, delay, &timing, policy, failed_attempts, &mut seq).await
+}
+
+/// Async version of [`tm_send_bytes_2wire_noack`](super::tm_send_bytes_2wire_noack).
+///
+/// Arguments:
+///  - `dio`, `clk` - MCU interface pins, DIO is used only as output
+///  - `delay` - async delay provider
+///  - `timing` - bus timing or single delay value in us, see [`TM1640_TIMING`]
+///  - `bytes` - slice of bytes to send
+#[cfg(feature = "clkdio")]
+pub async fn tm_send_bytes_2wire_noack<DIO, CLK, DL, T, E, EC>(
+    dio: &mut DIO,
+    clk: &mut CLK,
+    delay: &mut DL,
+    timing: T,
+    bytes: &[u8],
+) -> Result<(), TmError<E, EC>>
+where
+    DIO: OutputPin1 + ErrorType<Error = E>,
+    CLK: OutputPin1 + ErrorType<Error = EC>,
+    DL: DelayNs,
+    T: Into<BusTiming>,
+{
+    let timing = timing.into();
+    let data = [bytes];
+    let mut seq = Sequence::write_2wire_noack(&timing, &data).ok_or(TmError::Input)?;
+    let mut lines = bus::Bus2 {
+        dio: &mut bus::WriteOnly(&mut Pin::new(dio)),
+        clk: &mut Pin::new(clk),
+    };
+    run(&mut lines, delay, &mut seq).await
+}
+
+/// Async version of [`tm_read_byte_2wire`](super::tm_read_byte_2wire).
+///
+/// Arguments:
+///  - `dio`, `clk` - MCU interface pins
+///  - `delay` - async delay provider
+///  - `timing` - bus timing or single delay value in us
+#[cfg(all(feature = "keys", feature = "clkdio"))]
+pub async fn tm_read_byte_2wire<DIO, CLK, DL, T, E, EC>(
+    dio: &mut DIO,
+    clk: &mut CLK,
+    delay: &mut DL,
+    timing: T,
+) -> Result<u8, TmError<E, EC>>
+where
+    DIO: InputPin1 + OutputPin1 + ErrorType<Error = E>,
+    CLK: OutputPin1 + ErrorType<Error = EC>,
+    DL: DelayNs,
+    T: Into<BusTiming>,
+{
+    let policy = RetryPolicy::default();
+    tm_read_byte_2wire_retry(dio, clk, delay, timing, &policy, &mut 0).await
+}
+
+/// Async version of [`tm_read_byte_2wire_retry`](super::tm_read_byte_2wire_retry).
+///
+/// Arguments:
+///  - `dio`, `clk` - MCU interface pins
+///  - `delay` - async delay provider
+///  - `timing` - bus timing or single delay value in us
+///  - `policy` - number of attempts, backoff and recovery settings
+///  - `failed_attempts` - incremented on every attempt that failed because of ACK
+#[cfg(all(feature = "keys", feature = "clkdio"))]
+pub async fn tm_read_byte_2wire_retry<DIO, CLK, DL, T, E, EC>(
+    dio: &mut DIO,
+    clk: &mut CLK,
+    delay: &mut DL,
+    timing: T,
+    policy: &RetryPolicy,
+    failed_attempts: &mut u32,
+) -> Result<u8, TmError<E, EC>>
+where
+    DIO: InputPin1 + OutputPin1 + ErrorType<Error = E>,
+    CLK: OutputPin1 + ErrorType<Error = EC>,
+    DL: DelayNs,
+    T: Into<BusTiming>,
+{
+    let timing = timing.into();
+    let command: [&[u8]; 1] = [&[COM_DATA_READ]];
+    let mut response = [0_u8; TM1637_RESPONSE_SIZE as usize];
+    let mut seq = Sequence::read_2wire(&timing, policy.ack_polls, &command, &mut response)
+        .ok_or(TmError::Input)?;
+    run_2wire_retry(dio, clk, delay, &timing, policy, failed_attempts, &mut seq).await?;
+    Ok(response[0])
+}
+
+/// Async version of [`tm_send_bytes_3wire`](super::tm_send_bytes_3wire).
+///
+/// Arguments:
+///  - `dio`, `clk`, `stb` - MCU interface pins
+///  - `delay` - async delay provider
+///  - `timing` - bus timing or single delay value in us
+///  - `bytes` - slice of bytes to send
+#[cfg(feature = "clkdiostb")]
+pub async fn tm_send_bytes_3wire<DIO, CLK, STB, DL, T, E, EC, ES>(
+    dio: &mut DIO,
+    clk: &mut CLK,
+    stb: &mut STB,
+    delay: &mut DL,
+    timing: T,
+    bytes: &[u8],
+) -> Result<(), TmError<E, EC, ES>>
+where
+    DIO: OutputPin1 + ErrorType<Error = E>,
+    CLK: OutputPin1 + ErrorType<Error = EC>,
+    STB: OutputPin1 + ErrorType<Error = ES>,
+    DL: DelayNs,
+    T: Into<BusTiming>,
+{
+    let timing = timing.into();
+    let data = [bytes];
+    let mut seq = Sequence::write_3wire(&timing, &data).ok_or(TmError::Input)?;
+    let mut lines = bus::Bus3 {
+        dio: &mut bus::WriteOnly(&mut Pin::new(dio)),
+        clk: &mut Pin::new(clk),
+        stb: &mut Pin::new(stb),
+    };
+    run(&mut lines, delay, &mut seq).await
+}
+
+/// Async version of [`tm_read_bytes_into_3wire`](super::tm_read_bytes_into_3wire).
+///
+/// Arguments:
+///  - `dio`, `clk`, `stb` - MCU interface pins
+///  - `delay` - async delay provider
+///  - `timing` - bus timing or single delay value in us
+///  - `command` - read command, usually [`COM_DATA_READ`]
+///  - `response` - buffer for received bytes
+#[cfg(all(feature = "keys", feature = "clkdiostb"))]
+pub async fn tm_read_bytes_into_3wire<DIO, CLK, STB, DL, T, E, EC, ES>(
+    dio: &mut DIO,
+    clk: &mut CLK,
+    stb: &mut STB,
+    delay: &mut DL,
+    timing: T,
+    command: u8,
+    response: &mut [u8],
+) -> Result<(), TmError<E, EC, ES>>
+where
+    DIO: InputPin1 + OutputPin1 + ErrorType<Error = E>,
+    CLK: OutputPin1 + ErrorType<Error = EC>,
+    STB: OutputPin1 + ErrorType<Error = ES>,
+    DL: DelayNs,
+    T: Into<BusTiming>,
+{
+    let timing = timing.into();
+    let command = [command];
+    let command = [&command[..]];
+    let mut seq = Sequence::read_3wire(&timing, &command, response).ok_or(TmError::Input)?;
+    let mut lines = bus::Bus3 {
+        dio: &mut Pin::new(dio),
+        clk: &mut Pin::new(clk),
+        stb: &mut Pin::new(stb),
+    };
+    run(&mut lines, delay, &mut seq).await
+}
+
+/// Async version of [`tm_read_bytes_3wire`](super::tm_read_bytes_3wire).
+///
+/// Arguments:
+///  - `dio`, `clk`, `stb` - MCU interface pins
+///  - `delay` - async delay provider
+///  - `timing` - bus timing or single delay value in us
+///  - `read_count` - number of bytes to read into output array
+#[cfg(all(feature = "keys", feature = "clkdiostb"))]
+pub async fn tm_read_bytes_3wire<DIO, CLK, STB, DL, T, E, EC, ES>(
+    dio: &mut DIO,
+    clk: &mut CLK,
+    stb: &mut STB,
+    delay: &mut DL,
+    timing: T,
+    read_count: u8,
+) -> Result<[u8; 4], TmError<E, EC, ES>>
+where
+    DIO: InputPin1 + OutputPin1 + ErrorType<Error = E>,
+    CLK: OutputPin1 + ErrorType<Error = EC>,
+    STB: OutputPin1 + ErrorType<Error = ES>,
+    DL: DelayNs,
+    T: Into<BusTiming>,
+{
+    let mut response = [0_u8; 4];
+    if read_count == 0 || read_count > response.len() as u8 {
+        return Err(TmError::Input);
+    }
+    let response_part = &mut response[..read_count as usize];
+    tm_read_bytes_into_3wire(dio, clk, stb, delay, timing, COM_DATA_READ, response_part).await?;
+    Ok(response)
+}
+
+/// Async TM1637 driver, same API as blocking [`tm1637::Tm1637`].
+#[cfg(feature = "clkdio")]
+pub struct Tm1637<DIO, CLK, DL> {
+    dio: DIO,
+    clk: CLK,
+    delay: DL,
+    timing: BusTiming,
+    brightness: u8,
+    display_on: bool,
+    retry: RetryPolicy,
+    failed_attempts: u32,
+}
+
+#[cfg(feature = "clkdio")]
+impl<DIO, CLK, DL, E, EC> Tm1637<DIO, CLK, DL>
+where
+    DIO: InputPin1 + OutputPin1 + ErrorType<Error = E>,
+    CLK: OutputPin1 + ErrorType<Error = EC>,
+    DL: DelayNs,
+{
+    /// Creates new driver instance without touching the bus.
+    /// Call [`init`](Tm1637::init) before writing anything.
+    ///
+    /// Arguments:
+    ///  - `dio`, `clk` - MCU interface pins
+    ///  - `delay` - async delay provider
+    ///  - `timing` - bus timing or single delay value in us, see [`TM1637_TIMING`]
+    pub fn new<T: Into<BusTiming>>(
+        dio: DIO,
+        clk: CLK,
+        delay: DL,
+        timing: T,
+    ) -> Tm1637<DIO, CLK, DL> {
+        Tm1637 {
+            dio,
+            clk,
+            delay,
+            timing: timing.into(),
+            brightness: DISPLAY_BRIGHTNESS_MASK,
+            display_on: true,
+            retry: RetryPolicy::default(),
+            failed_attempts: 0,
+        }
+    }
+
+    /// Power-on initialization, see [`tm1637::Tm1637::init`].
+    pub async fn init(&mut self) -> Result<(), TmError<E, EC>> {
+        self.clk.set_high().map_err(TmError::Clk)?;
+        self.dio.set_high().map_err(TmError::Dio)?;
+        self.send(&[COM_DATA_ADDRESS_ADD]).await?;
+        self.clear().await?;
+        self.send_display_control().await
+    }
+
+    /// Writes display bytes starting from the first display.
+    /// Up to [`TM1637_MAX_SEGMENTS`] bytes are allowed.
+    pub async fn write_digits(&mut self, digits: &[u8]) -> Result<(), TmError<E, EC>> {
+        self.write_digits_at(0, digits).await
+    }
+
+    /// Writes display bytes starting from display with given `offset`.
+    pub async fn write_digits_at(
+        &mut self,
+        offset: u8,
+        digits: &[u8],
+    ) -> Result<(), TmError<E, EC>> {
+        let mut buf = [0_u8; 1 + TM1637_MAX_SEGMENTS as usize];
+        let bytes = tm_address_write(&mut buf, TM1637_MAX_SEGMENTS, offset, digits)
+            .ok_or(TmError::Input)?;
+        self.send(bytes).await
+    }
+
+    /// Turns all displays off by writing empty bytes.
+    pub async fn clear(&mut self) -> Result<(), TmError<E, EC>> {
+        self.write_digits(&[0; TM1637_MAX_SEGMENTS as usize]).await
+    }
+
+    /// Set brightness level from 0 (dimmest) to 7 (brightest).
+    pub async fn set_brightness(&mut self, level: u8) -> Result<(), TmError<E, EC>> {
+        if level > DISPLAY_BRIGHTNESS_MASK {
+            return Err(TmError::Input);
+        }
+        self.brightness = level;
+        self.send_display_control().await
+    }
+
+    /// Turns display on keeping current brightness.
+    pub async fn display_on(&mut self) -> Result<(), TmError<E, EC>> {
+        self.display_on = true;
+        self.send_display_control().await
+    }
+
+    /// Turns display off. Display memory is kept intact.
+    pub async fn display_off(&mut self) -> Result<(), TmError<E, EC>> {
+        self.display_on = false;
+        self.send_display_control().await
+    }
+
+    /// Reads raw key scan byte.
+    #[cfg(feature = "keys")]
+    pub async fn read_keys(&mut self) -> Result<u8, TmError<E, EC>> {
+        tm_read_byte_2wire_retry(
+            &mut self.dio,
+            &mut self.clk,
+            &mut self.delay,
+            self.timing,
+            &self.retry,
+            &mut self.failed_attempts,
+        )
+        .await
+    }
+
+    /// Sends raw bytes to MCU, see [`tm_send_bytes_2wire_retry`].
+    pub async fn send(&mut self, bytes: &[u8]) -> Result<(), TmError<E, EC>> {
+        tm_send_bytes_2wire_retry(
+            &mut self.dio,
+            &mut self.clk,
+            &mut self.delay,
+            self.timing,
+            bytes,
+            &self.retry,
+            &mut self.failed_attempts,
+        )
+        .await
+    }
+
+    /// Sets how transfers are repeated when chip misses ACK.
+    /// By default there is only one attempt.
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry = policy;
+    }
+
+    /// Number of transfer attempts failed because of missing ACK since driver was created.
+    pub fn failed_attempts(&self) -> u32 {
+        self.failed_attempts
+    }
+
+    /// Destroys driver and gives back pins and delay provider.
+    pub fn release(self) -> (DIO, CLK, DL) {
+        (self.dio, self.clk, self.delay)
+    }
+
+    async fn send_display_control(&mut self) -> Result<(), TmError<E, EC>> {
+        let command = tm_display_control(self.display_on, self.brightness);
+        self.send(&[command]).await
+    }
+}
+
+/// Async TM1640 driver, same API as blocking [`tm1640::Tm1640`].
+#[cfg(feature = "clkdio")]
+pub struct Tm1640<DIO, CLK, DL> {
+    dio: DIO,
+    clk: CLK,
+    delay: DL,
+    timing: BusTiming,
+    brightness: u8,
+    display_on: bool,
+}
+
+#[cfg(feature = "clkdio")]
+impl<DIO, CLK, DL, E, EC> Tm1640<DIO, CLK, DL>
+where
+    DIO: OutputPin1 + ErrorType<Error = E>,
+    CLK: OutputPin1 + ErrorType<Error = EC>,
+    DL: DelayNs,
+{
+    /// Creates new driver instance without touching the bus.
+    /// Call [`init`](Tm1640::init) before writing anything.
+    ///
+    /// Arguments:
+    ///  - `dio`, `clk` - MCU interface pins, DIO is used only as output
+    ///  - `delay` - async delay provider
+    ///  - `timing` - bus timing or single delay value in us, see [`TM1640_TIMING`]
+    pub fn new<T: Into<BusTiming>>(
+        dio: DIO,
+        clk: CLK,
+        delay: DL,
+        timing: T,
+    ) -> Tm1640<DIO, CLK, DL> {
+        Tm1640 {
+            dio,
+            clk,
+            delay,
+            timing: timing.into(),
+            brightness: DISPLAY_BRIGHTNESS_MASK,
+            display_on: true,
+        }
+    }
+
+    /// Power-on initialization, see [`tm1640::Tm1640::init`].
+    pub async fn init(&mut self) -> Result<(), TmError<E, EC>> {
+        self.clk.set_high().map_err(TmError::Clk)?;
+        self.dio.set_high().map_err(TmError::Dio)?;
+        self.send(&[COM_DATA_ADDRESS_ADD]).await?;
+        self.clear().await?;
+        self.send_display_control().await
+    }
+
+    /// Writes display bytes starting from the first display.
+    /// Up to [`TM1640_MAX_SEGMENTS`] bytes are allowed.
+    pub async fn write_digits(&mut self, digits: &[u8]) -> Result<(), TmError<E, EC>> {
+        self.write_digits_at(0, digits).await
+    }
+
+    /// Writes display bytes starting from display with given `offset`.
+    pub async fn write_digits_at(
+        &mut self,
+        offset: u8,
+        digits: &[u8],
+    ) -> Result<(), TmError<E, EC>> {
+        let mut buf = [0_u8; 1 + TM1640_MAX_SEGMENTS as usize];
+        let bytes = tm_address_write(&mut buf, TM1640_MAX_SEGMENTS, offset, digits)
+            .ok_or(TmError::Input)?;
+        self.send(bytes).await
+    }
+
+    /// Turns all displays off by writing empty bytes.
+    pub async fn clear(&mut self) -> Result<(), TmError<E, EC>> {
+        self.write_digits(&[0; TM1640_MAX_SEGMENTS as usize]).await
+    }
+
+    /// Set brightness level from 0 (dimmest) to 7 (brightest).
+    pub async fn set_brightness(&mut self, level: u8) -> Result<(), TmError<E, EC>> {
+        if level > DISPLAY_BRIGHTNESS_MASK {
+            return Err(TmError::Input);
+        }
+        self.brightness = level;
+        self.send_display_control().await
+    }
+
+    /// Turns display on keeping current brightness.
+    pub async fn display_on(&mut self) -> Result<(), TmError<E, EC>> {
+        self.display_on = true;
+        self.send_display_control().await
+    }
+
+    /// Turns display off. Display memory is kept intact.
+    pub async fn display_off(&mut self) -> Result<(), TmError<E, EC>> {
+        self.display_on = false;
+        self.send_display_control().await
+    }
+
+    /// Sends raw bytes to MCU, see [`tm_send_bytes_2wire_noack`].
+    pub async fn send(&mut self, bytes: &[u8]) -> Result<(), TmError<E, EC>> {
+        tm_send_bytes_2wire_noack(
+            &mut self.dio,
+            &mut self.clk,
+            &mut self.delay,
+            self.timing,
+            bytes,
+        )
+        .await
+    }
+
+    /// Destroys driver and gives back pins and delay provider.
+    pub fn release(self) -> (DIO, CLK, DL) {
+        (self.dio, self.clk, self.delay)
+    }
+
+    async fn send_display_control(&mut self) -> Result<(), TmError<E, EC>> {
+        let command = tm_display_control(self.display_on, self.brightness);
+        self.send(&[command]).await
+    }
+}
+
+/// Async TM1638 driver, same API as blocking [`tm1638::Tm1638`].
+#[cfg(feature = "clkdiostb")]
+pub struct Tm1638<DIO, CLK, STB, DL> {
+    dio: DIO,
+    clk: CLK,
+    stb: STB,
+    delay: DL,
+    timing: BusTiming,
+    brightness: u8,
+    display_on: bool,
+}
+
+#[cfg(feature = "clkdiostb")]
+impl<DIO, CLK, STB, DL, E, EC, ES> Tm1638<DIO, CLK, STB, DL>
+where
+    DIO: InputPin1 + OutputPin1 + ErrorType<Error = E>,
+    CLK: OutputPin1 + ErrorType<Error = EC>,
+    STB: OutputPin1 + ErrorType<Error = ES>,
+    DL: DelayNs,
+{
+    /// Creates new driver instance without touching the bus.
+    /// Call [`init`](Tm1638::init) before writing anything.
+    ///
+    /// Arguments:
+    ///  - `dio`, `clk`, `stb` - MCU interface pins
+    ///  - `delay` - async delay provider
+    ///  - `timing` - bus timing or single delay value in us, see [`TM1638_TIMING`]
+    pub fn new<T: Into<BusTiming>>(
+        dio: DIO,
+        clk: CLK,
+        stb: STB,
+        delay: DL,
+        timing: T,
+    ) -> Tm1638<DIO, CLK, STB, DL> {
+        Tm1638 {
+            dio,
+            clk,
+            stb,
+            delay,
+            timing: timing.into(),
+            brightness: DISPLAY_BRIGHTNESS_MASK,
+            display_on: true,
+        }
+    }
+
+    /// Power-on initialization, see [`tm1638::Tm1638::init`].
+    pub async fn init(&mut self) -> Result<(), TmError<E, EC, ES>> {
+        self.clk.set_high().map_err(TmError::Clk)?;
+        self.dio.set_high().map_err(TmError::Dio)?;
+        self.stb.set_high().map_err(TmError::Stb)?;
+        self.send(&[COM_DATA_ADDRESS_ADD]).await?;
+        self.clear().await?;
+        self.send_display_control().await
+    }
+
+    /// Writes both bytes of one grid, see [`tm1638::Tm1638::write_grid`].
+    pub async fn write_grid(
+        &mut self,
+        index: u8,
+        seg_lo: u8,
+        seg_hi: u8,
+    ) -> Result<(), TmError<E, EC, ES>> {
+        if index >= tm1638::TM1638_GRIDS {
+            return Err(TmError::Input);
+        }
+        self.send(&[COM_ADDRESS | (index * 2), seg_lo, seg_hi])
+            .await
+    }
+
+    /// Writes display bytes (segments 1-8) starting from the first grid.
+    /// Segments 9-10 are cleared for every written grid.
+    pub async fn write_digits(&mut self, digits: &[u8]) -> Result<(), TmError<E, EC, ES>> {
+        let grids = tm1638::TM1638_GRIDS as usize;
+        if digits.len() > grids {
+            return Err(TmError::Input);
+        }
+        let mut bytes = [0_u8; 1 + 2 * tm1638::TM1638_GRIDS as usize];
+        bytes[0] = COM_ADDRESS;
+        for (i, d) in digits.iter().enumerate() {
+            bytes[1 + i * 2] = *d;
+        }
+        self.send(&bytes[..1 + 2 * digits.len()]).await
+    }
+
+    /// Turns all displays off by writing empty bytes.
+    pub async fn clear(&mut self) -> Result<(), TmError<E, EC, ES>> {
+        let mut bytes = [0_u8; 1 + 2 * tm1638::TM1638_GRIDS as usize];
+        bytes[0] = COM_ADDRESS;
+        self.send(&bytes).await
+    }
+
+    /// Set brightness level from 0 (dimmest) to 7 (brightest).
+    pub async fn set_brightness(&mut self, level: u8) -> Result<(), TmError<E, EC, ES>> {
+        if level > DISPLAY_BRIGHTNESS_MASK {
+            return Err(TmError::Input);
+        }
+        self.brightness = level;
+        self.send_display_control().await
+    }
+
+    /// Turns display on keeping current brightness.
+    pub async fn display_on(&mut self) -> Result<(), TmError<E, EC, ES>> {
+        self.display_on = true;
+        self.send_display_control().await
+    }
+
+    /// Turns display off. Display memory is kept intact.
+    pub async fn display_off(&mut self) -> Result<(), TmError<E, EC, ES>> {
+        self.display_on = false;
+        self.send_display_control().await
+    }
+
+    /// Reads key scan data and decodes it with [`tm1638::decode_keys`].
+    #[cfg(feature = "keys")]
+    pub async fn read_keys(&mut self) -> Result<u32, TmError<E, EC, ES>> {
+        let scan = self.read_key_scan().await?;
+        Ok(tm1638::decode_keys(&scan))
+    }
+
+    /// Reads raw 4 bytes of key scan data.
+    #[cfg(feature = "keys")]
+    pub async fn read_key_scan(&mut self) -> Result<[u8; 4], TmError<E, EC, ES>> {
+        let mut scan = [0_u8; TM1638_RESPONSE_SIZE as usize];
+        tm_read_bytes_into_3wire(
+            &mut self.dio,
+            &mut self.clk,
+            &mut self.stb,
+            &mut self.delay,
+            self.timing,
+            COM_DATA_READ,
+            &mut scan,
+        )
+        .await?;
+        Ok(scan)
+    }
+
+    /// Sends raw bytes to MCU, see [`tm_send_bytes_3wire`].
+    pub async fn send(&mut self, bytes: &[u8]) -> Result<(), TmError<E, EC, ES>> {
+        tm_send_bytes_3wire(
+            &mut self.dio,
+            &mut self.clk,
+            &mut self.stb,
+            &mut self.delay,
+            self.timing,
+            bytes,
+        )
+        .await
+    }
+
+    /// Destroys driver and gives back pins and delay provider.
+    pub fn release(self) -> (DIO, CLK, STB, DL) {
+        (self.dio, self.clk, self.stb, self.delay)
+    }
+
+    async fn send_display_control(&mut self) -> Result<(), TmError<E, EC, ES>> {
+        let command = tm_display_control(self.display_on, self.brightness);
+        self.send(&[command]).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use core::cell::RefCell;
+    use core::future::Future;
+    use core::pin::Pin;
+    use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+    /// Polls future to completion on current thread, enough for mock pins and delays.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        fn clone(_: *const ()) -> RawWaker {
+            RawWaker::new(core::ptr::null(), &VTABLE)
+        }
+        fn noop(_: *const ()) {}
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+
+        let waker = unsafe { Waker::from_raw(clone(core::ptr::null())) };
+        let mut cx = Context::from_waker(&waker);
+        let mut future = future;
+        let mut future = unsafe { Pin::new_unchecked(&mut future) };
+        loop {
+            if let Poll::Ready(out) = future.as_mut().poll(&mut cx) {
+                return out;
+            }
+        }
+    }
+
+    const TRACE_SIZE: usize = 2048;
+
+    /// Sequence of pin levels and delays, both blocking and async calls are written here.
+    struct Trace {
+        len: usize,
+        events: [(u8, u32); TRACE_SIZE],
+        pending: u32,
+    }
+
+    impl Trace {
+        fn new() -> Trace {
+            Trace {
+                len: 0,
+                events: [(0, 0); TRACE_SIZE],
+                pending: 0,
+            }
+        }
+
+        fn push(&mut self, pin: u8, value: u32) {
+            self.events[self.len] = (pin, value);
+            self.len += 1;
+        }
+
+        fn events(&self) -> &[(u8, u32)] {
+            &self.events[..self.len]
+        }
+    }
+
+    const DELAY: u8 = 0;
+
+    #[derive(Debug, PartialEq)]
+    struct PinFault;
+
+    impl embedded_hal_1::digital::Error for PinFault {
+        fn kind(&self) -> embedded_hal_1::digital::ErrorKind {
+            embedded_hal_1::digital::ErrorKind::Other
+        }
+    }
+
+    /// Pin that writes levels into trace. It reads back always low (as if chip holds DIO down).
+    struct TracePin<'a> {
+        id: u8,
+        trace: &'a RefCell<Trace>,
+    }
+
+    impl ErrorType for TracePin<'_> {
+        type Error = PinFault;
+    }
+
+    impl OutputPin1 for TracePin<'_> {
+        fn set_low(&mut self) -> Result<(), PinFault> {
+            self.trace.borrow_mut().push(self.id, 0);
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), PinFault> {
+            self.trace.borrow_mut().push(self.id, 1);
+            Ok(())
+        }
+    }
+
+    impl InputPin1 for TracePin<'_> {
+        fn is_high(&mut self) -> Result<bool, PinFault> {
+            Ok(false)
+        }
+
+        fn is_low(&mut self) -> Result<bool, PinFault> {
+            Ok(true)
+        }
+    }
+
+    /// Async delay that returns `Pending` once before completing.
+    struct TraceDelay<'a> {
+        trace: &'a RefCell<Trace>,
+    }
+
+    struct YieldOnce(bool);
+
+    impl Future for YieldOnce {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            if self.0 {
+                return Poll::Ready(());
+            }
+            self.0 = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+
+    impl DelayNs for TraceDelay<'_> {
+        async fn delay_ns(&mut self, ns: u32) {
+            self.trace.borrow_mut().pending += 1;
+            YieldOnce(false).await;
+            self.trace.borrow_mut().push(DELAY, ns / 1000);
+        }
+    }
+
+    fn pins(trace: &RefCell<Trace>) -> (TracePin<'_>, TracePin<'_>, TracePin<'_>) {
+        (
+            TracePin { id: 1, trace },
+            TracePin { id: 2, trace },
+            TracePin { id: 3, trace },
+        )
+    }
+
+    #[test]
+    #[cfg(feature = "clkdiostb")]
+    fn send_3wire_matches_blocking_test() {
+        let bytes = [COM_ADDRESS, CHAR_1, 0, CHAR_2, 0];
+
+        let blocking = RefCell::new(Trace::new());
+        let (dio, clk, stb) = pins(&blocking);
+        let mut delay = |us: u16| blocking.borrow_mut().push(DELAY, us as u32);
+        super::super::tm_send_bytes_3wire(
+            &mut eh1::Pin::new(dio),
+            &mut eh1::Pin::new(clk),
+            &mut eh1::Pin::new(stb),
+            &mut delay,
+            7,
+            &bytes,
+        )
+        .unwrap();
+
+        let non_blocking = RefCell::new(Trace::new());
+        let (mut dio, mut clk, mut stb) = pins(&non_blocking);
+        let mut delay = TraceDelay {
+            trace: &non_blocking,
+        };
+        block_on(tm_send_bytes_3wire(
+            &mut dio, &mut clk, &mut stb, &mut delay, 7, &bytes,
+        ))
+        .unwrap();
+
+        let non_blocking = non_blocking.borrow();
+        assert_eq!(blocking.borrow().events(), non_blocking.events());
+        assert_eq!(non_blocking.pending, 3 + 3 * 8 * bytes.len() as u32);
+    }
+
+    #[test]
+    #[cfg(all(feature = "keys", feature = "clkdiostb"))]
+    fn read_3wire_matches_blocking_test() {
+        let blocking = RefCell::new(Trace::new());
+        let (dio, clk, stb) = pins(&blocking);
+        let mut delay = |us: u16| blocking.borrow_mut().push(DELAY, us as u32);
+        let expected = super::super::tm_read_bytes_3wire(
+            &mut eh1::Pin::new(dio),
+            &mut eh1::Pin::new(clk),
+            &mut eh1::Pin::new(stb),
+            &mut delay,
+            7,
+            2,
+        )
+        .unwrap();
+
+        let non_blocking = RefCell::new(Trace::new());
+        let (mut dio, mut clk, mut stb) = pins(&non_blocking);
+        let mut delay = TraceDelay {
+            trace: &non_blocking,
+        };
+        let result = block_on(tm_read_bytes_3wire(
+            &mut dio, &mut clk, &mut stb, &mut delay, 7, 2,
+        ))
+        .unwrap();
+        assert_eq!(expected, result);
+        assert_eq!(blocking.borrow().events(), non_blocking.borrow().events());
+
+        let result = block_on(tm_read_bytes_3wire(
+            &mut dio, &mut clk, &mut stb, &mut delay, 7, 5,
+        ));
+        assert!(matches!(result, Err(TmError::Input)));
+    }
+
+    #[test]
+    #[cfg(feature = "clkdio")]
+    fn send_2wire_matches_blocking_test() {
+        // DIO is never released by mock, so ACK fails right after 9th clock
+        let blocking = RefCell::new(Trace::new());
+        let (dio, clk, _) = pins(&blocking);
+        let mut delay = |us: u16| blocking.borrow_mut().push(DELAY, us as u32);
+        let expected = super::super::tm_send_bytes_2wire(
+            &mut eh1::Pin::new(dio),
+            &mut eh1::Pin::new(clk),
+            &mut delay,
+            5,
+            &[COM_DATA_ADDRESS_ADD],
+        );
+
+        let non_blocking = RefCell::new(Trace::new());
+        let (mut dio, mut clk, _) = pins(&non_blocking);
+        let mut delay = TraceDelay {
+            trace: &non_blocking,
+        };
+        let result = block_on(tm_send_bytes_2wire(
+            &mut dio,
+            &mut clk,
+            &mut delay,
+            5,
+            &[COM_DATA_ADDRESS_ADD],
+        ));
+
+        match (expected, result) {
+            (
+                Err(TmError::Ack {
+                    byte: a, phase: pa, ..
+                }),
+                Err(TmError::Ack {
+                    byte: b, phase: pb, ..
+                }),
+            ) => {
+                // DIO always reads low, so chip never releases it after ACK
+                assert_eq!((a, pa), (0, AckPhase::Release));
+                assert_eq!((a, pa), (b, pb));
+            }
+            other => panic!("unexpected results {:?}", other),
+        }
+        assert_eq!(blocking.borrow().events(), non_blocking.borrow().events());
+    }
+
+    #[test]
+    #[cfg(feature = "clkdio")]
+    fn send_2wire_retry_test() {
+        let trace = RefCell::new(Trace::new());
+        let (mut dio, mut clk, _) = pins(&trace);
+        let mut delay = TraceDelay { trace: &trace };
+        let policy = RetryPolicy {
+            ack_polls: 1,
+            ..RetryPolicy::new(3, 10)
+        };
+        let mut failed = 0;
+        let result = block_on(tm_send_bytes_2wire_retry(
+            &mut dio,
+            &mut clk,
+            &mut delay,
+            1,
+            &[COM_DATA_ADDRESS_ADD],
+            &policy,
+            &mut failed,
+        ));
+        assert!(matches!(
+            result,
+            Err(TmError::Ack {
+                byte: 0,
+                phase: AckPhase::Release,
+                ..
+            })
+        ));
+        assert_eq!(failed, 3);
+        // Backoff grows with every attempt
+        let backoff: u32 = trace
+            .borrow()
+            .events()
+            .iter()
+            .filter(|(pin, us)| *pin == DELAY && *us >= 10)
+            .map(|(_, us)| *us)
+            .sum();
+        assert_eq!(backoff, 10 + 20);
+    }
+
+    #[test]
+    #[cfg(feature = "clkdio")]
+    fn driver_retry_test() {
+        let trace = RefCell::new(Trace::new());
+        let (dio, clk, _) = pins(&trace);
+        let mut tm = Tm1637::new(dio, clk, TraceDelay { trace: &trace }, 1);
+        assert!(matches!(
+            block_on(tm.send(&[COM_DATA_ADDRESS_ADD])),
+            Err(TmError::Ack { .. })
+        ));
+        assert_eq!(tm.failed_attempts(), 1);
+
+        tm.set_retry_policy(RetryPolicy {
+            ack_polls: 1,
+            ..RetryPolicy::new(3, 10)
+        });
+        assert!(matches!(
+            block_on(tm.set_brightness(2)),
+            Err(TmError::Ack { .. })
+        ));
+        assert_eq!(tm.failed_attempts(), 4);
+
+        #[cfg(feature = "keys")]
+        {
+            assert!(block_on(tm.read_keys()).is_err());
+            assert_eq!(tm.failed_attempts(), 7);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "clkdio")]
+    fn driver_input_check_test() {
+        let trace = RefCell::new(Trace::new());
+        let (dio, clk, _) = pins(&trace);
+        let mut tm = Tm1640::new(dio, clk, TraceDelay { trace: &trace }, 1);
+        assert!(matches!(
+            block_on(tm.set_brightness(8)),
+            Err(TmError::Input)
+        ));
+        block_on(tm.write_digits(&[CHAR_0; 16])).unwrap();
+        // Start, 17 bytes, stop
+        assert_eq!(trace.borrow().pending, 1 + 17 * 3 * 8 + 4);
+    }
+}
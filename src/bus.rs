@@ -0,0 +1,809 @@
+//! Phase sequences of 2 and 3 wire bus, shared by blocking, async and parallel transfers.
+//!
+//! [`Sequence`] describes one transfer as a stream of [`Op`]s: line levels, delays,
+//! DIO checks and reads. Executor applies ops to its [`Lines`] and feeds read levels back.
+//! When some op fails executor calls [`Sequence::abort`], so stop sequence or STB release
+//! still goes out before the first error is returned.
+use super::*;
+
+/// Single bus action.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Op {
+    /// Set CLK level
+    Clk(bool),
+    /// Set STB level
+    Stb(bool),
+    /// Drive every DIO line low or release it high
+    Dio(bool),
+    /// Put given bit of current byte on DIO, every line gets bit of its own byte
+    Bit(u8),
+    /// Wait for given number of us
+    Delay(u16),
+    /// Poll DIO till it has expected level
+    Wait(Wait),
+    /// Read DIO as given bit of current response byte, MSB first
+    Sample(u8),
+}
+
+/// DIO check, see [`Op::Wait`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Wait {
+    /// Expected DIO level
+    pub high: bool,
+    /// Number of checks
+    pub polls: u8,
+    /// Delay after every failed check
+    pub delay_us: u16,
+    /// Index of byte for error report
+    pub byte: usize,
+    /// Phase for error report
+    pub phase: AckPhase,
+}
+
+impl Wait {
+    /// Error for the case when given DIO line did not get expected level.
+    pub fn error<E, C, S>(&self, line: usize) -> TmError<E, C, S> {
+        TmError::Ack {
+            byte: self.byte,
+            phase: self.phase,
+            line,
+        }
+    }
+}
+
+/// Error of [`Lines`] that keeps every pin error type.
+pub(crate) type LinesError<L> =
+    TmError<<L as Lines>::DioError, <L as Lines>::ClkError, <L as Lines>::StbError>;
+
+/// Pins that [`Op`]s are applied to, one CLK (and STB) with one or several DIO lines.
+pub(crate) trait Lines {
+    type DioError;
+    type ClkError;
+    type StbError;
+
+    /// Number of DIO lines.
+    fn count(&self) -> usize {
+        1
+    }
+
+    fn set_clk(&mut self, high: bool) -> Result<(), LinesError<Self>>;
+
+    fn set_stb(&mut self, high: bool) -> Result<(), LinesError<Self>>;
+
+    fn set_dio(&mut self, line: usize, high: bool) -> Result<(), LinesError<Self>>;
+
+    fn is_dio_high(&mut self, line: usize) -> Result<bool, LinesError<Self>>;
+}
+
+/// DIO and CLK pins of 2 wire bus.
+#[cfg(feature = "clkdio")]
+pub(crate) struct Bus2<'p, DIO, CLK> {
+    pub dio: &'p mut DIO,
+    pub clk: &'p mut CLK,
+}
+
+#[cfg(feature = "clkdio")]
+impl<DIO, CLK, E, EC> Lines for Bus2<'_, DIO, CLK>
+where
+    DIO: Dio<Error = E>,
+    CLK: OutputPin<Error = EC>,
+{
+    type DioError = E;
+    type ClkError = EC;
+    /// There is no STB, it is never failed
+    type StbError = E;
+
+    fn set_clk(&mut self, high: bool) -> Result<(), TmError<E, EC>> {
+        self.clk.set_dio(high).map_err(TmError::Clk)
+    }
+
+    fn set_stb(&mut self, _: bool) -> Result<(), TmError<E, EC>> {
+        Ok(())
+    }
+
+    fn set_dio(&mut self, _: usize, high: bool) -> Result<(), TmError<E, EC>> {
+        self.dio.set_dio(high).map_err(TmError::Dio)
+    }
+
+    fn is_dio_high(&mut self, _: usize) -> Result<bool, TmError<E, EC>> {
+        self.dio.is_dio_high().map_err(TmError::Dio)
+    }
+}
+
+/// DIO, CLK and STB pins of 3 wire bus.
+#[cfg(feature = "clkdiostb")]
+pub(crate) struct Bus3<'p, DIO, CLK, STB> {
+    pub dio: &'p mut DIO,
+    pub clk: &'p mut CLK,
+    pub stb: &'p mut STB,
+}
+
+#[cfg(feature = "clkdiostb")]
+impl<DIO, CLK, STB, E, EC, ES> Lines for Bus3<'_, DIO, CLK, STB>
+where
+    DIO: Dio<Error = E>,
+    CLK: OutputPin<Error = EC>,
+    STB: OutputPin<Error = ES>,
+{
+    type DioError = E;
+    type ClkError = EC;
+    type StbError = ES;
+
+    fn set_clk(&mut self, high: bool) -> Result<(), TmError<E, EC, ES>> {
+        self.clk.set_dio(high).map_err(TmError::Clk)
+    }
+
+    fn set_stb(&mut self, high: bool) -> Result<(), TmError<E, EC, ES>> {
+        self.stb.set_dio(high).map_err(TmError::Stb)
+    }
+
+    fn set_dio(&mut self, _: usize, high: bool) -> Result<(), TmError<E, EC, ES>> {
+        self.dio.set_dio(high).map_err(TmError::Dio)
+    }
+
+    fn is_dio_high(&mut self, _: usize) -> Result<bool, TmError<E, EC, ES>> {
+        self.dio.is_dio_high().map_err(TmError::Dio)
+    }
+}
+
+/// Output only DIO for sequences that never read it, reads as released line.
+#[cfg(any(feature = "clkdio", feature = "async"))]
+pub(crate) struct WriteOnly<'p, DIO>(pub &'p mut DIO);
+
+#[cfg(any(feature = "clkdio", feature = "async"))]
+impl<DIO: DioOut> DioOut for WriteOnly<'_, DIO> {
+    type Error = DIO::Error;
+
+    fn set_dio(&mut self, high: bool) -> Result<(), DIO::Error> {
+        self.0.set_dio(high)
+    }
+}
+
+#[cfg(any(feature = "clkdio", feature = "async"))]
+impl<DIO: DioOut> Dio for WriteOnly<'_, DIO> {
+    fn is_dio_high(&mut self) -> Result<bool, DIO::Error> {
+        Ok(true)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(not(all(feature = "clkdio", feature = "clkdiostb")), allow(dead_code))]
+enum Kind {
+    /// 2 wire write, `ack` tells if chip acknowledges bytes
+    Write2 { ack: bool },
+    /// 2 wire read command followed by response bytes, every byte is acknowledged
+    Read2,
+    /// 3 wire write
+    Write3,
+    /// 3 wire read command followed by response bytes
+    Read3,
+    /// CLK pulses with released DIO followed by stop sequence without ACK
+    Recover { pulses: u8 },
+    /// Check that released DIO is pulled up
+    Idle,
+    /// 2 wire start sequence only
+    Start,
+    /// 2 wire stop sequence only, with given number of transferred bytes
+    Stop,
+    /// Bare bytes without start and stop
+    Bits,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(not(all(feature = "clkdio", feature = "clkdiostb")), allow(dead_code))]
+enum Stage {
+    /// 2 wire start sequence
+    Start,
+    /// STB goes low
+    Strobe,
+    /// 8 bits of current byte
+    Send,
+    /// 2 wire ACK of current byte
+    Ack,
+    /// 3 wire DIO release before reading
+    Turn,
+    /// 8 bits of current response byte
+    Receive,
+    /// Recovery CLK pulses
+    Pulses,
+    /// 2 wire stop sequence, chip releases DIO after it
+    Stop,
+    /// 2 wire stop sequence for chips without ACK
+    StopNoAck,
+    /// STB goes high, lines return to idle
+    Release,
+    /// Released DIO check
+    Idle,
+    Done,
+}
+
+/// Ops of one transfer, see [module documentation](self).
+pub(crate) struct Sequence<'a> {
+    kind: Kind,
+    timing: BusTiming,
+    polls: u8,
+    /// Bytes to send for every DIO line
+    data: &'a [&'a [u8]],
+    response: &'a mut [u8],
+    stage: Stage,
+    step: usize,
+    /// Current byte in `data`
+    index: usize,
+    /// Current byte in `response`
+    read: usize,
+}
+
+impl<'a> Sequence<'a> {
+    fn new(
+        kind: Kind,
+        timing: &BusTiming,
+        polls: u8,
+        data: &'a [&'a [u8]],
+        response: &'a mut [u8],
+    ) -> Sequence<'a> {
+        let mut seq = Sequence {
+            kind,
+            timing: *timing,
+            polls,
+            data,
+            response,
+            stage: Stage::Done,
+            step: 0,
+            index: 0,
+            read: 0,
+        };
+        seq.restart();
+        seq
+    }
+
+    /// Rewinds transfer to the beginning, so it could be repeated after failure.
+    pub fn restart(&mut self) {
+        self.step = 0;
+        self.index = 0;
+        self.read = 0;
+        self.stage = match self.kind {
+            Kind::Write2 { .. } | Kind::Read2 | Kind::Start => Stage::Start,
+            Kind::Write3 | Kind::Read3 => Stage::Strobe,
+            Kind::Recover { .. } => Stage::Pulses,
+            Kind::Idle => Stage::Idle,
+            Kind::Stop => Stage::Stop,
+            Kind::Bits => self.next_byte(),
+        };
+    }
+
+    /// Transfer sequence, `None` if bytes to send or response is empty.
+    fn transfer(
+        kind: Kind,
+        timing: &BusTiming,
+        polls: u8,
+        data: &'a [&'a [u8]],
+        response: &'a mut [u8],
+    ) -> Option<Sequence<'a>> {
+        let reading = matches!(kind, Kind::Read2 | Kind::Read3);
+        if data.iter().any(|bytes| bytes.is_empty()) || reading && response.is_empty() {
+            return None;
+        }
+        Some(Sequence::new(kind, timing, polls, data, response))
+    }
+
+    /// 2 wire write with ACK check of every byte, `data` holds bytes for every DIO line.
+    #[cfg(feature = "clkdio")]
+    pub fn write_2wire(
+        timing: &BusTiming,
+        polls: u8,
+        data: &'a [&'a [u8]],
+    ) -> Option<Sequence<'a>> {
+        Sequence::transfer(Kind::Write2 { ack: true }, timing, polls, data, &mut [])
+    }
+
+    /// 2 wire write for chips without ACK.
+    #[cfg(feature = "clkdio")]
+    pub fn write_2wire_noack(timing: &BusTiming, data: &'a [&'a [u8]]) -> Option<Sequence<'a>> {
+        Sequence::transfer(Kind::Write2 { ack: false }, timing, 0, data, &mut [])
+    }
+
+    /// 2 wire read command followed by `response.len()` bytes.
+    #[cfg(all(feature = "keys", feature = "clkdio"))]
+    pub fn read_2wire(
+        timing: &BusTiming,
+        polls: u8,
+        command: &'a [&'a [u8]],
+        response: &'a mut [u8],
+    ) -> Option<Sequence<'a>> {
+        Sequence::transfer(Kind::Read2, timing, polls, command, response)
+    }
+
+    /// Clocks out whatever chip is sending and puts 2 wire bus back into idle state.
+    #[cfg(feature = "clkdio")]
+    pub fn recover_2wire(timing: &BusTiming, pulses: u8) -> Sequence<'a> {
+        Sequence::new(Kind::Recover { pulses }, timing, 0, &[], &mut [])
+    }
+
+    /// Releases every line and checks that DIO is pulled up.
+    #[cfg(any(feature = "clkdio", all(feature = "keys", feature = "clkdiostb")))]
+    pub fn idle(timing: &BusTiming, polls: u8) -> Sequence<'a> {
+        Sequence::new(Kind::Idle, timing, polls, &[], &mut [])
+    }
+
+    /// 3 wire write.
+    #[cfg(feature = "clkdiostb")]
+    pub fn write_3wire(timing: &BusTiming, data: &'a [&'a [u8]]) -> Option<Sequence<'a>> {
+        Sequence::transfer(Kind::Write3, timing, 0, data, &mut [])
+    }
+
+    /// 3 wire read command followed by `response.len()` bytes.
+    #[cfg(all(feature = "keys", feature = "clkdiostb"))]
+    pub fn read_3wire(
+        timing: &BusTiming,
+        command: &'a [&'a [u8]],
+        response: &'a mut [u8],
+    ) -> Option<Sequence<'a>> {
+        Sequence::transfer(Kind::Read3, timing, 0, command, response)
+    }
+
+    /// 2 wire start sequence alone, for chips with own framing like TM1650.
+    #[cfg(feature = "clkdio")]
+    pub fn start_2wire(timing: &BusTiming) -> Sequence<'a> {
+        Sequence::new(Kind::Start, timing, 0, &[], &mut [])
+    }
+
+    /// 2 wire stop sequence alone, `bytes` is a number of transferred bytes for error report.
+    #[cfg(feature = "clkdio")]
+    pub fn stop_2wire(timing: &BusTiming, polls: u8, bytes: usize) -> Sequence<'a> {
+        let mut seq = Sequence::new(Kind::Stop, timing, polls, &[], &mut []);
+        seq.index = bytes;
+        seq
+    }
+
+    /// Sends `data` bytes LSB first and then reads `response` bytes MSB first, no framing.
+    #[cfg(feature = "clkdio")]
+    pub fn bits(timing: &BusTiming, data: &'a [&'a [u8]], response: &'a mut [u8]) -> Sequence<'a> {
+        Sequence::new(Kind::Bits, timing, 0, data, response)
+    }
+
+    /// Next op, `None` when transfer is complete.
+    pub fn next_op(&mut self) -> Option<Op> {
+        loop {
+            if self.stage == Stage::Done {
+                return None;
+            }
+            if let Some(op) = self.op() {
+                self.step += 1;
+                return Some(op);
+            }
+            self.stage = self.following();
+            self.step = 0;
+        }
+    }
+
+    /// Skips the rest of transfer but lines are still put back into idle state.
+    pub fn abort(&mut self) {
+        self.stage = match self.stage {
+            Stage::Stop | Stage::StopNoAck | Stage::Release | Stage::Idle | Stage::Done => {
+                Stage::Done
+            }
+            _ => self.closing(),
+        };
+        self.step = 0;
+    }
+
+    /// Level of given bit of current byte for DIO `line`.
+    pub fn bit(&self, line: usize, bit: u8) -> bool {
+        (self.data[line][self.index] >> bit) & 0b1 != 0
+    }
+
+    /// Stores DIO level read at [`Op::Sample`].
+    pub fn sample(&mut self, bit: u8, high: bool) {
+        let byte = &mut self.response[self.read];
+        if bit == 0 {
+            *byte = 0;
+        }
+        if high {
+            *byte |= 0x80 >> bit;
+        }
+    }
+
+    fn op(&self) -> Option<Op> {
+        let t = &self.timing;
+        let step = self.step;
+        // Index of byte as chip counts them, response bytes go after command
+        let byte = self.index + self.read;
+        match self.stage {
+            Stage::Start => [Op::Clk(true), Op::Dio(false), Op::Delay(t.start_stop_us)]
+                .get(step)
+                .copied(),
+            Stage::Strobe => [
+                Op::Delay(t.stb_hold_us),
+                Op::Stb(false),
+                Op::Delay(t.stb_hold_us),
+            ]
+            .get(step)
+            .copied(),
+            Stage::Send if step < 8 * 6 => {
+                let bit = (step / 6) as u8;
+                let ops = [
+                    Op::Clk(false),
+                    // This delay can be skipped, but data transfer become unstable
+                    Op::Delay(t.clk_low_us),
+                    Op::Bit(bit),
+                    Op::Delay(t.data_setup_us),
+                    Op::Clk(true),
+                    Op::Delay(t.clk_high_us),
+                ];
+                Some(ops[step % 6])
+            }
+            Stage::Ack => {
+                let ops = [
+                    // 8th cycle falling edge, chip pulls DIO down
+                    Op::Dio(true),
+                    Op::Clk(false),
+                    Op::Delay(t.clk_low_us),
+                    self.wait(false, byte, AckPhase::Falling8),
+                    // 9th cycle rising edge, DIO is still low
+                    Op::Clk(true),
+                    Op::Delay(t.clk_high_us),
+                    self.wait(false, byte, AckPhase::Rising9),
+                    // 9th cycle falling edge, released DIO is raised by pull-up,
+                    // that is the slowest part of the bus
+                    Op::Clk(false),
+                    Op::Delay(t.ack_wait_us),
+                    self.wait(true, byte, AckPhase::Release),
+                ];
+                // Chip starts sending right after read command is acknowledged
+                let len = if self.is_read_command() { 9 } else { 10 };
+                ops[..len].get(step).copied()
+            }
+            // Chip needs time to put the first bit on DIO after read command
+            Stage::Turn => [Op::Dio(true), Op::Delay(t.ack_wait_us)].get(step).copied(),
+            Stage::Receive if step < 8 * 5 => {
+                let bit = (step / 5) as u8;
+                let ops = [
+                    Op::Clk(false),
+                    // Chip changes DIO at low CLK, it needs time to settle
+                    Op::Delay(t.ack_wait_us),
+                    Op::Clk(true),
+                    Op::Sample(bit),
+                    Op::Delay(t.clk_high_us),
+                ];
+                Some(ops[step % 5])
+            }
+            Stage::Pulses => {
+                let pulses = match self.kind {
+                    Kind::Recover { pulses } => pulses as usize,
+                    _ => 0,
+                };
+                if step == 0 {
+                    return Some(Op::Dio(true));
+                }
+                let ops = [
+                    Op::Clk(false),
+                    Op::Delay(t.clk_low_us),
+                    Op::Clk(true),
+                    Op::Delay(t.clk_high_us),
+                ];
+                if step - 1 < pulses * ops.len() {
+                    Some(ops[(step - 1) % ops.len()])
+                } else {
+                    None
+                }
+            }
+            Stage::Stop => [
+                Op::Dio(false),
+                Op::Delay(t.data_setup_us),
+                Op::Clk(true),
+                Op::Delay(t.start_stop_us),
+                Op::Dio(true),
+                self.wait(true, byte, AckPhase::Stop),
+                Op::Delay(t.start_stop_us),
+            ]
+            .get(step)
+            .copied(),
+            Stage::StopNoAck => [
+                Op::Clk(false),
+                Op::Delay(t.clk_low_us),
+                Op::Dio(false),
+                Op::Delay(t.data_setup_us),
+                Op::Clk(true),
+                Op::Delay(t.start_stop_us),
+                Op::Dio(true),
+                Op::Delay(t.start_stop_us),
+            ]
+            .get(step)
+            .copied(),
+            Stage::Release => {
+                let ops = [
+                    Op::Delay(t.stb_hold_us),
+                    Op::Stb(true),
+                    Op::Clk(true),
+                    Op::Dio(true),
+                ];
+                // Reading has its own wait before the first bit instead of hold time
+                let skip = if self.kind == Kind::Read3 { 1 } else { 0 };
+                ops[skip..].get(step).copied()
+            }
+            Stage::Idle => [
+                Op::Stb(true),
+                Op::Clk(true),
+                Op::Dio(true),
+                self.wait(true, 0, AckPhase::Release),
+            ]
+            .get(step)
+            .copied(),
+            Stage::Send | Stage::Receive | Stage::Done => None,
+        }
+    }
+
+    fn wait(&self, high: bool, byte: usize, phase: AckPhase) -> Op {
+        Op::Wait(Wait {
+            high,
+            polls: self.polls,
+            delay_us: self.timing.ack_wait_us,
+            byte,
+            phase,
+        })
+    }
+
+    fn is_read_command(&self) -> bool {
+        self.index == 0
+            && self.read == 0
+            && self.data.first().and_then(|bytes| bytes.first()).copied() == Some(COM_DATA_READ)
+    }
+
+    fn len(&self) -> usize {
+        self.data.first().map_or(0, |bytes| bytes.len())
+    }
+
+    /// Stage that goes after completed current one.
+    fn following(&mut self) -> Stage {
+        match self.stage {
+            Stage::Start | Stage::Strobe => self.next_byte(),
+            Stage::Send => match self.kind {
+                Kind::Write2 { ack: true } | Kind::Read2 => Stage::Ack,
+                Kind::Read3 => {
+                    self.index += 1;
+                    Stage::Turn
+                }
+                _ => {
+                    self.index += 1;
+                    self.next_byte()
+                }
+            },
+            Stage::Ack => {
+                if self.index < self.len() {
+                    self.index += 1;
+                } else {
+                    self.read += 1;
+                }
+                self.next_byte()
+            }
+            Stage::Turn => self.next_byte(),
+            Stage::Receive => {
+                if self.kind == Kind::Read2 {
+                    Stage::Ack
+                } else {
+                    self.read += 1;
+                    self.next_byte()
+                }
+            }
+            Stage::Pulses => Stage::StopNoAck,
+            Stage::Stop | Stage::StopNoAck | Stage::Release | Stage::Idle | Stage::Done => {
+                Stage::Done
+            }
+        }
+    }
+
+    fn next_byte(&self) -> Stage {
+        if self.index < self.len() {
+            Stage::Send
+        } else if self.read < self.response.len() {
+            Stage::Receive
+        } else {
+            self.closing()
+        }
+    }
+
+    /// Stage that puts bus back into idle state.
+    fn closing(&self) -> Stage {
+        match self.kind {
+            Kind::Write2 { ack: true } | Kind::Read2 => Stage::Stop,
+            Kind::Write2 { ack: false } | Kind::Recover { .. } => Stage::StopNoAck,
+            Kind::Write3 | Kind::Read3 => Stage::Release,
+            Kind::Idle | Kind::Start | Kind::Stop | Kind::Bits => Stage::Done,
+        }
+    }
+}
+
+/// Runs sequence on blocking pins and delay, the first error is returned after bus is released.
+pub(crate) fn run<L, D>(
+    lines: &mut L,
+    delay_us: &mut D,
+    seq: &mut Sequence,
+) -> Result<(), LinesError<L>>
+where
+    L: Lines,
+    D: BusDelay,
+{
+    let mut result = Ok(());
+    while let Some(op) = seq.next_op() {
+        let step = match op {
+            Op::Delay(us) => {
+                delay_us.delay_us(us);
+                Ok(())
+            }
+            Op::Wait(wait) => self::wait(lines, delay_us, &wait),
+            op => apply(lines, seq, op),
+        };
+        if let Err(e) = step {
+            if result.is_ok() {
+                result = Err(e);
+            }
+            seq.abort();
+        }
+    }
+    result
+}
+
+/// Polls DIO like [`Op::Wait`] says, blocking delay after every failed check.
+pub(crate) fn wait<L, D>(lines: &mut L, delay_us: &mut D, wait: &Wait) -> Result<(), LinesError<L>>
+where
+    L: Lines,
+    D: BusDelay,
+{
+    let mut failed = wait.error(0);
+    for _ in 0..wait.polls {
+        match check(lines, wait) {
+            Err(e @ TmError::Ack { .. }) => {
+                failed = e;
+                delay_us.delay_us(wait.delay_us);
+            }
+            ready => return ready,
+        }
+    }
+    Err(failed)
+}
+
+/// Applies op that changes or reads lines, delays are up to executor.
+pub(crate) fn apply<L: Lines>(
+    lines: &mut L,
+    seq: &mut Sequence,
+    op: Op,
+) -> Result<(), LinesError<L>> {
+    match op {
+        Op::Clk(high) => lines.set_clk(high),
+        Op::Stb(high) => lines.set_stb(high),
+        Op::Dio(high) => (0..lines.count()).try_for_each(|line| lines.set_dio(line, high)),
+        Op::Bit(bit) => {
+            (0..lines.count()).try_for_each(|line| lines.set_dio(line, seq.bit(line, bit)))
+        }
+        Op::Sample(bit) => {
+            let high = lines.is_dio_high(0)?;
+            seq.sample(bit, high);
+            Ok(())
+        }
+        Op::Delay(_) | Op::Wait(_) => Ok(()),
+    }
+}
+
+/// One check of [`Op::Wait`], `TmError::Ack` with the first line that does not have expected level yet.
+pub(crate) fn check<L: Lines>(lines: &mut L, wait: &Wait) -> Result<(), LinesError<L>> {
+    for line in 0..lines.count() {
+        if lines.is_dio_high(line)? != wait.high {
+            return Err(wait.error(line));
+        }
+    }
+    Ok(())
+}
+
+/// Repeats `transfer` on ACK errors according to policy, pin errors are returned right away.
+#[cfg(feature = "clkdio")]
+pub(crate) fn retry<L, D, R, F>(
+    lines: &mut L,
+    delay_us: &mut D,
+    timing: &BusTiming,
+    policy: &RetryPolicy,
+    failed_attempts: &mut u32,
+    mut transfer: F,
+) -> Result<R, LinesError<L>>
+where
+    L: Lines,
+    D: BusDelay,
+    F: FnMut(&mut L, &mut D) -> Result<R, LinesError<L>>,
+{
+    let mut attempt = 1;
+    loop {
+        let result = transfer(lines, delay_us);
+        if let Err(TmError::Ack { .. }) = result {
+            *failed_attempts = failed_attempts.saturating_add(1);
+            if attempt < policy.max_attempts {
+                if policy.recovery_pulses > 0 {
+                    let mut recover = Sequence::recover_2wire(timing, policy.recovery_pulses);
+                    run(lines, delay_us, &mut recover)?;
+                }
+                delay_us.delay_us(policy.backoff_us.saturating_mul(attempt as u16));
+                attempt += 1;
+                continue;
+            }
+        }
+        return result;
+    }
+}
+
+#[cfg(all(test, feature = "clkdio"))]
+mod tests {
+    use super::*;
+
+    /// Checked (byte, phase) pairs in order.
+    fn waits(seq: &mut Sequence, out: &mut [(usize, AckPhase)]) -> usize {
+        let mut len = 0;
+        while let Some(op) = seq.next_op() {
+            if let Op::Wait(wait) = op {
+                out[len] = (wait.byte, wait.phase);
+                len += 1;
+            }
+        }
+        len
+    }
+
+    #[test]
+    fn write_2wire_waits_test() {
+        let data: [&[u8]; 1] = [&[COM_ADDRESS, COM_DATA_READ]];
+        let mut seq = Sequence::write_2wire(&BusTiming::uniform(1), 2, &data).unwrap();
+        let mut out = [(0, AckPhase::Stop); 8];
+        let len = waits(&mut seq, &mut out);
+        // Data byte that looks like read command is still verified
+        assert_eq!(
+            out[..len],
+            [
+                (0, AckPhase::Falling8),
+                (0, AckPhase::Rising9),
+                (0, AckPhase::Release),
+                (1, AckPhase::Falling8),
+                (1, AckPhase::Rising9),
+                (1, AckPhase::Release),
+                (2, AckPhase::Stop),
+            ]
+        );
+    }
+
+    #[cfg(feature = "keys")]
+    #[test]
+    fn read_2wire_waits_test() {
+        let command: [&[u8]; 1] = [&[COM_DATA_READ]];
+        let mut response = [0; 2];
+        let mut seq =
+            Sequence::read_2wire(&BusTiming::uniform(1), 2, &command, &mut response).unwrap();
+        let mut out = [(0, AckPhase::Stop); 10];
+        let len = waits(&mut seq, &mut out);
+        // Chip drives DIO right after read command ACK, so its release is not checked
+        assert_eq!(
+            out[..len],
+            [
+                (0, AckPhase::Falling8),
+                (0, AckPhase::Rising9),
+                (1, AckPhase::Falling8),
+                (1, AckPhase::Rising9),
+                (1, AckPhase::Release),
+                (2, AckPhase::Falling8),
+                (2, AckPhase::Rising9),
+                (2, AckPhase::Release),
+                (3, AckPhase::Stop),
+            ]
+        );
+    }
+
+    #[test]
+    fn abort_test() {
+        let data: [&[u8]; 1] = [&[COM_ADDRESS, 1, 2]];
+        let mut seq = Sequence::write_2wire(&BusTiming::uniform(1), 2, &data).unwrap();
+        while seq.next_op() != Some(Op::Clk(true)) {}
+        seq.abort();
+        // Stop sequence still goes out
+        assert_eq!(seq.next_op(), Some(Op::Dio(false)));
+        seq.abort();
+        assert_eq!(seq.next_op(), None);
+
+        let empty: [&[u8]; 1] = [&[]];
+        let seq = Sequence::write_2wire(&BusTiming::uniform(1), 2, &empty);
+        assert!(seq.is_none());
+    }
+}
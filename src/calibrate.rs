@@ -0,0 +1,332 @@
+//! Bus timing calibration for your exact board.
+//!
+//! Slow part of the bus is DIO rise time that depends of pull-up resistor and wiring,
+//! clock phases are fast on every chip. Calibration keeps clock phases from
+//! [`Calibration::base`] and does binary search over data setup, ACK wait and start/stop delays.
+//! Every candidate is checked several times with harmless command, by ACK for 2 wire chips
+//! and by key scan consistency if **keys** feature is enabled.
+//!
+//! Result could be stored once per board and passed to drivers later:
+//!
+//! ```ignore
+//! let timing = calibrate_2wire(&mut dio, &mut clk, &mut delay_us, &Calibration::default())?;
+//! let mut tm = Tm1637::new(dio, clk, delay_us, timing);
+//! ```
+use super::*;
+
+/// Calibration settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Calibration {
+    /// Command that is sent to chip on every check, it must not change chip state
+    pub command: u8,
+    /// Lower bound of searched delay in us
+    pub min_us: u16,
+    /// Upper bound of searched delay in us
+    pub max_us: u16,
+    /// Number of checks for every delay value
+    pub rounds: u8,
+    /// Safety margin added to the smallest reliable delay, in percent
+    pub margin_percent: u8,
+    /// Timing with clock phases that are kept as is
+    pub base: BusTiming,
+}
+
+impl Default for Calibration {
+    /// Data command with address auto increment (chip default) and TM1637 clock phases.
+    fn default() -> Self {
+        Calibration {
+            command: COM_DATA_ADDRESS_ADD,
+            min_us: 1,
+            max_us: 1000,
+            rounds: 10,
+            margin_percent: 25,
+            base: TM1637_TIMING,
+        }
+    }
+}
+
+impl Calibration {
+    /// Timing with given delay for DIO related phases.
+    pub fn timing(&self, delay_us: u16) -> BusTiming {
+        BusTiming {
+            data_setup_us: delay_us,
+            ack_wait_us: delay_us,
+            start_stop_us: delay_us,
+            ..self.base
+        }
+    }
+
+    /// Adds safety margin to delay value, at least 1 us.
+    // `div_ceil` is not available on compilers this crate still supports
+    #[allow(clippy::manual_div_ceil)]
+    pub fn with_margin(&self, delay_us: u16) -> u16 {
+        let margin = (delay_us as u32 * self.margin_percent as u32 + 99) / 100;
+        delay_us.saturating_add(margin.max(1).min(u16::MAX as u32) as u16)
+    }
+
+    /// Binary search over delay values, `check` returns false if delay is not reliable.
+    fn search<E, C, S, F>(&self, mut check: F) -> Result<BusTiming, TmError<E, C, S>>
+    where
+        F: FnMut(BusTiming) -> Result<bool, TmError<E, C, S>>,
+    {
+        if self.rounds == 0 || self.min_us > self.max_us {
+            return Err(TmError::Input);
+        }
+        // Even the slowest bus does not work, nothing to search
+        if !check(self.timing(self.max_us))? {
+            return Err(TmError::Input);
+        }
+
+        let (mut low, mut high) = (self.min_us, self.max_us);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if check(self.timing(mid))? {
+                high = mid;
+            } else {
+                low = mid + 1;
+            }
+        }
+        Ok(self.timing(self.with_margin(high)))
+    }
+}
+
+/// Turns missing ACK into unreliable result, pin errors are passed as is.
+#[cfg(feature = "clkdio")]
+fn reliable<R, E, C>(result: Result<R, TmError<E, C>>) -> Result<Option<R>, TmError<E, C>> {
+    match result {
+        Ok(r) => Ok(Some(r)),
+        Err(TmError::Ack { .. }) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Finds the smallest reliable timing for 2 wire chip (TM1637, TM1651).
+///
+/// Returns [`TmError::Input`] if settings are wrong or even [`Calibration::max_us`] is not reliable.
+///
+/// Arguments:
+///  - `dio`, `clk` - MCU interface pins
+///  - `delay_us` - closure that provides delay functionality
+///  - `calibration` - search range, number of checks and safety margin
+#[cfg(feature = "clkdio")]
+pub fn calibrate_2wire<DIO, CLK, D, E, EC>(
+    dio: &mut DIO,
+    clk: &mut CLK,
+    delay_us: &mut D,
+    calibration: &Calibration,
+) -> Result<BusTiming, TmError<E, EC>>
+where
+    DIO: Dio<Error = E>,
+    CLK: OutputPin<Error = EC>,
+    D: BusDelay,
+{
+    calibration.search(|timing| {
+        #[cfg(feature = "keys")]
+        let mut keys = None;
+        for _ in 0..calibration.rounds {
+            let send = tm_send_bytes_2wire(dio, clk, delay_us, timing, &[calibration.command]);
+            if reliable(send)?.is_none() {
+                return Ok(false);
+            }
+            #[cfg(feature = "keys")]
+            {
+                let read = match reliable(tm_read_byte_2wire(dio, clk, delay_us, timing))? {
+                    Some(read) => read,
+                    None => return Ok(false),
+                };
+                if *keys.get_or_insert(read) != read {
+                    return Ok(false);
+                }
+            }
+        }
+        Ok(true)
+    })
+}
+
+/// Finds the smallest timing with consistent key scan data for 3 wire chip (TM1638, TM1628 etc.).
+///
+/// 3 wire chips have no ACK, thus keep keys untouched while calibration is running.
+/// Returns [`TmError::Input`] if settings are wrong, [`tm_probe_3wire`] finds no chip
+/// or even [`Calibration::max_us`] is not reliable.
+///
+/// Arguments:
+///  - `dio`, `clk`, `stb` - MCU interface pins
+///  - `delay_us` - closure that provides delay functionality
+///  - `calibration` - search range, number of checks and safety margin
+#[cfg(all(feature = "keys", feature = "clkdiostb"))]
+pub fn calibrate_3wire<DIO, CLK, STB, D, E, EC, ES>(
+    dio: &mut DIO,
+    clk: &mut CLK,
+    stb: &mut STB,
+    delay_us: &mut D,
+    calibration: &Calibration,
+) -> Result<BusTiming, TmError<E, EC, ES>>
+where
+    DIO: Dio<Error = E>,
+    CLK: OutputPin<Error = EC>,
+    STB: OutputPin<Error = ES>,
+    D: BusDelay,
+{
+    // Pulled up DIO without chip gives perfectly consistent 0xFF key scan data
+    let slowest = calibration.timing(calibration.max_us);
+    if tm_probe_3wire(dio, clk, stb, delay_us, slowest)? == Probe::Absent {
+        return Err(TmError::Input);
+    }
+    calibration.search(|timing| {
+        let mut keys = None;
+        for _ in 0..calibration.rounds {
+            tm_send_bytes_3wire(dio, clk, stb, delay_us, timing, &[calibration.command])?;
+            let read = tm_read_bytes_3wire(dio, clk, stb, delay_us, timing, TM1638_RESPONSE_SIZE)?;
+            if *keys.get_or_insert(read) != read {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "clkdio")]
+    use crate::sim::{SimChip, SimPin};
+    #[cfg(feature = "clkdio")]
+    use core::cell::{Cell, RefCell};
+
+    /// Virtual time shared by delay closure and pins.
+    #[cfg(feature = "clkdio")]
+    struct Clock {
+        now_us: Cell<u32>,
+        released_us: Cell<u32>,
+    }
+
+    /// DIO that rises slowly after release as if pull-up resistor was weak.
+    #[cfg(feature = "clkdio")]
+    struct SlowDio<'a> {
+        pin: SimPin<'a>,
+        clock: &'a Clock,
+        rise_us: u32,
+    }
+
+    #[cfg(feature = "clkdio")]
+    impl OutputPin for SlowDio<'_> {
+        type Error = core::convert::Infallible;
+
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            self.pin.set_low()
+        }
+
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            self.clock.released_us.set(self.clock.now_us.get());
+            self.pin.set_high()
+        }
+    }
+
+    #[cfg(feature = "clkdio")]
+    impl InputPin for SlowDio<'_> {
+        type Error = core::convert::Infallible;
+
+        fn is_high(&self) -> Result<bool, Self::Error> {
+            let rising = self.clock.now_us.get() - self.clock.released_us.get() < self.rise_us;
+            Ok(self.pin.is_high()? && !rising)
+        }
+
+        fn is_low(&self) -> Result<bool, Self::Error> {
+            self.is_high().map(|high| !high)
+        }
+    }
+
+    /// CLK falling edge is where chip releases DIO.
+    #[cfg(feature = "clkdio")]
+    struct Clk<'a> {
+        pin: SimPin<'a>,
+        clock: &'a Clock,
+    }
+
+    #[cfg(feature = "clkdio")]
+    impl OutputPin for Clk<'_> {
+        type Error = core::convert::Infallible;
+
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            self.clock.released_us.set(self.clock.now_us.get());
+            self.pin.set_low()
+        }
+
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            self.pin.set_high()
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "clkdio")]
+    fn calibrate_2wire_test() {
+        let chip = RefCell::new(SimChip::tm1637());
+        let clock = Clock {
+            now_us: Cell::new(0),
+            released_us: Cell::new(0),
+        };
+        let mut dio = SlowDio {
+            pin: SimPin::dio(&chip),
+            clock: &clock,
+            rise_us: 400,
+        };
+        let mut clk = Clk {
+            pin: SimPin::clk(&chip),
+            clock: &clock,
+        };
+        let mut delay = |us: u16| clock.now_us.set(clock.now_us.get() + us as u32);
+        let calibration = Calibration {
+            rounds: 3,
+            ..Calibration::default()
+        };
+
+        let timing = calibrate_2wire(&mut dio, &mut clk, &mut delay, &calibration).unwrap();
+        // Stop sequence polls released DIO right away and 4 more times after each delay
+        assert_eq!(timing, calibration.timing(125));
+        assert_eq!(timing.clk_low_us, TM1637_TIMING.clk_low_us);
+
+        let slow = Calibration {
+            max_us: 50,
+            ..calibration
+        };
+        assert!(matches!(
+            calibrate_2wire(&mut dio, &mut clk, &mut delay, &slow),
+            Err(TmError::Input)
+        ));
+    }
+
+    #[test]
+    #[cfg(all(feature = "keys", feature = "clkdiostb"))]
+    fn calibrate_3wire_test() {
+        use crate::sim::{SimChip, SimPin};
+        use crate::tests::Line;
+        use core::cell::RefCell;
+
+        let chip = RefCell::new(SimChip::tm1638());
+        chip.borrow_mut().set_key_scan(&[1, 2, 3, 4]);
+        let (mut dio, mut clk, mut stb) =
+            (SimPin::dio(&chip), SimPin::clk(&chip), SimPin::stb(&chip));
+        let calibration = Calibration {
+            rounds: 2,
+            base: TM1638_TIMING,
+            ..Calibration::default()
+        };
+        let timing = calibrate_3wire(&mut dio, &mut clk, &mut stb, &mut |_| {}, &calibration);
+        assert_eq!(timing.unwrap(), calibration.timing(2));
+
+        // Nothing is connected, pulled up DIO reads the same 0xFF every time
+        let (mut dio, mut clk, mut stb) = (Line::default(), Line::default(), Line::default());
+        let timing = calibrate_3wire(&mut dio, &mut clk, &mut stb, &mut |_| {}, &calibration);
+        assert!(matches!(timing, Err(TmError::Input)));
+        assert!(dio.reads.get() > 0);
+    }
+
+    #[test]
+    fn margin_test() {
+        let calibration = Calibration::default();
+        assert_eq!(calibration.with_margin(0), 1);
+        assert_eq!(calibration.with_margin(3), 4);
+        assert_eq!(calibration.with_margin(100), 125);
+        assert_eq!(calibration.with_margin(u16::MAX), u16::MAX);
+    }
+}
@@ -0,0 +1,991 @@
+//! Decoder of captured bus traffic into TM16xx transactions (**sim** feature).
+//!
+//! Capture could be a VCD file (from [`super::recorder`], GTKWave, sigrok etc.)
+//! or a simple CSV with timestamped pin levels:
+//!
+//! ```text
+//! time,clk,dio,stb
+//! 0,1,1,1
+//! 10,1,1,0
+//! ```
+//!
+//! Signals are found by name: `clk` (or `scl`), `dio` (or `din`, `sda`) and optional `stb`.
+//! Capture is decoded as 2 wire interface with start/stop conditions and ACK
+//! until STB goes low for the first time, after that transactions are framed by STB.
+//! VCD signal `dio_in` written by recorder is treated as DIO line too.
+//!
+//! Every [`Transaction`] implements `Display`, so you could just print decoded capture:
+//!
+//! ```text
+//! #4 COM_DATA_ADDRESS_ADD ACK
+//! #820 COM_ADDRESS|0 ACK: 0x06 '1' ACK, 0x5b '2' ACK
+//! #1650 COM_DATA_READ ACK: key 0xef ACK
+//! ```
+//!
+//! With [`decode_vcd_checked`] and [`decode_csv_checked`] phase durations are also checked
+//! against [`BusTiming`], every phase that is shorter is reported with its transaction:
+//!
+//! ```text
+//! #4 COM_DATA_ADDRESS_ADD ACK (clk_low 1000ns < 2us at #10)
+//! ```
+use core::fmt;
+
+use super::*;
+
+/// Maximum number of bytes kept for one transaction, the rest is dropped.
+pub const DECODER_MAX_FRAMES: usize = 64;
+
+/// Maximum number of timing violations kept for one transaction, the rest is dropped.
+pub const DECODER_MAX_VIOLATIONS: usize = 8;
+
+/// Command byte classified by its instruction set, see `COM_*` constants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// Display mode setting, see [`COM_DISPLAY_MODE`]
+    DisplayMode(u8),
+    /// Data setting, see [`COM_DATA`]
+    Data { read: bool, fixed: bool, raw: u8 },
+    /// Display control, see [`COM_DISPLAY`]
+    Display { on: bool, brightness: u8 },
+    /// Address setting, see [`COM_ADDRESS`]
+    Address(u8),
+}
+
+impl Command {
+    /// Classifies command byte by its two highest bits.
+    pub fn classify(byte: u8) -> Command {
+        match byte & 0b1100_0000 {
+            COM_DATA => Command::Data {
+                read: byte & 0b11 == COM_DATA_READ & 0b11,
+                fixed: byte & 0b100 != 0,
+                raw: byte,
+            },
+            COM_DISPLAY => Command::Display {
+                on: byte & 0b1000 != 0,
+                brightness: byte & DISPLAY_BRIGHTNESS_MASK,
+            },
+            COM_ADDRESS => Command::Address(byte & 0b0011_1111),
+            _ => Command::DisplayMode(byte & 0b0011_1111),
+        }
+    }
+
+    /// True if chip sends data back after this command.
+    pub fn is_read(&self) -> bool {
+        matches!(self, Command::Data { read: true, .. })
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Command::DisplayMode(mode) => write!(f, "COM_DISPLAY_MODE|{}", mode),
+            Command::Data { raw, .. } if raw == COM_DATA_ADDRESS_ADD => {
+                write!(f, "COM_DATA_ADDRESS_ADD")
+            }
+            Command::Data { raw, .. } if raw == COM_DATA_ADDRESS_FIXED => {
+                write!(f, "COM_DATA_ADDRESS_FIXED")
+            }
+            Command::Data { raw, .. } if raw == COM_DATA_READ => write!(f, "COM_DATA_READ"),
+            Command::Data { raw, .. } => write!(f, "COM_DATA|{:#04x}", raw & !COM_DATA),
+            Command::Display {
+                on: true,
+                brightness,
+            } => write!(f, "COM_DISPLAY_ON|{}", brightness),
+            Command::Display { on: false, .. } => write!(f, "COM_DISPLAY_OFF"),
+            Command::Address(address) => write!(f, "COM_ADDRESS|{}", address),
+        }
+    }
+}
+
+/// Characters known to this crate, digits go first so they win over same looking letters.
+const SEGMENT_CHARS: [(u8, char); 39] = [
+    (CHAR_0, '0'),
+    (CHAR_1, '1'),
+    (CHAR_2, '2'),
+    (CHAR_3, '3'),
+    (CHAR_4, '4'),
+    (CHAR_5, '5'),
+    (CHAR_6, '6'),
+    (CHAR_7, '7'),
+    (CHAR_8, '8'),
+    (CHAR_9, '9'),
+    (0, ' '),
+    (CHAR_A, 'A'),
+    (CHAR_a, 'a'),
+    (CHAR_b, 'b'),
+    (CHAR_C, 'C'),
+    (CHAR_c, 'c'),
+    (CHAR_d, 'd'),
+    (CHAR_E, 'E'),
+    (CHAR_e, 'e'),
+    (CHAR_F, 'F'),
+    (CHAR_G, 'G'),
+    (CHAR_H, 'H'),
+    (CHAR_h, 'h'),
+    (CHAR_i, 'i'),
+    (CHAR_J, 'J'),
+    (CHAR_L, 'L'),
+    (CHAR_l, 'l'),
+    (CHAR_N, 'N'),
+    (CHAR_n, 'n'),
+    (CHAR_o, 'o'),
+    (CHAR_P, 'P'),
+    (CHAR_q, 'q'),
+    (CHAR_R, 'R'),
+    (CHAR_r, 'r'),
+    (CHAR_t, 't'),
+    (CHAR_U, 'U'),
+    (CHAR_u, 'u'),
+    (CHAR_y, 'y'),
+    (CHAR_MINUS, '-'),
+];
+
+/// Renders 7 segment byte as character, `?` if it looks like nothing known.
+/// Segment DP ([`SEG_8`]) is ignored.
+pub fn segment_char(byte: u8) -> char {
+    let byte = byte & !SEG_8;
+    SEGMENT_CHARS
+        .iter()
+        .find(|(seg, _)| *seg == byte)
+        .map(|(_, ch)| *ch)
+        .unwrap_or('?')
+}
+
+/// Transfer direction of a byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// MCU to chip
+    Write,
+    /// Chip to MCU (key scan data)
+    Read,
+}
+
+/// One decoded byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    /// Byte value in datasheet bit order
+    pub value: u8,
+    pub direction: Direction,
+    /// ACK result for 2 wire interface: `Some(true)` if DIO was low at 9th clock
+    pub ack: Option<bool>,
+}
+
+/// Bus phase checked against [`BusTiming`], named after its field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// CLK low before rising edge that clocks written bit or ACK
+    ClkLow,
+    /// CLK high before falling edge
+    ClkHigh,
+    /// DIO stable before rising edge that clocks written bit
+    DataSetup,
+    /// CLK low before rising edge that clocks bit sent by chip
+    AckWait,
+    /// DIO low before the first CLK falling edge, CLK high before stop
+    StartStop,
+    /// STB low before the first CLK falling edge, last CLK rising edge before STB goes high
+    StbHold,
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Phase::ClkLow => "clk_low",
+            Phase::ClkHigh => "clk_high",
+            Phase::DataSetup => "data_setup",
+            Phase::AckWait => "ack_wait",
+            Phase::StartStop => "start_stop",
+            Phase::StbHold => "stb_hold",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Bus phase that was shorter than [`BusTiming`] requires.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Violation {
+    pub phase: Phase,
+    /// Capture time of the edge that ended the phase
+    pub time: u64,
+    /// Measured phase duration
+    pub duration_ns: u64,
+    /// Required phase duration
+    pub required_us: u16,
+}
+
+/// Bytes sent between start and stop conditions (or while STB was low).
+#[derive(Debug, Clone)]
+pub struct Transaction {
+    /// Capture time of start condition
+    pub start: u64,
+    /// Capture time of stop condition
+    pub end: u64,
+    /// Transaction ended in the middle of a byte or had more than [`DECODER_MAX_FRAMES`] bytes
+    pub truncated: bool,
+    len: usize,
+    frames: [Frame; DECODER_MAX_FRAMES],
+    violations_len: usize,
+    violations: [Violation; DECODER_MAX_VIOLATIONS],
+}
+
+impl Transaction {
+    fn new(start: u64) -> Transaction {
+        Transaction {
+            start,
+            end: start,
+            truncated: false,
+            len: 0,
+            frames: [Frame {
+                value: 0,
+                direction: Direction::Write,
+                ack: None,
+            }; DECODER_MAX_FRAMES],
+            violations_len: 0,
+            violations: [Violation {
+                phase: Phase::ClkLow,
+                time: start,
+                duration_ns: 0,
+                required_us: 0,
+            }; DECODER_MAX_VIOLATIONS],
+        }
+    }
+
+    fn push(&mut self, frame: Frame) {
+        if self.len < DECODER_MAX_FRAMES {
+            self.frames[self.len] = frame;
+            self.len += 1;
+        } else {
+            self.truncated = true;
+        }
+    }
+
+    /// All decoded bytes, the first one is a command.
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames[..self.len]
+    }
+
+    /// Classified command byte.
+    pub fn command(&self) -> Option<Command> {
+        self.frames().first().map(|f| Command::classify(f.value))
+    }
+
+    /// Bytes that follow command byte.
+    pub fn data(&self) -> &[Frame] {
+        if self.len > 0 {
+            &self.frames[1..self.len]
+        } else {
+            &[]
+        }
+    }
+
+    /// Phases that were shorter than bus timing requires, empty if timing was not checked.
+    pub fn violations(&self) -> &[Violation] {
+        &self.violations[..self.violations_len]
+    }
+}
+
+fn write_ack(f: &mut fmt::Formatter<'_>, ack: Option<bool>) -> fmt::Result {
+    match ack {
+        Some(true) => write!(f, " ACK"),
+        Some(false) => write!(f, " NAK"),
+        None => Ok(()),
+    }
+}
+
+impl fmt::Display for Transaction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.start)?;
+        let command = match self.frames().first() {
+            Some(frame) => {
+                write!(f, " {}", Command::classify(frame.value))?;
+                write_ack(f, frame.ack)?;
+                Command::classify(frame.value)
+            }
+            None => return write!(f, " (empty)"),
+        };
+        for (i, frame) in self.data().iter().enumerate() {
+            write!(f, "{}", if i == 0 { ":" } else { "," })?;
+            match frame.direction {
+                Direction::Read => write!(f, " key {:#04x}", frame.value)?,
+                Direction::Write => {
+                    write!(f, " {:#04x}", frame.value)?;
+                    if let Command::Address(_) = command {
+                        write!(f, " '{}", segment_char(frame.value))?;
+                        if frame.value & SEG_8 != 0 {
+                            write!(f, ".")?;
+                        }
+                        write!(f, "'")?;
+                    }
+                }
+            }
+            write_ack(f, frame.ack)?;
+        }
+        if self.truncated {
+            write!(f, " (truncated)")?;
+        }
+        for v in self.violations() {
+            write!(
+                f,
+                " ({} {}ns < {}us at #{})",
+                v.phase, v.duration_ns, v.required_us, v.time
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Bus state machine fed with line levels, see [`decode_vcd`] and [`decode_csv`].
+pub struct Decoder {
+    three_wire: bool,
+    clk: bool,
+    dio: bool,
+    stb: bool,
+    bits: u8,
+    byte: u8,
+    reading: bool,
+    clocked: bool,
+    transaction: Option<Transaction>,
+    timing: Option<BusTiming>,
+    unit_ps: u64,
+    clk_edge: u64,
+    dio_edge: u64,
+    stb_edge: u64,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Decoder::new()
+    }
+}
+
+impl Decoder {
+    /// Creates decoder, all lines are expected to be high (idle) before the first sample.
+    pub fn new() -> Decoder {
+        Decoder {
+            three_wire: false,
+            clk: true,
+            dio: true,
+            stb: true,
+            bits: 0,
+            byte: 0,
+            reading: false,
+            clocked: false,
+            transaction: None,
+            timing: None,
+            unit_ps: 1_000_000,
+            clk_edge: 0,
+            dio_edge: 0,
+            stb_edge: 0,
+        }
+    }
+
+    /// Creates decoder that checks phase durations against `timing`.
+    ///
+    /// Arguments:
+    ///  - `timing` - bus timing or single delay value in us, see [`TM1637_TIMING`]
+    ///  - `unit_ps` - duration of capture time unit in picoseconds, `1_000_000` for us
+    pub fn with_timing<T: Into<BusTiming>>(timing: T, unit_ps: u64) -> Decoder {
+        Decoder {
+            timing: Some(timing.into()),
+            unit_ps: unit_ps.max(1),
+            ..Decoder::new()
+        }
+    }
+
+    /// Feeds line levels at given capture time.
+    /// Decoded transaction is passed to `out` when it ends.
+    pub fn sample<F>(&mut self, time: u64, clk: bool, dio: bool, stb: bool, out: &mut F)
+    where
+        F: FnMut(&Transaction),
+    {
+        let rising = !self.clk && clk;
+        let falling = self.clk && !clk;
+        let dio_changed = self.dio != dio;
+        let stb_changed = self.stb != stb;
+        let clk_was_high = self.clk && clk;
+        if stb_changed && !stb {
+            self.three_wire = true;
+        }
+        if self.timing.is_some() && self.transaction.is_some() {
+            self.check(time, rising, falling, dio_changed, stb_changed && stb);
+        }
+        if self.clk != clk {
+            self.clk_edge = time;
+        }
+        if dio_changed {
+            self.dio_edge = time;
+        }
+        if stb_changed {
+            self.stb_edge = time;
+        }
+        self.clk = clk;
+        self.dio = dio;
+        self.stb = stb;
+
+        if self.three_wire {
+            if stb_changed && !stb {
+                self.begin(time, out);
+            }
+            if !stb && rising {
+                self.clock_bit(dio);
+            }
+            if stb_changed && stb {
+                self.end(time, out);
+            }
+            return;
+        }
+
+        // Start and stop are DIO edges while CLK stays high, ACK slot is not a stop
+        if clk_was_high && dio_changed && self.bits < 8 {
+            if dio {
+                self.end(time, out);
+            } else {
+                self.begin(time, out);
+            }
+        } else if rising && self.transaction.is_some() {
+            if self.bits < 8 {
+                self.clock_bit(dio);
+            } else {
+                self.push_byte(Some(!dio));
+            }
+        }
+    }
+
+    /// Passes unfinished transaction to `out`, call it at the end of capture.
+    pub fn finish<F>(&mut self, time: u64, out: &mut F)
+    where
+        F: FnMut(&Transaction),
+    {
+        if let Some(mut tr) = self.transaction.take() {
+            tr.end = time;
+            tr.truncated = true;
+            out(&tr);
+        }
+    }
+
+    fn begin<F: FnMut(&Transaction)>(&mut self, time: u64, out: &mut F) {
+        self.finish(time, out);
+        self.transaction = Some(Transaction::new(time));
+        self.bits = 0;
+        self.byte = 0;
+        self.reading = false;
+        self.clocked = false;
+    }
+
+    /// Checks duration of phase that ends at this sample, levels are not updated yet.
+    fn check(&mut self, time: u64, rising: bool, falling: bool, dio_changed: bool, stb_up: bool) {
+        let timing = match self.timing {
+            Some(timing) => timing,
+            None => return,
+        };
+        let clk_edge = self.clk_edge;
+        let in_frame = !self.three_wire || !self.stb;
+        if rising && in_frame {
+            let ack = !self.three_wire && self.bits == 8;
+            // Chip drives DIO for key scan data, it only needs time to settle
+            let from_chip = self.reading && !ack;
+            if from_chip {
+                self.violation(Phase::AckWait, time, clk_edge, timing.ack_wait_us);
+            } else {
+                self.violation(Phase::ClkLow, time, clk_edge, timing.clk_low_us);
+                if !ack {
+                    let dio_edge = self.dio_edge;
+                    self.violation(Phase::DataSetup, time, dio_edge, timing.data_setup_us);
+                }
+            }
+        }
+        if falling && in_frame {
+            // Nobody knows how long CLK was high before transaction, start is checked instead
+            if self.clocked {
+                self.violation(Phase::ClkHigh, time, clk_edge, timing.clk_high_us);
+            } else {
+                if self.three_wire {
+                    let stb_edge = self.stb_edge;
+                    self.violation(Phase::StbHold, time, stb_edge, timing.stb_hold_us);
+                } else {
+                    let start = self.transaction.as_ref().map_or(time, |tr| tr.start);
+                    self.violation(Phase::StartStop, time, start, timing.start_stop_us);
+                }
+            }
+            self.clocked = true;
+        }
+        // Stop condition, DIO goes up while CLK is high
+        if !self.three_wire && self.clk && !falling && dio_changed && !self.dio && self.bits < 8 {
+            self.violation(Phase::StartStop, time, clk_edge, timing.start_stop_us);
+        }
+        // Reading releases STB right after the last bit, there is no hold time
+        if stb_up && !self.reading {
+            self.violation(Phase::StbHold, time, clk_edge, timing.stb_hold_us);
+        }
+    }
+
+    fn violation(&mut self, phase: Phase, time: u64, since: u64, required_us: u16) {
+        let duration_ns = time.saturating_sub(since).saturating_mul(self.unit_ps) / 1000;
+        if duration_ns >= required_us as u64 * 1000 {
+            return;
+        }
+        if let Some(tr) = self.transaction.as_mut() {
+            if tr.violations_len < DECODER_MAX_VIOLATIONS {
+                tr.violations[tr.violations_len] = Violation {
+                    phase,
+                    time,
+                    duration_ns,
+                    required_us,
+                };
+                tr.violations_len += 1;
+            }
+        }
+    }
+
+    fn end<F: FnMut(&Transaction)>(&mut self, time: u64, out: &mut F) {
+        if let Some(mut tr) = self.transaction.take() {
+            tr.end = time;
+            // One stray clock before stop condition is normal for 2 wire interface
+            if self.bits > 1 {
+                tr.truncated = true;
+            }
+            out(&tr);
+        }
+    }
+
+    fn clock_bit(&mut self, dio: bool) {
+        if dio {
+            self.byte |= 1 << self.bits;
+        }
+        self.bits += 1;
+        if self.bits == 8 && self.three_wire {
+            self.push_byte(None);
+        }
+    }
+
+    fn push_byte(&mut self, ack: Option<bool>) {
+        let value = self.byte;
+        self.bits = 0;
+        self.byte = 0;
+        if let Some(tr) = self.transaction.as_mut() {
+            let direction = if self.reading {
+                Direction::Read
+            } else {
+                Direction::Write
+            };
+            if tr.len == 0 && Command::classify(value).is_read() {
+                self.reading = true;
+            }
+            tr.push(Frame {
+                value,
+                direction,
+                ack,
+            });
+        }
+    }
+}
+
+/// Error while reading capture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// Capture has no signal with given name
+    MissingSignal(&'static str),
+    /// Capture line could not be parsed, line numbers start from 1
+    Syntax(usize),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::MissingSignal(name) => write!(f, "capture has no {} signal", name),
+            DecodeError::Syntax(line) => write!(f, "capture syntax error at line {}", line),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Signal {
+    Clk,
+    Dio,
+    Stb,
+}
+
+fn signal_by_name(name: &str) -> Option<Signal> {
+    let name = name.trim();
+    let is = |s: &str| name.eq_ignore_ascii_case(s);
+    if is("clk") || is("sclk") || is("scl") {
+        Some(Signal::Clk)
+    } else if is("dio") || is("dio_in") || is("din") || is("sda") {
+        Some(Signal::Dio)
+    } else if is("stb") || is("cs") {
+        Some(Signal::Stb)
+    } else {
+        None
+    }
+}
+
+/// Decodes VCD capture and passes every transaction to `out`.
+///
+/// Capture time is reported in VCD time units.
+pub fn decode_vcd<F>(vcd: &str, out: F) -> Result<(), DecodeError>
+where
+    F: FnMut(&Transaction),
+{
+    vcd_transactions(vcd, None, out)
+}
+
+/// Same as [`decode_vcd`] but also checks phase durations against `timing`,
+/// see [`Transaction::violations`]. VCD time units are taken from `$timescale`.
+pub fn decode_vcd_checked<T, F>(vcd: &str, timing: T, out: F) -> Result<(), DecodeError>
+where
+    T: Into<BusTiming>,
+    F: FnMut(&Transaction),
+{
+    vcd_transactions(vcd, Some(timing.into()), out)
+}
+
+/// Parses `$timescale` declaration into picoseconds, VCD default is 1ns.
+fn vcd_timescale_ps(vcd: &str) -> Option<u64> {
+    let start = vcd.find("$timescale")? + "$timescale".len();
+    let end = start + vcd[start..].find("$end")?;
+    let scale = vcd[start..end].trim();
+    let split = scale
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(scale.len());
+    let value: u64 = scale[..split].parse().ok()?;
+    let unit = match scale[split..].trim() {
+        "s" => 1_000_000_000_000,
+        "ms" => 1_000_000_000,
+        "us" => 1_000_000,
+        "ns" => 1_000,
+        "ps" => 1,
+        _ => return None,
+    };
+    Some(value * unit)
+}
+
+fn vcd_transactions<F>(vcd: &str, timing: Option<BusTiming>, mut out: F) -> Result<(), DecodeError>
+where
+    F: FnMut(&Transaction),
+{
+    // Up to 2 ids for DIO: line itself and levels read by MCU
+    let mut clk_id = None;
+    let mut stb_id = None;
+    let mut dio_ids: [Option<&str>; 2] = [None, None];
+
+    let mut lines = vcd.lines().enumerate();
+    for (_, line) in lines.by_ref() {
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("$var") => {
+                let mut tokens = tokens.skip(2);
+                let (id, name) = match (tokens.next(), tokens.next()) {
+                    (Some(id), Some(name)) => (id, name),
+                    _ => continue,
+                };
+                match signal_by_name(name) {
+                    Some(Signal::Clk) => clk_id = Some(id),
+                    Some(Signal::Stb) => stb_id = Some(id),
+                    Some(Signal::Dio) if dio_ids[0].is_none() => dio_ids[0] = Some(id),
+                    Some(Signal::Dio) => dio_ids[1] = Some(id),
+                    None => {}
+                }
+            }
+            Some("$enddefinitions") => break,
+            _ => {}
+        }
+    }
+    let clk_id = clk_id.ok_or(DecodeError::MissingSignal("clk"))?;
+    if dio_ids[0].is_none() {
+        return Err(DecodeError::MissingSignal("dio"));
+    }
+
+    let mut decoder = match timing {
+        Some(timing) => Decoder::with_timing(timing, vcd_timescale_ps(vcd).unwrap_or(1_000)),
+        None => Decoder::new(),
+    };
+    let (mut clk, mut dio, mut stb) = (true, true, true);
+    let mut time = 0;
+    for (index, line) in lines {
+        for token in line.split_whitespace() {
+            if let Some(stamp) = token.strip_prefix('#') {
+                let stamp = stamp.parse().map_err(|_| DecodeError::Syntax(index + 1))?;
+                if stamp != time {
+                    decoder.sample(time, clk, dio, stb, &mut out);
+                    time = stamp;
+                }
+                continue;
+            }
+            let level = match token.as_bytes()[0] {
+                b'0' => false,
+                // Undefined and floating lines are pulled up
+                b'1' | b'x' | b'X' | b'z' | b'Z' => true,
+                _ => continue,
+            };
+            let id = &token[1..];
+            if id == clk_id {
+                clk = level;
+            } else if Some(id) == stb_id {
+                stb = level;
+            } else if dio_ids.contains(&Some(id)) {
+                dio = level;
+            }
+        }
+    }
+    decoder.sample(time, clk, dio, stb, &mut out);
+    decoder.finish(time, &mut out);
+    Ok(())
+}
+
+/// Decodes CSV capture and passes every transaction to `out`.
+///
+/// First line is a header with column names. Column that is not a signal is used as time,
+/// if there is no such column or it is not an integer, row number is used instead.
+/// Levels are `0` and `1`.
+pub fn decode_csv<F>(csv: &str, out: F) -> Result<(), DecodeError>
+where
+    F: FnMut(&Transaction),
+{
+    csv_transactions(csv, Decoder::new(), out)
+}
+
+/// Same as [`decode_csv`] but also checks phase durations against `timing`,
+/// see [`Transaction::violations`]. Time column is expected to be in us.
+pub fn decode_csv_checked<T, F>(csv: &str, timing: T, out: F) -> Result<(), DecodeError>
+where
+    T: Into<BusTiming>,
+    F: FnMut(&Transaction),
+{
+    csv_transactions(csv, Decoder::with_timing(timing, 1_000_000), out)
+}
+
+fn csv_transactions<F>(csv: &str, mut decoder: Decoder, mut out: F) -> Result<(), DecodeError>
+where
+    F: FnMut(&Transaction),
+{
+    let mut lines = csv.lines().enumerate();
+    let header = match lines.next() {
+        Some((_, header)) => header,
+        None => return Err(DecodeError::MissingSignal("clk")),
+    };
+
+    let (mut clk_col, mut dio_col, mut stb_col, mut time_col) = (None, None, None, None);
+    for (col, name) in header.split(',').enumerate() {
+        match signal_by_name(name) {
+            Some(Signal::Clk) => clk_col = Some(col),
+            Some(Signal::Dio) => dio_col = Some(col),
+            Some(Signal::Stb) => stb_col = Some(col),
+            None if time_col.is_none() => time_col = Some(col),
+            None => {}
+        }
+    }
+    let clk_col = clk_col.ok_or(DecodeError::MissingSignal("clk"))?;
+    let dio_col = dio_col.ok_or(DecodeError::MissingSignal("dio"))?;
+
+    let mut time = 0;
+    for (index, line) in lines {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (mut clk, mut dio, mut stb) = (None, None, Some(true));
+        time = index as u64;
+        for (col, value) in line.split(',').enumerate() {
+            let value = value.trim();
+            if Some(col) == time_col {
+                if let Ok(t) = value.parse() {
+                    time = t;
+                }
+                continue;
+            }
+            let level = match value {
+                "0" => false,
+                "1" => true,
+                _ => continue,
+            };
+            if col == clk_col {
+                clk = Some(level);
+            } else if col == dio_col {
+                dio = Some(level);
+            } else if Some(col) == stb_col {
+                stb = Some(level);
+            }
+        }
+        match (clk, dio, stb) {
+            (Some(clk), Some(dio), Some(stb)) => decoder.sample(time, clk, dio, stb, &mut out),
+            _ => return Err(DecodeError::Syntax(index + 1)),
+        }
+    }
+    decoder.finish(time, &mut out);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(all(feature = "keys", any(feature = "clkdio", feature = "clkdiostb")))]
+    use crate::recorder::{RecordPin, Recorder};
+    #[cfg(all(feature = "keys", any(feature = "clkdio", feature = "clkdiostb")))]
+    use crate::sim::{Line, SimChip, SimPin};
+    #[cfg(all(feature = "keys", any(feature = "clkdio", feature = "clkdiostb")))]
+    use core::cell::RefCell;
+
+    extern crate std;
+    use std::string::{String, ToString};
+    use std::vec::Vec;
+
+    #[cfg(all(feature = "keys", any(feature = "clkdio", feature = "clkdiostb")))]
+    fn decode(vcd: &str) -> Vec<String> {
+        let mut out = Vec::new();
+        decode_vcd(vcd, |tr| out.push(tr.to_string())).unwrap();
+        out
+    }
+
+    #[test]
+    fn command_test() {
+        assert_eq!(
+            Command::classify(COM_DATA_READ),
+            Command::Data {
+                read: true,
+                fixed: false,
+                raw: COM_DATA_READ
+            }
+        );
+        assert_eq!(Command::classify(COM_ADDRESS | 3), Command::Address(3));
+        assert_eq!(
+            Command::classify(COM_DISPLAY_ON | 2).to_string(),
+            "COM_DISPLAY_ON|2"
+        );
+        assert_eq!(
+            Command::classify(COM_DISPLAY_OFF).to_string(),
+            "COM_DISPLAY_OFF"
+        );
+        assert_eq!(
+            Command::classify(COM_DATA_ADDRESS_FIXED).to_string(),
+            "COM_DATA_ADDRESS_FIXED"
+        );
+        assert_eq!(Command::classify(0b11).to_string(), "COM_DISPLAY_MODE|3");
+
+        assert_eq!(segment_char(CHAR_0), '0');
+        assert_eq!(segment_char(CHAR_S), '5');
+        assert_eq!(segment_char(CHAR_b | SEG_8), 'b');
+        assert_eq!(segment_char(SEG_1 | SEG_4), '?');
+    }
+
+    #[test]
+    #[cfg(all(feature = "clkdio", feature = "keys"))]
+    fn decode_2wire_test() {
+        let chip = RefCell::new(SimChip::tm1637());
+        chip.borrow_mut().set_key_scan(&[0xEF]);
+        let rec: Recorder<4096> = Recorder::new();
+        let mut dio = RecordPin::new(&rec, Line::Dio, SimPin::dio(&chip));
+        let mut clk = RecordPin::new(&rec, Line::Clk, SimPin::clk(&chip));
+        let mut delay = rec.delay(|_| {});
+        tm_send_bytes_2wire(&mut dio, &mut clk, &mut delay, 2, &[COM_DISPLAY_ON | 3]).unwrap();
+        let digits = [COM_ADDRESS | 1, CHAR_1 | SEG_8, CHAR_A];
+        tm_send_bytes_2wire(&mut dio, &mut clk, &mut delay, 2, &digits).unwrap();
+        tm_read_byte_2wire(&mut dio, &mut clk, &mut delay, 2).unwrap();
+        assert_eq!(rec.dropped(), 0);
+
+        let mut vcd = String::new();
+        rec.write_vcd(&mut vcd).unwrap();
+        let decoded = decode(&vcd);
+        assert_eq!(decoded.len(), 3);
+        assert!(decoded[0].ends_with(" COM_DISPLAY_ON|3 ACK"));
+        assert!(decoded[1].ends_with(" COM_ADDRESS|1 ACK: 0x86 '1.' ACK, 0x77 'A' ACK"));
+        assert!(decoded[2].ends_with(" COM_DATA_READ ACK: key 0xef ACK"));
+
+        let mut violations = Vec::new();
+        decode_vcd_checked(&vcd, 2, |tr| violations.extend_from_slice(tr.violations())).unwrap();
+        assert!(violations.is_empty());
+
+        let mut out = Vec::new();
+        decode_vcd_checked(&vcd, 3, |tr| out.push(tr.clone())).unwrap();
+        let start = out[0].violations()[0];
+        assert_eq!(start.phase, Phase::StartStop);
+        assert_eq!(start.duration_ns, 2000);
+        assert_eq!(start.required_us, 3);
+        assert!(out[0]
+            .to_string()
+            .contains(" (start_stop 2000ns < 3us at #"));
+
+        // Only key scan bits depend on ACK wait
+        let timing = BusTiming {
+            ack_wait_us: 3,
+            ..BusTiming::uniform(2)
+        };
+        out.clear();
+        decode_vcd_checked(&vcd, timing, |tr| out.push(tr.clone())).unwrap();
+        assert!(out[0].violations().is_empty());
+        assert!(out[1].violations().is_empty());
+        assert_eq!(out[2].violations()[0].phase, Phase::AckWait);
+    }
+
+    #[test]
+    #[cfg(all(feature = "clkdiostb", feature = "keys"))]
+    fn decode_3wire_test() {
+        let chip = RefCell::new(SimChip::tm1638());
+        chip.borrow_mut().set_key_scan(&[0x04, 0, 0, 0x40]);
+        let rec: Recorder<4096> = Recorder::new();
+        let mut dio = RecordPin::new(&rec, Line::Dio, SimPin::dio(&chip));
+        let mut clk = RecordPin::new(&rec, Line::Clk, SimPin::clk(&chip));
+        let mut stb = RecordPin::new(&rec, Line::Stb, SimPin::stb(&chip));
+        let mut delay = rec.delay(|_| {});
+        let bytes = [COM_ADDRESS, CHAR_4, 0];
+        tm_send_bytes_3wire(&mut dio, &mut clk, &mut stb, &mut delay, 1, &bytes).unwrap();
+        tm_read_bytes_3wire(&mut dio, &mut clk, &mut stb, &mut delay, 1, 4).unwrap();
+
+        let mut vcd = String::new();
+        rec.write_vcd(&mut vcd).unwrap();
+        let decoded = decode(&vcd);
+        assert_eq!(decoded.len(), 2);
+        assert!(decoded[0].ends_with(" COM_ADDRESS|0: 0x66 '4', 0x00 ' '"));
+        assert!(decoded[1].ends_with(" COM_DATA_READ: key 0x04, key 0x00, key 0x00, key 0x40"));
+
+        let mut violations = Vec::new();
+        decode_vcd_checked(&vcd, 1, |tr| violations.extend_from_slice(tr.violations())).unwrap();
+        assert!(violations.is_empty());
+
+        let mut out = Vec::new();
+        decode_vcd_checked(&vcd, 2, |tr| out.push(tr.clone())).unwrap();
+        assert_eq!(out[0].violations()[0].phase, Phase::StbHold);
+    }
+
+    #[test]
+    fn decode_csv_test() {
+        // Display off command 0x80 via 3 wire interface, LSB first
+        let mut csv = String::from("Time,CLK,DIO,STB\n0,1,1,1\n1,1,1,0\n");
+        let mut t = 2;
+        for bit in 0..8 {
+            let level = (COM_DISPLAY_OFF >> bit) & 1;
+            csv += &std::format!("{},0,{},0\n{},1,{},0\n", t, level, t + 1, level);
+            t += 2;
+        }
+        csv += &std::format!("{},1,1,1\n", t);
+
+        let mut out = Vec::new();
+        decode_csv(&csv, |tr| out.push(tr.clone())).unwrap();
+        assert_eq!(out.len(), 1);
+        assert_eq!(out[0].start, 1);
+        assert_eq!(out[0].end, 18);
+        assert!(!out[0].truncated);
+        assert_eq!(
+            out[0].command(),
+            Some(Command::Display {
+                on: false,
+                brightness: 0
+            })
+        );
+
+        let mut out = Vec::new();
+        decode_csv_checked(&csv, 1, |tr| out.push(tr.clone())).unwrap();
+        assert!(out[0].violations().is_empty());
+        out.clear();
+        decode_csv_checked(&csv, BusTiming::uniform(2), |tr| out.push(tr.clone())).unwrap();
+        let v = out[0].violations()[0];
+        assert_eq!((v.phase, v.time, v.duration_ns), (Phase::StbHold, 2, 1000));
+
+        assert_eq!(
+            decode_csv("time,dio\n", |_| {}),
+            Err(DecodeError::MissingSignal("clk"))
+        );
+        assert_eq!(
+            decode_csv("time,clk,dio\n0,1\n", |_| {}),
+            Err(DecodeError::Syntax(2))
+        );
+    }
+}
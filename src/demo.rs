@@ -1,122 +1,143 @@
 //! Demo feature aimed to quick functionality check.
 //! Do not use it in production code.
 //!
-//!
-//! It is require you to have properly configure global memory allocator.
-//! If you are developing for embedded devices
-//! than you probably does not have global memory allocator by default.
+//! Demo handles up to 8 displays and does not need global memory allocator.
+//! [`Demo::next_state`] returns borrowed bytes instead of `Vec`.
+
+// `is_multiple_of` is not available on compilers this crate still supports
+#![allow(clippy::manual_is_multiple_of)]
+use super::frame::FrameBuffer;
 use super::fx::*;
 use super::*;
 
-use alloc::vec::Vec;
+/// Maximum number of displays, extra ones are ignored.
+const DISPLAYS_MAX: usize = 8;
+
+/// TM1637 display memory size.
+const TM1637_RAM_SIZE: usize = TM1637_MAX_SEGMENTS as usize;
 
 pub struct Demo {
     spin: Spinner,
-    slide: Slider,
-    slide_last: Vec<u8>,
+    slide: Slider<&'static [u8]>,
+    state: [u8; DISPLAYS_MAX],
+    frame: FrameBuffer<TM1637_RAM_SIZE>,
     displays: usize,
     iter: usize,
     brightness: u8,
 }
 
 impl Demo {
+    /// Creates demo for `displays` count, values above 8 are capped to 8.
     pub fn new(displays: u8) -> Demo {
-        let d = if displays <= 1 { 1 } else { displays - 1 };
+        let displays = (displays as usize).clamp(1, DISPLAYS_MAX);
         Demo {
             spin: Spinner::new(SEG_1, true),
-            slide: Slider::new(SlideType::Cycle, d, &CHARS),
-            slide_last: Vec::new(),
-            displays: displays as usize,
+            slide: Slider::new(SlideType::Cycle, Self::slide_len(displays) as u8, &CHARS),
+            state: [0; DISPLAYS_MAX],
+            frame: FrameBuffer::new(),
+            displays,
             iter: 0,
             brightness: 0,
         }
     }
 
-    pub fn init_2wire<DIO, CLK, D>(
+    pub fn init_2wire<DIO, CLK, D, T, E, EC>(
         &mut self,
         dio: &mut DIO,
         clk: &mut CLK,
         delay_us: &mut D,
-        bus_delay_us: u16,
-    ) -> Result<(), TmError>
+        timing: T,
+    ) -> Result<(), TmError<E, EC>>
     where
-        DIO: InputPin + OutputPin,
-        CLK: OutputPin,
-        D: FnMut(u16) -> (),
+        DIO: Dio<Error = E>,
+        CLK: OutputPin<Error = EC>,
+        D: BusDelay,
+        T: Into<BusTiming>,
     {
-        clk.set_high().map_err(|_| TmError::Clk)?;
-        dio.set_high().map_err(|_| TmError::Dio)?;
-        tm_send_bytes_2wire(dio, clk, delay_us, bus_delay_us, &[COM_DATA_ADDRESS_ADD])?;
-        tm_send_bytes_2wire(dio, clk, delay_us, bus_delay_us, &[COM_DISPLAY_ON])
+        let timing = timing.into();
+        clk.set_high().map_err(TmError::Clk)?;
+        dio.set_dio(true).map_err(TmError::Dio)?;
+        self.frame.invalidate();
+        tm_send_bytes_2wire(dio, clk, delay_us, timing, &[COM_DATA_ADDRESS_ADD])?;
+        tm_send_bytes_2wire(dio, clk, delay_us, timing, &[COM_DISPLAY_ON])
     }
 
-    pub fn init_3wire<DIO, CLK, STB, D>(
+    pub fn init_3wire<DIO, CLK, STB, D, T, E, EC, ES>(
         &mut self,
         dio: &mut DIO,
         clk: &mut CLK,
         stb: &mut STB,
         delay_us: &mut D,
-        bus_delay_us: u16,
-    ) -> Result<(), TmError>
+        timing: T,
+    ) -> Result<(), TmError<E, EC, ES>>
     where
-        DIO: InputPin + OutputPin,
-        CLK: OutputPin,
-        STB: OutputPin,
-        D: FnMut(u16) -> (),
+        DIO: Dio<Error = E>,
+        CLK: OutputPin<Error = EC>,
+        STB: OutputPin<Error = ES>,
+        D: BusDelay,
+        T: Into<BusTiming>,
     {
-        clk.set_high().map_err(|_| TmError::Clk)?;
-        dio.set_high().map_err(|_| TmError::Dio)?;
-        stb.set_high().map_err(|_| TmError::Stb)?;
-        tm_send_bytes_3wire(
-            dio,
-            clk,
-            stb,
-            delay_us,
-            bus_delay_us,
-            &[COM_DATA_ADDRESS_ADD],
-        )?;
-        tm_send_bytes_3wire(dio, clk, stb, delay_us, bus_delay_us, &[COM_DISPLAY_ON])
+        let timing = timing.into();
+        clk.set_high().map_err(TmError::Clk)?;
+        dio.set_dio(true).map_err(TmError::Dio)?;
+        stb.set_high().map_err(TmError::Stb)?;
+        tm_send_bytes_3wire(dio, clk, stb, delay_us, timing, &[COM_DATA_ADDRESS_ADD])?;
+        tm_send_bytes_3wire(dio, clk, stb, delay_us, timing, &[COM_DISPLAY_ON])
     }
 
-    pub fn next_state(&mut self) -> Vec<u8> {
-        let mut result = Vec::new();
+    /// Returns bytes for every display.
+    ///
+    /// Bytes are borrowed from the demo state, copy them if you need to keep them.
+    pub fn next_state(&mut self) -> &[u8] {
+        let slide_len = Self::slide_len(self.displays);
 
         if self.iter % 3 == 0 {
-            self.slide_last = self.slide.next().unwrap();
+            self.slide.next_frame(&mut self.state[..slide_len]);
 
             let off = (self.iter / 3) % 4;
-            for i in 0..self.slide_last.len() {
+            for (i, byte) in self.state[..slide_len].iter_mut().enumerate() {
                 if (i + off) % 4 == 0 {
-                    self.slide_last[i] = self.slide_last[i] | SEG_8;
+                    *byte |= SEG_8;
                 }
             }
         }
 
-        result.append(&mut self.slide_last.clone());
         if self.displays > 1 {
-            result.push(self.spin.next().unwrap());
+            self.state[slide_len] = self.spin.next().unwrap();
         }
 
-        result
+        &self.state[..self.displays]
+    }
+
+    fn slide_len(displays: usize) -> usize {
+        if displays <= 1 {
+            1
+        } else {
+            displays - 1
+        }
     }
 
-    pub fn next_2wire<DIO, CLK, D>(
+    pub fn next_2wire<DIO, CLK, D, T, E, EC>(
         &mut self,
         dio: &mut DIO,
         clk: &mut CLK,
         delay_us: &mut D,
-        bus_delay_us: u16,
-    ) -> Result<u8, TmError>
+        timing: T,
+    ) -> Result<u8, TmError<E, EC>>
     where
-        DIO: InputPin + OutputPin,
-        CLK: OutputPin,
-        D: FnMut(u16) -> (),
+        DIO: Dio<Error = E>,
+        CLK: OutputPin<Error = EC>,
+        D: BusDelay,
+        T: Into<BusTiming>,
     {
-        let mut out = self.next_state();
-        let mut bytes = Vec::new();
-        bytes.push(COM_ADDRESS);
-        bytes.append(&mut out);
-        tm_send_bytes_2wire(dio, clk, delay_us, bus_delay_us, &bytes)?;
+        let timing = timing.into();
+        let out = self.next_state();
+        let len = out.len().min(TM1637_RAM_SIZE);
+        let mut bytes = [0_u8; TM1637_RAM_SIZE];
+        bytes[..len].copy_from_slice(&out[..len]);
+        // Only changed digits are sent
+        self.frame.write(0, &bytes[..len]);
+        self.frame.flush_2wire(dio, clk, delay_us, timing)?;
 
         self.iter += 1;
 
@@ -126,42 +147,45 @@ impl Demo {
                 dio,
                 clk,
                 delay_us,
-                bus_delay_us,
+                timing,
                 &[COM_DISPLAY_ON | (self.brightness & DISPLAY_BRIGHTNESS_MASK)],
             )?;
         }
 
-        tm_read_byte_2wire(dio, clk, delay_us, bus_delay_us)
+        // Key scan read changes data setting command
+        self.frame.forget_mode();
+        tm_read_byte_2wire(dio, clk, delay_us, timing)
     }
 
-    pub fn next_3wire<DIO, CLK, STB, D>(
+    pub fn next_3wire<DIO, CLK, STB, D, T, E, EC, ES>(
         &mut self,
         dio: &mut DIO,
         clk: &mut CLK,
         stb: &mut STB,
         delay_us: &mut D,
-        bus_delay_us: u16,
-    ) -> Result<[u8; 4], TmError>
+        timing: T,
+    ) -> Result<[u8; 4], TmError<E, EC, ES>>
     where
-        DIO: InputPin + OutputPin,
-        CLK: OutputPin,
-        STB: OutputPin,
-        D: FnMut(u16) -> (),
+        DIO: Dio<Error = E>,
+        CLK: OutputPin<Error = EC>,
+        STB: OutputPin<Error = ES>,
+        D: BusDelay,
+        T: Into<BusTiming>,
     {
-        let out = self.next_state();
-        let mut bytes = Vec::new();
-        bytes.push(COM_ADDRESS);
+        let timing = timing.into();
         let off = self.iter % 10;
-        for i in 0..out.len() {
-            bytes.push(out[i]);
-            bytes.push(if (i + 10 - off) % 10 == 0 {
-                SEG_9 | SEG_10 | SEG_11 | SEG_12
-            } else {
-                0
-            });
+        let mut bytes = [0_u8; 1 + 2 * DISPLAYS_MAX];
+        bytes[0] = COM_ADDRESS;
+        let out = self.next_state();
+        let len = 1 + 2 * out.len();
+        for (i, byte) in out.iter().enumerate() {
+            bytes[1 + 2 * i] = *byte;
+            if (i + 10 - off) % 10 == 0 {
+                bytes[2 + 2 * i] = SEG_9 | SEG_10 | SEG_11 | SEG_12;
+            }
         }
 
-        tm_send_bytes_3wire(dio, clk, stb, delay_us, bus_delay_us, &bytes)?;
+        tm_send_bytes_3wire(dio, clk, stb, delay_us, timing, &bytes[..len])?;
 
         self.iter += 1;
         if self.iter % 10 == 0 {
@@ -171,11 +195,11 @@ impl Demo {
                 clk,
                 stb,
                 delay_us,
-                bus_delay_us,
+                timing,
                 &[COM_DISPLAY_ON | (self.brightness & DISPLAY_BRIGHTNESS_MASK)],
             )?;
         }
-        tm_read_bytes_3wire(dio, clk, stb, delay_us, bus_delay_us, 4)
+        tm_read_bytes_3wire(dio, clk, stb, delay_us, timing, 4)
     }
 }
 
@@ -0,0 +1,372 @@
+//! API for HALs that implement embedded-hal 1.0 traits (**eh1** feature).
+//!
+//! Functions here take 1.0 pins and [`DelayNs`] implementation instead of delay closure.
+//! Every pin keeps its own [`ErrorType::Error`], it is kept inside of [`TmError`].
+//!
+//! High level drivers have `new_eh1` constructors that take 1.0 pins and `DelayNs` as is:
+//!
+//! ```ignore
+//! let mut tm = Tm1637::new_eh1(dio, clk, delay, TM1637_TIMING);
+//! ```
+use super::{BusDelay, TmError};
+#[cfg(any(feature = "clkdio", feature = "clkdiostb"))]
+use super::{BusTiming, Dio};
+
+use embedded_hal::digital::v2 as hal02;
+use embedded_hal_1::delay::DelayNs;
+#[cfg(any(feature = "clkdio", feature = "clkdiostb"))]
+use embedded_hal_1::digital::InputPin;
+use embedded_hal_1::digital::{ErrorType, OutputPin};
+
+/// Wraps embedded-hal 1.0 pin keeping original error type.
+///
+/// Output pin works as 0.2 `digital::v2::OutputPin`, pin that is both input and output works as [`Dio`].
+pub struct Pin<P> {
+    pin: P,
+}
+
+impl<P> Pin<P> {
+    /// Wraps pin, works with owned pins as well as with `&mut` references.
+    pub fn new(pin: P) -> Pin<P> {
+        Pin { pin }
+    }
+
+    /// Gives back original pin.
+    pub fn release(self) -> P {
+        self.pin
+    }
+}
+
+impl<P: OutputPin> hal02::OutputPin for Pin<P> {
+    type Error = P::Error;
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.pin.set_low()
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.pin.set_high()
+    }
+}
+
+#[cfg(any(feature = "clkdio", feature = "clkdiostb"))]
+impl<P: InputPin + OutputPin> Dio for Pin<P> {
+    #[inline]
+    fn is_dio_high(&mut self) -> Result<bool, Self::Error> {
+        self.pin.is_high()
+    }
+}
+
+/// Wraps [`DelayNs`] implementation into [`BusDelay`] that is used across this crate.
+pub struct Delay<DL> {
+    delay: DL,
+}
+
+impl<DL> Delay<DL> {
+    /// Wraps delay, works with owned delay as well as with `&mut` reference.
+    pub fn new(delay: DL) -> Delay<DL> {
+        Delay { delay }
+    }
+
+    /// Gives back original delay.
+    pub fn release(self) -> DL {
+        self.delay
+    }
+}
+
+impl<DL: DelayNs> BusDelay for Delay<DL> {
+    #[inline]
+    fn delay_us(&mut self, us: u16) {
+        self.delay.delay_us(us as u32)
+    }
+}
+
+/// Turns [`DelayNs`] implementation into delay closure, see also [`Delay`].
+pub fn delay_fn<DL: DelayNs>(mut delay: DL) -> impl FnMut(u16) {
+    move |us| delay.delay_us(us as u32)
+}
+
+/// Same as [`tm_send_bytes_2wire`](super::tm_send_bytes_2wire) but for embedded-hal 1.0.
+///
+/// Arguments:
+///  - `dio`, `clk` - MCU interface pins
+///  - `delay` - delay provider
+///  - `timing` - bus timing or single delay value in us
+///  - `bytes` - slice of bytes to send
+#[cfg(feature = "clkdio")]
+pub fn tm_send_bytes_2wire<DIO, CLK, DL, T, E, EC>(
+    dio: &mut DIO,
+    clk: &mut CLK,
+    delay: &mut DL,
+    timing: T,
+    bytes: &[u8],
+) -> Result<(), TmError<E, EC>>
+where
+    DIO: InputPin + OutputPin + ErrorType<Error = E>,
+    CLK: OutputPin + ErrorType<Error = EC>,
+    DL: DelayNs,
+    T: Into<BusTiming>,
+{
+    super::tm_send_bytes_2wire(
+        &mut Pin::new(dio),
+        &mut Pin::new(clk),
+        &mut Delay::new(delay),
+        timing,
+        bytes,
+    )
+}
+
+/// Same as [`tm_send_bytes_2wire_noack`](super::tm_send_bytes_2wire_noack) but for embedded-hal 1.0.
+///
+/// Arguments:
+///  - `dio`, `clk` - MCU interface pins, DIO is used only as output
+///  - `delay` - delay provider
+///  - `timing` - bus timing or single delay value in us, see [`TM1640_TIMING`](super::TM1640_TIMING)
+///  - `bytes` - slice of bytes to send
+#[cfg(feature = "clkdio")]
+pub fn tm_send_bytes_2wire_noack<DIO, CLK, DL, T, E, EC>(
+    dio: &mut DIO,
+    clk: &mut CLK,
+    delay: &mut DL,
+    timing: T,
+    bytes: &[u8],
+) -> Result<(), TmError<E, EC>>
+where
+    DIO: OutputPin + ErrorType<Error = E>,
+    CLK: OutputPin + ErrorType<Error = EC>,
+    DL: DelayNs,
+    T: Into<BusTiming>,
+{
+    super::tm_send_bytes_2wire_noack(
+        &mut Pin::new(dio),
+        &mut Pin::new(clk),
+        &mut Delay::new(delay),
+        timing,
+        bytes,
+    )
+}
+
+/// Same as [`tm_read_byte_2wire`](super::tm_read_byte_2wire) but for embedded-hal 1.0.
+///
+/// Arguments:
+///  - `dio`, `clk` - MCU interface pins
+///  - `delay` - delay provider
+///  - `timing` - bus timing or single delay value in us
+#[cfg(all(feature = "keys", feature = "clkdio"))]
+pub fn tm_read_byte_2wire<DIO, CLK, DL, T, E, EC>(
+    dio: &mut DIO,
+    clk: &mut CLK,
+    delay: &mut DL,
+    timing: T,
+) -> Result<u8, TmError<E, EC>>
+where
+    DIO: InputPin + OutputPin + ErrorType<Error = E>,
+    CLK: OutputPin + ErrorType<Error = EC>,
+    DL: DelayNs,
+    T: Into<BusTiming>,
+{
+    super::tm_read_byte_2wire(
+        &mut Pin::new(dio),
+        &mut Pin::new(clk),
+        &mut Delay::new(delay),
+        timing,
+    )
+}
+
+/// Same as [`tm_send_bytes_3wire`](super::tm_send_bytes_3wire) but for embedded-hal 1.0.
+///
+/// Arguments:
+///  - `dio`, `clk`, `stb` - MCU interface pins
+///  - `delay` - delay provider
+///  - `timing` - bus timing or single delay value in us
+///  - `bytes` - slice of bytes to send
+#[cfg(feature = "clkdiostb")]
+pub fn tm_send_bytes_3wire<DIO, CLK, STB, DL, T, E, EC, ES>(
+    dio: &mut DIO,
+    clk: &mut CLK,
+    stb: &mut STB,
+    delay: &mut DL,
+    timing: T,
+    bytes: &[u8],
+) -> Result<(), TmError<E, EC, ES>>
+where
+    DIO: InputPin + OutputPin + ErrorType<Error = E>,
+    CLK: OutputPin + ErrorType<Error = EC>,
+    STB: OutputPin + ErrorType<Error = ES>,
+    DL: DelayNs,
+    T: Into<BusTiming>,
+{
+    super::tm_send_bytes_3wire(
+        &mut Pin::new(dio),
+        &mut Pin::new(clk),
+        &mut Pin::new(stb),
+        &mut Delay::new(delay),
+        timing,
+        bytes,
+    )
+}
+
+/// Same as [`tm_read_bytes_3wire`](super::tm_read_bytes_3wire) but for embedded-hal 1.0.
+///
+/// Arguments:
+///  - `dio`, `clk`, `stb` - MCU interface pins
+///  - `delay` - delay provider
+///  - `timing` - bus timing or single delay value in us
+///  - `read_count` - number of bytes to read into output array
+#[cfg(all(feature = "keys", feature = "clkdiostb"))]
+pub fn tm_read_bytes_3wire<DIO, CLK, STB, DL, T, E, EC, ES>(
+    dio: &mut DIO,
+    clk: &mut CLK,
+    stb: &mut STB,
+    delay: &mut DL,
+    timing: T,
+    read_count: u8,
+) -> Result<[u8; 4], TmError<E, EC, ES>>
+where
+    DIO: InputPin + OutputPin + ErrorType<Error = E>,
+    CLK: OutputPin + ErrorType<Error = EC>,
+    STB: OutputPin + ErrorType<Error = ES>,
+    DL: DelayNs,
+    T: Into<BusTiming>,
+{
+    super::tm_read_bytes_3wire(
+        &mut Pin::new(dio),
+        &mut Pin::new(clk),
+        &mut Pin::new(stb),
+        &mut Delay::new(delay),
+        timing,
+        read_count,
+    )
+}
+
+/// Same as [`tm_send_bytes_1wire`](super::tm_send_bytes_1wire) but for embedded-hal 1.0.
+///
+/// Arguments:
+///  - `dio` - MCU interface pin
+///  - `delay` - delay provider
+///  - `delay_value` - duration of one bit in us, see [`TM1652_BIT_DELAY_US`](super::TM1652_BIT_DELAY_US)
+///  - `bytes` - slice of bytes to send
+#[cfg(feature = "dio")]
+pub fn tm_send_bytes_1wire<DIO, DL, E>(
+    dio: &mut DIO,
+    delay: &mut DL,
+    delay_value: u16,
+    bytes: &[u8],
+) -> Result<(), TmError<E>>
+where
+    DIO: OutputPin + ErrorType<Error = E>,
+    DL: DelayNs,
+{
+    super::tm_send_bytes_1wire(
+        &mut Pin::new(dio),
+        &mut Delay::new(delay),
+        delay_value,
+        bytes,
+    )
+}
+
+#[cfg(all(test, any(feature = "clkdio", feature = "dio")))]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct PinFault;
+
+    impl embedded_hal_1::digital::Error for PinFault {
+        fn kind(&self) -> embedded_hal_1::digital::ErrorKind {
+            embedded_hal_1::digital::ErrorKind::Other
+        }
+    }
+
+    /// Output pin that counts edges and fails after `limit` of them.
+    struct EdgePin {
+        edges: u32,
+        limit: u32,
+    }
+
+    impl ErrorType for EdgePin {
+        type Error = PinFault;
+    }
+
+    impl OutputPin for EdgePin {
+        fn set_low(&mut self) -> Result<(), PinFault> {
+            self.set_high()
+        }
+
+        fn set_high(&mut self) -> Result<(), PinFault> {
+            self.edges += 1;
+            if self.edges > self.limit {
+                return Err(PinFault);
+            }
+            Ok(())
+        }
+    }
+
+    struct NoDelay;
+
+    impl DelayNs for NoDelay {
+        fn delay_ns(&mut self, _: u32) {}
+    }
+
+    /// Data line that always reads low, looks like ACK from chip.
+    #[cfg(feature = "clkdio")]
+    struct AckPin;
+
+    #[cfg(feature = "clkdio")]
+    impl ErrorType for AckPin {
+        type Error = core::convert::Infallible;
+    }
+
+    #[cfg(feature = "clkdio")]
+    impl OutputPin for AckPin {
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "clkdio")]
+    impl InputPin for AckPin {
+        fn is_high(&mut self) -> Result<bool, Self::Error> {
+            Ok(false)
+        }
+
+        fn is_low(&mut self) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+    }
+
+    #[cfg(feature = "clkdio")]
+    #[test]
+    fn driver_keeps_pin_errors_test() {
+        use crate::tm1637::Tm1637;
+
+        let clk = EdgePin { edges: 0, limit: 3 };
+        let mut tm = Tm1637::new_eh1(AckPin, clk, NoDelay, 1);
+        match tm.init() {
+            Err(TmError::Clk(e)) => assert_eq!(e, PinFault),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[cfg(feature = "dio")]
+    #[test]
+    fn pin_error_is_kept_test() {
+        let mut dio = EdgePin {
+            edges: 0,
+            limit: u32::MAX,
+        };
+        assert!(tm_send_bytes_1wire(&mut dio, &mut NoDelay, 1, &[0x08, 0x18]).is_ok());
+        // start, 8 data, parity and stop bits for each frame
+        assert_eq!(dio.edges, 22);
+
+        let mut dio = EdgePin { edges: 0, limit: 3 };
+        match tm_send_bytes_1wire(&mut dio, &mut NoDelay, 1, &[0x08]) {
+            Err(TmError::Dio(e)) => assert_eq!(e, PinFault),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,369 @@
+//! Display memory mirror that sends only changed bytes.
+//!
+//! [`FrameBuffer`] keeps a copy of chip display memory and marks written addresses as dirty.
+//! On flush it picks the cheapest update for chips with TM1637/TM1638 command set:
+//!  - [`Flush::Fixed`] - few scattered bytes, every one is written with fixed address command
+//!  - [`Flush::Run`] - one auto increment write from the first to the last dirty address
+//!  - [`Flush::Full`] - auto increment write of whole memory
+//!
+//! Buffer remembers last data setting command it sent, so mode is switched only when it is needed.
+//! Call [`forget_mode`](FrameBuffer::forget_mode) after key scan reads or other data setting commands,
+//! and [`invalidate`](FrameBuffer::invalidate) if display memory was written bypassing the buffer.
+use super::*;
+
+/// Maximum display memory size supported by [`FrameBuffer`].
+pub const FRAME_MAX_SIZE: usize = 48;
+
+/// Update that was chosen by [`FrameBuffer::flush`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Flush {
+    /// Nothing changed, nothing was sent
+    Clean,
+    /// Dirty bytes were written one by one in fixed address mode
+    Fixed,
+    /// Dirty range was written in auto increment mode
+    Run,
+    /// Whole memory was written in auto increment mode
+    Full,
+}
+
+/// Copy of `N` bytes of display memory with dirty tracking.
+#[derive(Debug, Clone)]
+pub struct FrameBuffer<const N: usize> {
+    ram: [u8; N],
+    dirty: [bool; N],
+    auto_increment: Option<bool>,
+}
+
+impl<const N: usize> Default for FrameBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> FrameBuffer<N> {
+    /// Creates blank buffer, chip state is unknown so the first flush writes everything.
+    ///
+    /// `N` must not exceed [`FRAME_MAX_SIZE`].
+    pub fn new() -> FrameBuffer<N> {
+        assert!(N <= FRAME_MAX_SIZE, "Frame is too big");
+        FrameBuffer {
+            ram: [0; N],
+            dirty: [true; N],
+            auto_increment: None,
+        }
+    }
+
+    /// Current buffer content.
+    pub fn ram(&self) -> &[u8; N] {
+        &self.ram
+    }
+
+    /// Writes bytes starting from `address`, only changed bytes become dirty.
+    ///
+    /// Panics if bytes do not fit into buffer.
+    pub fn write(&mut self, address: usize, bytes: &[u8]) {
+        let ram = &mut self.ram[address..address + bytes.len()];
+        let dirty = &mut self.dirty[address..address + bytes.len()];
+        for ((old, new), dirty) in ram.iter_mut().zip(bytes).zip(dirty.iter_mut()) {
+            if *old != *new {
+                *old = *new;
+                *dirty = true;
+            }
+        }
+    }
+
+    /// Returns true if there is something to flush.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.iter().any(|d| *d)
+    }
+
+    /// Forgets chip state: next flush rewrites whole memory and resends data setting command.
+    pub fn invalidate(&mut self) {
+        self.dirty = [true; N];
+        self.forget_mode();
+    }
+
+    /// Forgets last data setting command, next flush sends it again.
+    pub fn forget_mode(&mut self) {
+        self.auto_increment = None;
+    }
+
+    /// Cheapest update for current dirty bytes, this is what [`flush`](FrameBuffer::flush) would do.
+    pub fn plan(&self) -> Flush {
+        let (first, last, count) = match self.dirty_range() {
+            Some(range) => range,
+            None => return Flush::Clean,
+        };
+
+        if count == 1 {
+            // Single byte write works in both modes
+            return Flush::Fixed;
+        }
+
+        // Cost in bytes, start and stop of every transfer are counted as one more byte
+        let fixed = self.mode_cost(false) + 3 * count;
+        let run = self.mode_cost(true) + 2 + last - first + 1;
+        if fixed < run {
+            Flush::Fixed
+        } else if first == 0 && last == N - 1 {
+            Flush::Full
+        } else {
+            Flush::Run
+        }
+    }
+
+    /// Sends dirty bytes with given transfer function and returns chosen update.
+    ///
+    /// `send` should write bytes with one bus transfer, like [`tm_send_bytes_2wire`] does.
+    /// Bytes stay dirty if transfer fails.
+    pub fn flush<F, E, C, S>(&mut self, mut send: F) -> Result<Flush, TmError<E, C, S>>
+    where
+        F: FnMut(&[u8]) -> Result<(), TmError<E, C, S>>,
+    {
+        let plan = self.plan();
+        let result = match plan {
+            Flush::Clean => Ok(()),
+            Flush::Fixed => self.flush_fixed(&mut send),
+            Flush::Run | Flush::Full => self.flush_run(&mut send),
+        };
+        if result.is_err() {
+            self.forget_mode();
+        }
+        result.map(|_| plan)
+    }
+
+    /// Flushes buffer to 2 wire chip with ACK (TM1637), see [`flush`](FrameBuffer::flush).
+    #[cfg(feature = "clkdio")]
+    pub fn flush_2wire<DIO, CLK, D, T, E, EC>(
+        &mut self,
+        dio: &mut DIO,
+        clk: &mut CLK,
+        delay_us: &mut D,
+        timing: T,
+    ) -> Result<Flush, TmError<E, EC>>
+    where
+        DIO: Dio<Error = E>,
+        CLK: OutputPin<Error = EC>,
+        D: BusDelay,
+        T: Into<BusTiming>,
+    {
+        let timing = timing.into();
+        self.flush(|bytes| tm_send_bytes_2wire(dio, clk, delay_us, timing, bytes))
+    }
+
+    /// Flushes buffer to 2 wire chip without ACK (TM1640), see [`flush`](FrameBuffer::flush).
+    #[cfg(feature = "clkdio")]
+    pub fn flush_2wire_noack<DIO, CLK, D, T, E, EC>(
+        &mut self,
+        dio: &mut DIO,
+        clk: &mut CLK,
+        delay_us: &mut D,
+        timing: T,
+    ) -> Result<Flush, TmError<E, EC>>
+    where
+        DIO: DioOut<Error = E>,
+        CLK: OutputPin<Error = EC>,
+        D: BusDelay,
+        T: Into<BusTiming>,
+    {
+        let timing = timing.into();
+        self.flush(|bytes| tm_send_bytes_2wire_noack(dio, clk, delay_us, timing, bytes))
+    }
+
+    /// Flushes buffer to 3 wire chip (TM1638, TM1628 and so on), see [`flush`](FrameBuffer::flush).
+    #[cfg(feature = "clkdiostb")]
+    pub fn flush_3wire<DIO, CLK, STB, D, T, E, EC, ES>(
+        &mut self,
+        dio: &mut DIO,
+        clk: &mut CLK,
+        stb: &mut STB,
+        delay_us: &mut D,
+        timing: T,
+    ) -> Result<Flush, TmError<E, EC, ES>>
+    where
+        DIO: Dio<Error = E>,
+        CLK: OutputPin<Error = EC>,
+        STB: OutputPin<Error = ES>,
+        D: BusDelay,
+        T: Into<BusTiming>,
+    {
+        let timing = timing.into();
+        self.flush(|bytes| tm_send_bytes_3wire(dio, clk, stb, delay_us, timing, bytes))
+    }
+
+    /// First and last dirty addresses and number of dirty bytes.
+    fn dirty_range(&self) -> Option<(usize, usize, usize)> {
+        let first = self.dirty.iter().position(|d| *d)?;
+        let last = self.dirty.iter().rposition(|d| *d)?;
+        let count = self.dirty.iter().filter(|d| **d).count();
+        Some((first, last, count))
+    }
+
+    fn mode_cost(&self, auto_increment: bool) -> usize {
+        if self.auto_increment == Some(auto_increment) {
+            0
+        } else {
+            2
+        }
+    }
+
+    fn set_mode<F, E, C, S>(
+        &mut self,
+        send: &mut F,
+        auto_increment: bool,
+    ) -> Result<(), TmError<E, C, S>>
+    where
+        F: FnMut(&[u8]) -> Result<(), TmError<E, C, S>>,
+    {
+        if self.auto_increment != Some(auto_increment) {
+            let command = if auto_increment {
+                COM_DATA_ADDRESS_ADD
+            } else {
+                COM_DATA_ADDRESS_FIXED
+            };
+            send(&[command])?;
+            self.auto_increment = Some(auto_increment);
+        }
+        Ok(())
+    }
+
+    fn flush_fixed<F, E, C, S>(&mut self, send: &mut F) -> Result<(), TmError<E, C, S>>
+    where
+        F: FnMut(&[u8]) -> Result<(), TmError<E, C, S>>,
+    {
+        if self.dirty.iter().filter(|d| **d).count() > 1 {
+            self.set_mode(send, false)?;
+        }
+        for address in 0..N {
+            if self.dirty[address] {
+                send(&[COM_ADDRESS | address as u8, self.ram[address]])?;
+                self.dirty[address] = false;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush_run<F, E, C, S>(&mut self, send: &mut F) -> Result<(), TmError<E, C, S>>
+    where
+        F: FnMut(&[u8]) -> Result<(), TmError<E, C, S>>,
+    {
+        let (first, last, _) = match self.dirty_range() {
+            Some(range) => range,
+            None => return Ok(()),
+        };
+        self.set_mode(send, true)?;
+
+        let len = last - first + 1;
+        let mut bytes = [0_u8; 1 + FRAME_MAX_SIZE];
+        bytes[0] = COM_ADDRESS | first as u8;
+        bytes[1..=len].copy_from_slice(&self.ram[first..=last]);
+        send(&bytes[..=len])?;
+        for dirty in self.dirty[first..=last].iter_mut() {
+            *dirty = false;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "clkdio"))]
+mod tests {
+    use super::*;
+    use crate::sim::{SimChip, SimPin};
+    use core::cell::RefCell;
+
+    #[test]
+    fn flush_test() {
+        let chip = RefCell::new(SimChip::tm1637());
+        let (mut dio, mut clk) = (SimPin::dio(&chip), SimPin::clk(&chip));
+        let mut flush = |frame: &mut FrameBuffer<6>| {
+            let before = chip.borrow().transactions();
+            let plan = frame
+                .flush_2wire(&mut dio, &mut clk, &mut |_| {}, TM1637_TIMING)
+                .unwrap();
+            (plan, chip.borrow().transactions() - before)
+        };
+
+        let mut frame = FrameBuffer::<6>::new();
+        frame.write(0, &[CHAR_1, CHAR_2, CHAR_3, CHAR_4]);
+        assert_eq!(flush(&mut frame), (Flush::Full, 2));
+        assert_eq!(flush(&mut frame), (Flush::Clean, 0));
+
+        // Same value does not make byte dirty
+        frame.write(1, &[CHAR_2, CHAR_0]);
+        assert_eq!(frame.plan(), Flush::Fixed);
+        assert_eq!(flush(&mut frame), (Flush::Fixed, 1));
+        frame.write(5, &[CHAR_5]);
+        assert_eq!(flush(&mut frame), (Flush::Fixed, 1));
+
+        frame.write(1, &[CHAR_9, CHAR_8, CHAR_7]);
+        assert_eq!(flush(&mut frame), (Flush::Run, 1));
+        frame.write(0, &[CHAR_A]);
+        frame.write(4, &[CHAR_b]);
+        assert_eq!(flush(&mut frame), (Flush::Run, 1));
+
+        assert_eq!(chip.borrow().ram(), frame.ram());
+        assert_eq!(chip.borrow().errors(), 0);
+
+        frame.forget_mode();
+        frame.write(2, &[CHAR_C, CHAR_d]);
+        assert_eq!(flush(&mut frame), (Flush::Run, 2));
+
+        frame.invalidate();
+        assert_eq!(flush(&mut frame), (Flush::Full, 2));
+    }
+
+    #[test]
+    fn flush_noack_test() {
+        let chip = RefCell::new(SimChip::tm1640());
+        let (mut dio, mut clk) = (SimPin::dio(&chip), SimPin::clk(&chip));
+        let mut frame = FrameBuffer::<16>::new();
+        frame.write(3, &[CHAR_1, CHAR_2]);
+        let plan = frame.flush_2wire_noack(&mut dio, &mut clk, &mut |_| {}, TM1640_TIMING);
+        assert_eq!(plan.unwrap(), Flush::Full);
+        frame.write(7, &[CHAR_3]);
+        let plan = frame.flush_2wire_noack(&mut dio, &mut clk, &mut |_| {}, TM1640_TIMING);
+        assert_eq!(plan.unwrap(), Flush::Fixed);
+
+        assert_eq!(chip.borrow().ram(), frame.ram());
+        assert_eq!(chip.borrow().errors(), 0);
+    }
+
+    #[test]
+    fn fixed_mode_test() {
+        let mut sent = 0;
+        let mut frame = FrameBuffer::<16>::new();
+        let mut flush = |frame: &mut FrameBuffer<16>| {
+            let before = sent;
+            let plan = frame
+                .flush(|_| -> Result<(), TmError<()>> {
+                    sent += 1;
+                    Ok(())
+                })
+                .unwrap();
+            (plan, sent - before)
+        };
+        assert_eq!(flush(&mut frame), (Flush::Full, 2));
+
+        // Switches mode once, then keeps it
+        frame.write(0, &[CHAR_1]);
+        frame.write(15, &[CHAR_2]);
+        assert_eq!(flush(&mut frame), (Flush::Fixed, 3));
+        frame.write(3, &[CHAR_3]);
+        frame.write(12, &[CHAR_4]);
+        assert_eq!(flush(&mut frame), (Flush::Fixed, 2));
+
+        frame.write(4, &[CHAR_1; 8]);
+        assert_eq!(flush(&mut frame), (Flush::Run, 2));
+    }
+
+    #[test]
+    fn flush_error_test() {
+        let mut frame = FrameBuffer::<4>::new();
+        let res = frame.flush(|_| -> Result<(), TmError<()>> { Err(TmError::Clk(())) });
+        assert!(matches!(res, Err(TmError::Clk(()))));
+        assert!(frame.is_dirty());
+        assert_eq!(frame.plan(), Flush::Full);
+    }
+}
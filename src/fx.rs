@@ -1,18 +1,20 @@
 //! Set of very simple effects for LED displays.
-//! 
-//! **Depens on "galloc"** feature (global allocator) to be available because of using `Vec`.
+//!
+//! Effects do not need global allocator: they write frames into buffer that you provide,
+//! see [`AnimateFrame`]. With **"galloc"** feature [`Slider`] also returns frames as `Vec`.
 //!
 //! Animations are low level primitives that works next way:
 //!
 //!  1. You have to create animation structure
-//!  1. Now you should treat any animation as `Animate` trait
+//!  1. Now you should treat any animation as `Animate` or `AnimateFrame` trait
 //!  1. Retrieve next `Animate` state in cycle
 //!  1. Feed next animation state to MCUs
 //!  1. Do not forget to provide delays in animation cycle
 //!
 use super::*;
 
-use alloc::vec::Vec;
+#[cfg(feature = "galloc")]
+use alloc::{vec, vec::Vec};
 
 const CIRCLE: [u8; 6] = [SEG_1, SEG_2, SEG_3, SEG_4, SEG_5, SEG_6];
 
@@ -28,6 +30,17 @@ pub trait Animate<R> {
     fn next(&mut self) -> Option<R>;
 }
 
+///
+/// Animation that renders next state into caller provided frame buffer.
+///
+pub trait AnimateFrame {
+    /// Writes next animation frame into the beginning of `frame` and returns written part.
+    /// Frame is truncated if buffer is shorter than animation needs.
+    ///
+    /// If animation completed returns `None`, buffer could be partially overwritten.
+    fn next_frame<'f>(&mut self, frame: &'f mut [u8]) -> Option<&'f [u8]>;
+}
+
 /// Stands for spinning segments in one display round and round.
 /// Spinning path is the same as for ZERO digit.
 #[derive(Debug)]
@@ -43,6 +56,7 @@ impl Spinner {
     /// Arguments:
     ///  - `initial_mask` - consider it as ZERO without one segment or only one segment.
     ///  - `clockwise` - rotation direction true for clockwise
+    #[allow(clippy::needless_range_loop)]
     pub fn new(initial_mask: u8, clockwise: bool) -> Spinner {
         let mut init = [false; 6];
 
@@ -61,6 +75,7 @@ impl Spinner {
 }
 
 impl Animate<u8> for Spinner {
+    #[allow(clippy::needless_range_loop, clippy::needless_return)]
     fn next(&mut self) -> Option<u8> {
         let mut res = 0_u8;
         let max_i = self.mask.len();
@@ -80,6 +95,18 @@ impl Animate<u8> for Spinner {
     }
 }
 
+impl AnimateFrame for Spinner {
+    fn next_frame<'f>(&mut self, frame: &'f mut [u8]) -> Option<&'f [u8]> {
+        let len = frame.len().min(1);
+        let frame = &mut frame[..len];
+        let res = self.next()?;
+        for byte in frame.iter_mut() {
+            *byte = res;
+        }
+        Some(frame)
+    }
+}
+
 /// Defines how to slide your "text"
 #[derive(Debug)]
 pub enum SlideType {
@@ -92,59 +119,46 @@ pub enum SlideType {
 }
 
 /// Sliding animation from last display to first (from right to left).
+///
+/// Bytes to slide are kept in `W`: borrowed slice, array or `Vec`.
+/// Former `Slider::new(.., &bytes)` calls now give `Slider<&[u8]>` and produce frames
+/// with [`AnimateFrame::next_frame`], `Animate<Vec<u8>>` is left only with "galloc".
 #[derive(Debug)]
-pub struct Slider {
+pub struct Slider<W> {
     tp: SlideType,
     count: u8,
     result_len: u8,
-    word: Vec<u8>,
+    word: W,
 }
 
-impl Slider {
+impl<W: AsRef<[u8]>> Slider<W> {
     /// Configure slider animation.
     ///
     /// Arguments:
     ///  - `slide_type` - animation behaviour
     ///  - `displays_count` - number of displays connected to MCU
     ///  - `bytes_to_slide` - input bytes that should slide along displays
-    pub fn new(slide_type: SlideType, displays_count: u8, bytes_to_slide: &[u8]) -> Slider {
-        let mut word = Vec::<u8>::new();
-
-        for i in 0..bytes_to_slide.len() {
-            word.push(bytes_to_slide[i]);
-        }
-
+    pub fn new(slide_type: SlideType, displays_count: u8, bytes_to_slide: W) -> Slider<W> {
         Slider {
             tp: slide_type,
             count: 0,
             result_len: displays_count,
-            word: word,
+            word: bytes_to_slide,
         }
     }
 }
 
-impl Animate<Vec<u8>> for Slider {
-    fn next(&mut self) -> Option<Vec<u8>> {
+impl<W: AsRef<[u8]>> AnimateFrame for Slider<W> {
+    fn next_frame<'f>(&mut self, frame: &'f mut [u8]) -> Option<&'f [u8]> {
         if self.count == 255 {
             // Do not support long words
             return None;
         }
 
-        let mut out = Vec::<u8>::new();
+        let word = self.word.as_ref();
+        // Blank displays before word, negative if first chars are already out of screen
         let off_out = self.result_len as isize - self.count as isize;
-
-        // Add blank offset before word
-        if off_out > 0 {
-            for _ in 0..off_out {
-                out.push(0);
-            }
-        }
-
-        let off_in = if off_out < 0 {
-            (off_out * -1) as usize
-        } else {
-            0
-        };
+        let off_in = if off_out < 0 { -off_out as usize } else { 0 };
 
         if off_in > 0 {
             // Must stop if first char of word goes out of screen
@@ -153,42 +167,42 @@ impl Animate<Vec<u8>> for Slider {
             }
         }
 
-        if off_in >= self.word.len() {
+        if off_in >= word.len() {
             // Stop if last char already out of screen
             if let SlideType::StopAfterLastChar = self.tp {
                 return None;
             }
         }
 
-        if (off_in as usize) < self.word.len() {
-            for i in 0..self.word.len() {
-                if out.len() >= self.result_len as usize {
-                    break;
-                }
-                if i < off_in {
-                    // Skip first chars
-                    continue;
-                }
-                out.push(self.word[i]);
-            }
-        }
-
-        // Add blank offset after word
-        if out.len() < self.result_len as usize {
-            for _ in 0..(self.result_len as usize - out.len()) {
-                out.push(0);
-            }
+        let len = frame.len().min(self.result_len as usize);
+        let frame = &mut frame[..len];
+        for (i, byte) in frame.iter_mut().enumerate() {
+            let pos = i as isize - off_out;
+            *byte = if pos < 0 {
+                0
+            } else {
+                word.get(pos as usize).copied().unwrap_or(0)
+            };
         }
 
         self.count += 1;
-        if off_in + 1 >= self.word.len() {
+        if off_in + 1 >= word.len() {
             // Reset counter if last char out of screen and we do it in cycles
             if let SlideType::Cycle = self.tp {
                 self.count = 0;
             }
         }
 
-        return Some(out);
+        Some(frame)
+    }
+}
+
+#[cfg(feature = "galloc")]
+impl<W: AsRef<[u8]>> Animate<Vec<u8>> for Slider<W> {
+    fn next(&mut self) -> Option<Vec<u8>> {
+        let mut out = vec![0; self.result_len as usize];
+        self.next_frame(&mut out)?;
+        Some(out)
     }
 }
 
@@ -207,6 +221,8 @@ mod tests {
         last
     }
 
+    #[cfg(feature = "galloc")]
+    #[allow(clippy::redundant_pattern_matching, clippy::assertions_on_constants)]
     fn animate_none<R>(ani: &mut dyn Animate<R>, steps: usize) {
         for s in 0..steps {
             if let Some(_) = ani.next() {
@@ -216,6 +232,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::clone_on_copy)]
     fn spinner_test() {
         let init = CHAR_0 & !SEG_1;
 
@@ -233,6 +250,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "galloc")]
     fn slide_test() {
         let init = [
             CHAR_0, CHAR_1, CHAR_2, CHAR_3, CHAR_4, CHAR_5, CHAR_6, CHAR_7, CHAR_8, CHAR_9,
@@ -286,4 +304,27 @@ mod tests {
             [CHAR_5, CHAR_6, CHAR_7, CHAR_8, CHAR_9]
         );
     }
+
+    #[test]
+    fn slide_frame_test() {
+        let init = [CHAR_0, CHAR_1, CHAR_2];
+        let mut frame = [0xFF_u8; 4];
+
+        let mut sstop = Slider::new(SlideType::StopAfterLastChar, 3, &init[..]);
+        assert_eq!(sstop.next_frame(&mut frame).unwrap(), [0; 3]);
+        assert_eq!(frame[3], 0xFF);
+        sstop.next_frame(&mut frame).unwrap();
+        assert_eq!(sstop.next_frame(&mut frame).unwrap(), [0, CHAR_0, CHAR_1]);
+        assert_eq!(
+            sstop.next_frame(&mut frame).unwrap(),
+            [CHAR_0, CHAR_1, CHAR_2]
+        );
+        assert_eq!(sstop.next_frame(&mut frame[..2]).unwrap(), [CHAR_1, CHAR_2]);
+        sstop.next_frame(&mut frame).unwrap();
+        assert_eq!(sstop.next_frame(&mut frame), None);
+
+        let mut spin = Spinner::new(CHAR_0 & !SEG_1, true);
+        assert_eq!(spin.next_frame(&mut frame).unwrap(), [CHAR_0 & !SEG_1]);
+        assert_eq!(spin.next_frame(&mut []), Some(&[][..]));
+    }
 }
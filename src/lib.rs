@@ -11,6 +11,18 @@
 //! You still have to send and receive raw data as bytes.
 //! In order to understand meaning of this bytes you should have some knowledges.
 //!
+//! # High level drivers
+//! For popular chips there are driver structures that own interface pins and do all datasheet sequences for you:
+//!
+//! - [`tm1637::Tm1637`] - TM1637 driver (**cldkio**)
+//! - [`tm1638::Tm1638`] - TM1638 driver (**clkdiostb**)
+//! - [`tm1640::Tm1640`] - TM1640 driver (**cldkio**)
+//! - [`tm1650::Tm1650`] - TM1650 driver, works with pins or I2C peripheral (**cldkio**)
+//! - [`tm1651::Tm1651`] - TM1651 bar graph driver (**cldkio**)
+//! - [`tm1652::Tm1652`] - TM1652 driver, works with pin or serial peripheral (**dio**)
+//! - [`tm16xx::Tm16xx`] - TM1628, TM1668, TM1629 driver (**clkdiostb**)
+//! - [`pt631x`] - PT6311, PT6312 VFD controller profiles for `Tm16xx` driver (**clkdiostb**)
+//!
 //! # Need code examples?
 //! Just explore repository [examples folder](https://github.com/rustrum/tmledkey-hal-drv).
 //!
@@ -23,8 +35,14 @@
 //!
 //! - **cldkio** - functions to work with 2 wire interfaces
 //! - **clkdiostb** - functions to work with 3 wire interfaces
+//! - **dio** - functions to work with 1 wire UART like interfaces (TM1652)
+//! - **eh1** - `eh1` functions for embedded-hal 1.0 pins and `DelayNs`
+//! - **async** - `asynch` functions and drivers with embedded-hal-async `DelayNs` (enables "eh1")
+//! - **sim** - `sim` virtual chip to test your code on host without hardware, `recorder` for bus waveforms,
+//!   `decoder` for captured traces
 //! - **keys** - key scan support
-//! - **fx** - tiny effects api (depends on "galloc")
+//! - **defmt** - `defmt::Format` for [`TmError`]
+//! - **fx** - tiny effects api, works without allocator (`Vec` frames with "galloc")
 //! - **galloc** - functionality that require to have global allocator in your application
 //!
 //! You should look into Cargo.toml in [source code](https://github.com/rustrum/tmledkey-hal-drv)
@@ -40,9 +58,13 @@
 //! It is up to you what kind of delay approach you will use.
 //! Just keep in mind that delays have to be precise.
 //!
-//! Some functions accepts delay value as input parameter.
+//! Bus functions accept [`BusTiming`] with separate delay for every bus phase.
 //! It is kinda weird, but it would allow you to reduce bus communication delays if your circuit configuration allows you to.
-//! In other cases you can use pre defined delay values.
+//! In other cases you can use chip presets like [`TM1637_TIMING`] or plain `u16` delay value for all phases.
+//! Smallest reliable timing for your board could be found with `calibrate` module.
+//!
+//! Delay is anything that implements [`BusDelay`], every `FnMut(u16)` closure does.
+//! With **eh1** feature you could pass `DelayNs` implementation instead, see `eh1` module.
 //!
 #![no_std]
 #![allow(non_upper_case_globals)]
@@ -57,313 +79,624 @@ pub mod fx;
 #[cfg(feature = "demo")]
 pub mod demo;
 
-use embedded_hal::digital::v2::{InputPin, OutputPin};
+#[cfg(feature = "clkdio")]
+pub mod tm1637;
+
+#[cfg(feature = "clkdio")]
+pub mod tm1640;
+
+#[cfg(feature = "clkdio")]
+pub mod tm1650;
+
+#[cfg(feature = "clkdio")]
+pub mod tm1651;
+
+#[cfg(feature = "dio")]
+pub mod tm1652;
+
+#[cfg(feature = "clkdiostb")]
+pub mod tm1638;
+
+#[cfg(feature = "clkdiostb")]
+pub mod tm16xx;
+
+#[cfg(feature = "clkdiostb")]
+pub mod pt631x;
+
+#[cfg(feature = "clkdiostb")]
+pub mod spi;
+
+#[cfg(any(feature = "clkdio", feature = "clkdiostb"))]
+pub mod pins;
+
+#[cfg(any(feature = "clkdio", feature = "clkdiostb"))]
+pub mod shared;
+
+#[cfg(any(feature = "clkdio", feature = "clkdiostb"))]
+pub mod frame;
+
+#[cfg(any(feature = "clkdio", all(feature = "clkdiostb", feature = "keys")))]
+pub mod calibrate;
+
+#[cfg(any(feature = "clkdio", feature = "clkdiostb"))]
+mod bus;
 
-#[cfg(not(any(feature = "clkdio", feature = "clkdiostb")))]
-compile_error!("Either feature \"clkdio\" or \"clkdiostb\" must be enabled for this crate. Otherwise there is no reason to use it");
+#[cfg(feature = "eh1")]
+pub mod eh1;
+
+#[cfg(all(feature = "async", any(feature = "clkdio", feature = "clkdiostb")))]
+pub mod asynch;
+
+#[cfg(any(test, feature = "sim"))]
+pub mod sim;
+
+#[cfg(any(test, feature = "sim"))]
+pub mod recorder;
+
+#[cfg(any(test, feature = "sim"))]
+pub mod decoder;
+
+use core::fmt;
+#[cfg(any(feature = "clkdio", feature = "clkdiostb"))]
+use embedded_hal::digital::v2::InputPin;
+use embedded_hal::digital::v2::OutputPin;
+
+#[cfg(not(any(feature = "clkdio", feature = "clkdiostb", feature = "dio")))]
+compile_error!("Either feature \"clkdio\", \"clkdiostb\" or \"dio\" must be enabled for this crate. Otherwise there is no reason to use it");
 
 /// Describes possible error mostly related to low level interaction with MCU.
 /// At least it should give you an insight about what goes wrong.
+///
+/// `E` is an error type of your DIO pin (or peripheral), `C` and `S` are error types of CLK and STB pins.
+/// They are the same by default, so pins from one HAL port give plain `TmError<E>`.
+/// Original error is kept inside.
 #[derive(Debug)]
-pub enum TmError {
-    Dio,
-    /// ACK error with tricky code.
-    /// Code could help to get more detailed information about exact place where error occurred.
-    Ack(u8),
-    Clk,
-    Stb,
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TmError<E, C = E, S = E> {
+    Dio(E),
+    /// Chip did not acknowledge byte with given index (0 is a command byte).
+    /// For [`AckPhase::Stop`] index is a number of bytes transferred.
+    /// `line` is an index of DIO line that failed when several chips are driven in parallel,
+    /// see `shared::SharedClkBus::send_parallel`, otherwise it is always 0.
+    Ack {
+        byte: usize,
+        phase: AckPhase,
+        line: usize,
+    },
+    Clk(C),
+    Stb(S),
+    /// Peripheral (I2C, serial, etc.) transfer error
+    Bus(E),
     /// There was some errors in user input
     Input,
 }
 
-/// Expecting to have delay after start sequence or previous send call
-#[inline]
-fn tm_bus_send<DIO, CLK, D>(
-    dio: &mut DIO,
-    clk: &mut CLK,
-    delay_us: &mut D,
-    bus_delay_us: u16,
-    mut byte: u8,
-) -> Result<(), TmError>
+/// Step of 2 wire ACK sequence where chip did not behave as expected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AckPhase {
+    /// DIO was not pulled down after 8th clock falling edge
+    Falling8,
+    /// DIO was not kept low at 9th clock rising edge
+    Rising9,
+    /// DIO was not released after 9th clock falling edge
+    Release,
+    /// DIO was not released after stop sequence
+    Stop,
+}
+
+impl fmt::Display for AckPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AckPhase::Falling8 => "8th falling edge",
+            AckPhase::Rising9 => "9th rising edge",
+            AckPhase::Release => "DIO release",
+            AckPhase::Stop => "stop",
+        })
+    }
+}
+
+impl<E, C, S> fmt::Display for TmError<E, C, S>
 where
-    DIO: InputPin + OutputPin,
-    CLK: OutputPin,
-    D: FnMut(u16) -> (),
+    E: fmt::Debug,
+    C: fmt::Debug,
+    S: fmt::Debug,
 {
-    for _ in 0..8 {
-        clk.set_low().map_err(|_| TmError::Clk)?;
-        // This delay can be skipped, but data transfer become unstable
-        delay_us(bus_delay_us);
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TmError::Dio(e) => write!(f, "DIO pin error: {:?}", e),
+            TmError::Ack { byte, phase, line } => {
+                write!(f, "no ACK for byte {} at {}", byte, phase)?;
+                if *line > 0 {
+                    write!(f, " on DIO line {}", line)?;
+                }
+                Ok(())
+            }
+            TmError::Clk(e) => write!(f, "CLK pin error: {:?}", e),
+            TmError::Stb(e) => write!(f, "STB pin error: {:?}", e),
+            TmError::Bus(e) => write!(f, "bus error: {:?}", e),
+            TmError::Input => write!(f, "invalid input"),
+        }
+    }
+}
 
-        let high = byte & 0b1 != 0;
-        byte = byte >> 1;
+/// Delays in us for every phase of 2 and 3 wire bus.
+///
+/// Bus functions accept anything that converts into timing,
+/// plain `u16` delay value is used for every phase like it was before.
+/// Usually you would start from chip preset like [`TM1637_TIMING`] and tune it for your circuit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BusTiming {
+    /// CLK low before DIO is changed
+    pub clk_low_us: u16,
+    /// CLK high after rising edge
+    pub clk_high_us: u16,
+    /// DIO set up before CLK rising edge
+    pub data_setup_us: u16,
+    /// Wait for chip to pull down or release DIO (ACK, key scan data)
+    pub ack_wait_us: u16,
+    /// Start and stop sequence hold time
+    pub start_stop_us: u16,
+    /// STB hold time before and after transfer
+    pub stb_hold_us: u16,
+}
+
+impl BusTiming {
+    /// Same delay for every phase.
+    pub const fn uniform(delay_us: u16) -> BusTiming {
+        BusTiming {
+            clk_low_us: delay_us,
+            clk_high_us: delay_us,
+            data_setup_us: delay_us,
+            ack_wait_us: delay_us,
+            start_stop_us: delay_us,
+            stb_hold_us: delay_us,
+        }
+    }
+}
+
+impl From<u16> for BusTiming {
+    fn from(delay_us: u16) -> Self {
+        BusTiming::uniform(delay_us)
+    }
+}
+
+/// Delay in us between bus phases.
+///
+/// Implemented for every `FnMut(u16)` closure, with **eh1** feature use `eh1::Delay` to pass `DelayNs`.
+pub trait BusDelay {
+    /// Blocks for given number of microseconds.
+    fn delay_us(&mut self, us: u16);
+}
+
+impl<F: FnMut(u16)> BusDelay for F {
+    #[inline]
+    fn delay_us(&mut self, us: u16) {
+        self(us)
+    }
+}
+
+/// Data line that is only written, like TM1640 DIN.
+///
+/// Implemented for every `OutputPin`, see [`pins`] for other options.
+#[cfg(any(feature = "clkdio", feature = "clkdiostb"))]
+pub trait DioOut {
+    /// Error type of underlying pins
+    type Error;
+
+    /// Drives line low or releases it high.
+    fn set_dio(&mut self, high: bool) -> Result<(), Self::Error>;
+}
+
+/// Bidirectional data line, chip drives it for ACK and key scan data.
+///
+/// Implemented for every pin that is both `InputPin` and `OutputPin` with the same error type,
+/// use [`pins::SplitDio`] for separate output and input pins.
+#[cfg(any(feature = "clkdio", feature = "clkdiostb"))]
+pub trait Dio: DioOut {
+    /// Reads actual line level.
+    fn is_dio_high(&mut self) -> Result<bool, Self::Error>;
+}
+
+#[cfg(any(feature = "clkdio", feature = "clkdiostb"))]
+impl<P: OutputPin> DioOut for P {
+    type Error = P::Error;
+
+    #[inline]
+    fn set_dio(&mut self, high: bool) -> Result<(), P::Error> {
         if high {
-            dio.set_high().map_err(|_| TmError::Dio)?;
+            self.set_high()
         } else {
-            dio.set_low().map_err(|_| TmError::Dio)?;
+            self.set_low()
         }
-        delay_us(bus_delay_us);
+    }
+}
 
-        clk.set_high().map_err(|_| TmError::Clk)?;
-        delay_us(bus_delay_us);
+#[cfg(any(feature = "clkdio", feature = "clkdiostb"))]
+impl<P> Dio for P
+where
+    P: OutputPin + InputPin<Error = <P as OutputPin>::Error>,
+{
+    #[inline]
+    fn is_dio_high(&mut self) -> Result<bool, Self::Error> {
+        self.is_high()
     }
-    Ok(())
 }
 
-/// Expecting to have delay after start sequence or previous send call
-#[inline]
-#[cfg(feature = "keys")]
-fn tm_bus_read<DIO, CLK, D>(
+/// Runs 2 wire transfer sequence on blocking pins.
+#[cfg(feature = "clkdio")]
+fn tm_bus_2wire_run<DIO, CLK, D, E, EC>(
     dio: &mut DIO,
     clk: &mut CLK,
     delay_us: &mut D,
-    bus_delay_us: u16,
-) -> Result<u8, TmError>
+    seq: &mut bus::Sequence,
+) -> Result<(), TmError<E, EC>>
 where
-    DIO: InputPin + OutputPin,
-    CLK: OutputPin,
-    D: FnMut(u16) -> (),
+    DIO: Dio<Error = E>,
+    CLK: OutputPin<Error = EC>,
+    D: BusDelay,
 {
-    let mut byte = 0;
-    for i in 0..8 {
-        clk.set_low().map_err(|_| TmError::Clk)?;
-        delay_us(bus_delay_us);
-        // Looks like MCU changes dio at low CLK
-        clk.set_high().map_err(|_| TmError::Clk)?;
-        if dio.is_high().map_err(|_| TmError::Dio)? {
-            byte = byte | 0x80 >> i;
-        }
-        delay_us(bus_delay_us);
-    }
-    Ok(byte)
+    bus::run(&mut bus::Bus2 { dio, clk }, delay_us, seq)
 }
 
+/// Send one or several bytes to MCU via 2 wire interface (DIO,CLK).
+///
+/// According to datasheet it can be single command byte or a sequence starting with command byte followed by several data bytes.
+///
+/// Arguments:
+///  - `dio`, `clk` - MCU interface pins
+///  - `delay_us` - closure that provides delay functionality
+///  - `timing` - bus timing or single delay value in us, see [`TM1637_TIMING`]
+///  - `bytes` - slice of bytes to send
+#[inline]
 #[cfg(feature = "clkdio")]
-fn tm_bus_dio_wait_ack<DIO, D>(
+pub fn tm_send_bytes_2wire<DIO, CLK, D, T, E, EC>(
     dio: &mut DIO,
+    clk: &mut CLK,
     delay_us: &mut D,
-    bus_delay_us: u16,
-    expect_high: bool,
-    err: u8,
-) -> Result<(), TmError>
+    timing: T,
+    bytes: &[u8],
+) -> Result<(), TmError<E, EC>>
 where
-    DIO: InputPin + OutputPin,
-    D: FnMut(u16) -> (),
+    DIO: Dio<Error = E>,
+    CLK: OutputPin<Error = EC>,
+    D: BusDelay,
+    T: Into<BusTiming>,
 {
-    for _ in 0..5 {
-        if expect_high == dio.is_high().map_err(|_| TmError::Dio)? {
-            return Ok(());
-        }
-        delay_us(bus_delay_us);
-    }
-
-    Err(TmError::Ack(err))
+    let timing = timing.into();
+    tm_bus_2wire_send_bytes(dio, clk, delay_us, &timing, bytes, TM_ACK_POLLS)
 }
 
-/// Expecting to have initial state on bus CLK is UP and DIO is UP.
-#[inline]
 #[cfg(feature = "clkdio")]
-fn tm_bus_2wire_start<DIO, CLK, D>(
+fn tm_bus_2wire_send_bytes<DIO, CLK, D, E, EC>(
     dio: &mut DIO,
     clk: &mut CLK,
     delay_us: &mut D,
-    bus_delay_us: u16,
-) -> Result<(), TmError>
+    timing: &BusTiming,
+    bytes: &[u8],
+    ack_polls: u8,
+) -> Result<(), TmError<E, EC>>
 where
-    DIO: InputPin + OutputPin,
-    CLK: OutputPin,
-    D: FnMut(u16) -> (),
+    DIO: Dio<Error = E>,
+    CLK: OutputPin<Error = EC>,
+    D: BusDelay,
 {
-    clk.set_high().map_err(|_| TmError::Clk)?;
-    dio.set_low().map_err(|_| TmError::Dio)?;
-    delay_us(bus_delay_us);
-    Ok(())
+    let data = [bytes];
+    let mut seq = bus::Sequence::write_2wire(timing, ack_polls, &data).ok_or(TmError::Input)?;
+    tm_bus_2wire_run(dio, clk, delay_us, &mut seq)
 }
 
+/// Send one or several bytes to MCU via 2 wire interface (DIO,CLK) without waiting for ACK.
+///
+/// Some chips like TM1640 have no ACK and no key scan, thus DIO is used only as output here.
+/// According to datasheet it can be single command byte or a sequence starting with command byte followed by several data bytes.
+///
+/// Arguments:
+///  - `dio`, `clk` - MCU interface pins
+///  - `delay_us` - closure that provides delay functionality
+///  - `timing` - bus timing or single delay value in us, see [`TM1640_TIMING`]
+///  - `bytes` - slice of bytes to send
 #[inline]
 #[cfg(feature = "clkdio")]
-fn tm_bus_2wire_stop<DIO, CLK, D>(
+pub fn tm_send_bytes_2wire_noack<DIO, CLK, D, T, E, EC>(
     dio: &mut DIO,
     clk: &mut CLK,
     delay_us: &mut D,
-    bus_delay_us: u16,
-) -> Result<(), TmError>
+    timing: T,
+    bytes: &[u8],
+) -> Result<(), TmError<E, EC>>
 where
-    DIO: InputPin + OutputPin,
-    CLK: OutputPin,
-    D: FnMut(u16) -> (),
+    DIO: DioOut<Error = E>,
+    CLK: OutputPin<Error = EC>,
+    D: BusDelay,
+    T: Into<BusTiming>,
 {
-    dio.set_low().map_err(|_| TmError::Dio)?;
-    delay_us(bus_delay_us);
-
-    clk.set_high().map_err(|_| TmError::Clk)?;
-    delay_us(bus_delay_us);
-
-    dio.set_high().map_err(|_| TmError::Dio)?;
-    tm_bus_dio_wait_ack(dio, delay_us, bus_delay_us, true, 255)?;
-    delay_us(bus_delay_us);
-    Ok(())
+    let timing = timing.into();
+    let data = [bytes];
+    let mut seq = bus::Sequence::write_2wire_noack(&timing, &data).ok_or(TmError::Input)?;
+    tm_bus_2wire_run(&mut bus::WriteOnly(dio), clk, delay_us, &mut seq)
 }
 
-/// Should be called right after send
-#[cfg(feature = "clkdio")]
-fn tm_bus_2wire_ack<DIO, CLK, D>(
+/// Reads key scan data as byte via 2 wire interface (DIO,CLK).
+///
+/// Arguments:
+///  - `dio`, `clk` - MCU interface pins
+///  - `delay_us` - closure that provides delay functionality
+///  - `timing` - bus timing or single delay value in us, see [`TM1637_TIMING`]
+#[inline]
+#[cfg(all(feature = "keys", feature = "clkdio"))]
+pub fn tm_read_byte_2wire<DIO, CLK, D, T, E, EC>(
     dio: &mut DIO,
     clk: &mut CLK,
     delay_us: &mut D,
-    bus_delay_us: u16,
-    err_code: u8,
-    verify_last: bool,
-) -> Result<(), TmError>
+    timing: T,
+) -> Result<u8, TmError<E, EC>>
 where
-    DIO: InputPin + OutputPin,
-    CLK: OutputPin,
-    D: FnMut(u16) -> (),
+    DIO: Dio<Error = E>,
+    CLK: OutputPin<Error = EC>,
+    D: BusDelay,
+    T: Into<BusTiming>,
 {
-    // 8th cycle falling edge
-    dio.set_high().map_err(|_| TmError::Dio)?;
-    clk.set_low().map_err(|_| TmError::Clk)?;
-    delay_us(bus_delay_us);
-
-    // Ensure that DIO was pulled down at 8th cycle falling edge
-    tm_bus_dio_wait_ack(dio, delay_us, bus_delay_us, false, err_code + 1)?;
-
-    // 9th cycle rising edge
-    clk.set_high().map_err(|_| TmError::Clk)?;
-    delay_us(bus_delay_us);
-
-    // Ensure DIO still low at 9th cycle rising edge
-    tm_bus_dio_wait_ack(dio, delay_us, bus_delay_us, false, err_code + 2)?;
-
-    // 9th cycle falling edge
-    clk.set_low().map_err(|_| TmError::Clk)?;
-    delay_us(bus_delay_us);
-
-    // Ensure DIO was released and now it is up
-    if verify_last {
-        // No need to check last ACK for reading mode
-        tm_bus_dio_wait_ack(dio, delay_us, bus_delay_us, true, err_code + 3)?;
-    }
-
-    Ok(())
+    let timing = timing.into();
+    let mut response = [0_u8; TM1637_RESPONSE_SIZE as usize];
+    tm_bus_2wire_read_bytes(dio, clk, delay_us, &timing, &mut response, TM_ACK_POLLS)?;
+    Ok(response[0])
 }
 
+/// Reads key scan data into response slice via 2 wire interface (DIO,CLK).
+///
+/// Slice length is the number of bytes to read, see [`TM1637_RESPONSE_SIZE`].
+/// Bytes are received MSB first like [`tm_read_byte_2wire`] does.
+///
+/// Arguments:
+///  - `dio`, `clk` - MCU interface pins
+///  - `delay_us` - closure that provides delay functionality
+///  - `timing` - bus timing or single delay value in us, see [`TM1637_TIMING`]
+///  - `response` - buffer for received bytes
 #[inline]
-#[cfg(feature = "clkdio")]
-fn tm_bus_2wire_send_byte_ack<DIO, CLK, D>(
+#[cfg(all(feature = "keys", feature = "clkdio"))]
+pub fn tm_read_bytes_into_2wire<DIO, CLK, D, T, E, EC>(
     dio: &mut DIO,
     clk: &mut CLK,
     delay_us: &mut D,
-    bus_delay_us: u16,
-    byte: u8,
-    err_code: u8,
-) -> Result<(), TmError>
+    timing: T,
+    response: &mut [u8],
+) -> Result<(), TmError<E, EC>>
 where
-    DIO: InputPin + OutputPin,
-    CLK: OutputPin,
-    D: FnMut(u16) -> (),
+    DIO: Dio<Error = E>,
+    CLK: OutputPin<Error = EC>,
+    D: BusDelay,
+    T: Into<BusTiming>,
 {
-    tm_bus_send(dio, clk, delay_us, bus_delay_us, byte)?;
-    let verify_last = byte & COM_DATA_READ != COM_DATA_READ;
-    tm_bus_2wire_ack(dio, clk, delay_us, bus_delay_us, err_code, verify_last)
+    let timing = timing.into();
+    tm_bus_2wire_read_bytes(dio, clk, delay_us, &timing, response, TM_ACK_POLLS)
 }
 
-#[inline]
 #[cfg(all(feature = "keys", feature = "clkdio"))]
-fn tm_bus_2wire_read_byte_ack<DIO, CLK, D>(
+fn tm_bus_2wire_read_bytes<DIO, CLK, D, E, EC>(
     dio: &mut DIO,
     clk: &mut CLK,
     delay_us: &mut D,
-    bus_delay_us: u16,
-    err_code: u8,
-) -> Result<u8, TmError>
+    timing: &BusTiming,
+    response: &mut [u8],
+    ack_polls: u8,
+) -> Result<(), TmError<E, EC>>
 where
-    DIO: InputPin + OutputPin,
-    CLK: OutputPin,
-    D: FnMut(u16) -> (),
+    DIO: Dio<Error = E>,
+    CLK: OutputPin<Error = EC>,
+    D: BusDelay,
 {
-    let result = tm_bus_read(dio, clk, delay_us, bus_delay_us);
-    tm_bus_2wire_ack(dio, clk, delay_us, bus_delay_us, err_code, true)?;
-    result
+    let command: [&[u8]; 1] = [&[COM_DATA_READ]];
+    let mut seq =
+        bus::Sequence::read_2wire(timing, ack_polls, &command, response).ok_or(TmError::Input)?;
+    tm_bus_2wire_run(dio, clk, delay_us, &mut seq)
 }
 
-/// Send one or several bytes to MCU via 2 wire interface (DIO,CLK).
+/// Retry policy for 2 wire interface transfers, see [`tm_send_bytes_2wire_retry`].
 ///
-/// According to datasheet it can be single command byte or a sequence starting with command byte followed by several data bytes.
+/// Default policy makes exactly one attempt, same as [`tm_send_bytes_2wire`] does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg(feature = "clkdio")]
+pub struct RetryPolicy {
+    /// Total number of attempts including the first one
+    pub max_attempts: u8,
+    /// Delay in us before the 2nd attempt, each next attempt waits one more `backoff_us`
+    pub backoff_us: u16,
+    /// Number of CLK pulses followed by stop sequence to release a stuck chip, 0 disables recovery
+    pub recovery_pulses: u8,
+    /// How many times DIO is checked at every ACK phase before giving up
+    pub ack_polls: u8,
+}
+
+#[cfg(feature = "clkdio")]
+impl RetryPolicy {
+    /// Creates policy with given number of attempts and 9 recovery pulses (like I2C bus clear).
+    pub fn new(max_attempts: u8, backoff_us: u16) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            backoff_us,
+            recovery_pulses: 9,
+            ack_polls: TM_ACK_POLLS,
+        }
+    }
+}
+
+#[cfg(feature = "clkdio")]
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            backoff_us: 0,
+            recovery_pulses: 0,
+            ack_polls: TM_ACK_POLLS,
+        }
+    }
+}
+
+/// Repeats `transfer` on ACK errors according to policy, pin errors are returned right away.
+#[cfg(feature = "clkdio")]
+fn tm_bus_2wire_retry<DIO, CLK, D, E, EC, R, F>(
+    dio: &mut DIO,
+    clk: &mut CLK,
+    delay_us: &mut D,
+    timing: &BusTiming,
+    policy: &RetryPolicy,
+    failed_attempts: &mut u32,
+    mut transfer: F,
+) -> Result<R, TmError<E, EC>>
+where
+    DIO: Dio<Error = E>,
+    CLK: OutputPin<Error = EC>,
+    D: BusDelay,
+    F: FnMut(&mut DIO, &mut CLK, &mut D) -> Result<R, TmError<E, EC>>,
+{
+    bus::retry(
+        &mut bus::Bus2 { dio, clk },
+        delay_us,
+        timing,
+        policy,
+        failed_attempts,
+        |lines, delay_us| transfer(lines.dio, lines.clk, delay_us),
+    )
+}
+
+/// Same as [`tm_send_bytes_2wire`] but repeats transfer when chip does not respond with ACK.
 ///
 /// Arguments:
 ///  - `dio`, `clk` - MCU interface pins
 ///  - `delay_us` - closure that provides delay functionality
-///  - `delay_value` - delay value in us, depends of MCU you are using and circuit features
+///  - `timing` - bus timing or single delay value in us
 ///  - `bytes` - slice of bytes to send
-#[inline]
+///  - `policy` - number of attempts, backoff and recovery settings
+///  - `failed_attempts` - incremented on every attempt that failed because of ACK, useful to monitor link quality
 #[cfg(feature = "clkdio")]
-pub fn tm_send_bytes_2wire<DIO, CLK, D>(
+pub fn tm_send_bytes_2wire_retry<DIO, CLK, D, T, E, EC>(
     dio: &mut DIO,
     clk: &mut CLK,
     delay_us: &mut D,
-    delay_value: u16,
+    timing: T,
     bytes: &[u8],
-) -> Result<(), TmError>
+    policy: &RetryPolicy,
+    failed_attempts: &mut u32,
+) -> Result<(), TmError<E, EC>>
 where
-    DIO: InputPin + OutputPin,
-    CLK: OutputPin,
-    D: FnMut(u16) -> (),
+    DIO: Dio<Error = E>,
+    CLK: OutputPin<Error = EC>,
+    D: BusDelay,
+    T: Into<BusTiming>,
 {
-    tm_bus_2wire_start(dio, clk, delay_us, delay_value)?;
-
-    let mut send = Err(TmError::Input);
-    let mut iter = 10;
-    for bt in bytes {
-        send = tm_bus_2wire_send_byte_ack(dio, clk, delay_us, delay_value, bt.clone(), iter);
-        if send.is_err() {
-            break;
-        }
-        iter += 10;
-    }
-
-    let stop = tm_bus_2wire_stop(dio, clk, delay_us, delay_value);
-    if send.is_err() {
-        send
-    } else {
-        stop
-    }
+    let timing = timing.into();
+    tm_bus_2wire_retry(
+        dio,
+        clk,
+        delay_us,
+        &timing,
+        policy,
+        failed_attempts,
+        |dio, clk, delay_us| {
+            tm_bus_2wire_send_bytes(dio, clk, delay_us, &timing, bytes, policy.ack_polls)
+        },
+    )
 }
 
-/// Reads key scan data as byte via 2 wire interface (DIO,CLK).
+/// Same as [`tm_read_byte_2wire`] but repeats transfer when chip does not respond with ACK.
 ///
 /// Arguments:
 ///  - `dio`, `clk` - MCU interface pins
 ///  - `delay_us` - closure that provides delay functionality
-///  - `delay_value` - delay value in us, depends of MCU you are using and circuit features
-#[inline]
+///  - `timing` - bus timing or single delay value in us
+///  - `policy` - number of attempts, backoff and recovery settings
+///  - `failed_attempts` - incremented on every attempt that failed because of ACK
 #[cfg(all(feature = "keys", feature = "clkdio"))]
-pub fn tm_read_byte_2wire<DIO, CLK, D>(
+pub fn tm_read_byte_2wire_retry<DIO, CLK, D, T, E, EC>(
     dio: &mut DIO,
     clk: &mut CLK,
     delay_us: &mut D,
-    delay_value: u16,
-) -> Result<u8, TmError>
+    timing: T,
+    policy: &RetryPolicy,
+    failed_attempts: &mut u32,
+) -> Result<u8, TmError<E, EC>>
 where
-    DIO: InputPin + OutputPin,
-    CLK: OutputPin,
-    D: FnMut(u16) -> (),
+    DIO: Dio<Error = E>,
+    CLK: OutputPin<Error = EC>,
+    D: BusDelay,
+    T: Into<BusTiming>,
 {
-    tm_bus_2wire_start(dio, clk, delay_us, delay_value)?;
+    let timing = timing.into();
+    tm_bus_2wire_retry(
+        dio,
+        clk,
+        delay_us,
+        &timing,
+        policy,
+        failed_attempts,
+        |dio, clk, delay_us| {
+            let mut response = [0_u8; TM1637_RESPONSE_SIZE as usize];
+            let ack_polls = policy.ack_polls;
+            tm_bus_2wire_read_bytes(dio, clk, delay_us, &timing, &mut response, ack_polls)?;
+            Ok(response[0])
+        },
+    )
+}
 
-    tm_bus_2wire_send_byte_ack(dio, clk, delay_us, delay_value, COM_DATA_READ, 230)?;
+/// Result of chip presence check, see [`tm_probe_2wire`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg(any(feature = "clkdio", all(feature = "keys", feature = "clkdiostb")))]
+pub enum Probe {
+    /// Nothing responds, DIO is not pulled up or chip returns no data
+    Absent,
+    /// Chip with ACK on 2 wire bus like TM1637 or TM1651
+    TwoWireAck,
+    /// DIO is pulled up but nobody acknowledges, expecting chip without ACK like TM1640
+    TwoWireNoAck,
+    /// Chip on 3 wire bus responds to read key command (TM1638, TM1628 etc.)
+    ThreeWire,
+}
 
-    let read = tm_bus_2wire_read_byte_ack(dio, clk, delay_us, delay_value, 240);
+/// Checks if there is any chip connected to 2 wire interface (DIO,CLK).
+///
+/// Released DIO have to be pulled up, otherwise chip is reported as [`Probe::Absent`].
+/// Then harmless data command is sent and ACK tells TM1637 (TM1651) from TM1640.
+/// Keep in mind that chip without ACK looks exactly like pulled up bus without any chip,
+/// thus [`Probe::TwoWireNoAck`] could also mean that display is missing and MCU pull-up is enabled.
+///
+/// Arguments:
+///  - `dio`, `clk` - MCU interface pins
+///  - `delay_us` - closure that provides delay functionality
+///  - `timing` - bus timing or single delay value in us, see [`TM1637_TIMING`]
+#[cfg(feature = "clkdio")]
+pub fn tm_probe_2wire<DIO, CLK, D, T, E, EC>(
+    dio: &mut DIO,
+    clk: &mut CLK,
+    delay_us: &mut D,
+    timing: T,
+) -> Result<Probe, TmError<E, EC>>
+where
+    DIO: Dio<Error = E>,
+    CLK: OutputPin<Error = EC>,
+    D: BusDelay,
+    T: Into<BusTiming>,
+{
+    let timing = timing.into();
 
-    let stop = tm_bus_2wire_stop(dio, clk, delay_us, delay_value);
-    if stop.is_err() {
-        if read.is_err() {
-            return read;
-        } else {
-            return Err(stop.err().unwrap());
+    let mut idle = bus::Sequence::idle(&timing, TM_ACK_POLLS);
+    match tm_bus_2wire_run(dio, clk, delay_us, &mut idle) {
+        Ok(()) => {}
+        Err(TmError::Ack { .. }) => return Ok(Probe::Absent),
+        Err(e) => return Err(e),
+    }
+
+    let command = [COM_DATA_ADDRESS_ADD];
+    match tm_bus_2wire_send_bytes(dio, clk, delay_us, &timing, &command, TM_ACK_POLLS) {
+        Ok(()) => Ok(Probe::TwoWireAck),
+        Err(TmError::Ack { .. }) => {
+            // 9th clock pulse was not expected, repeat command to leave chip in known state
+            tm_send_bytes_2wire_noack(dio, clk, delay_us, timing, &command)?;
+            Ok(Probe::TwoWireNoAck)
         }
+        Err(e) => Err(e),
     }
-    read
 }
 
 /// Send bytes using 3 wire interface (DIO,CLK,STB).
@@ -377,110 +710,279 @@ where
 /// Arguments:
 ///  - `dio`, `clk`, `stb` - MCU interface pins
 ///  - `delay_us` - closure that provides delay functionality
-///  - `delay_value` - delay value in us, depends of MCU you are using and your features
+///  - `timing` - bus timing or single delay value in us, see [`TM1638_TIMING`]
 ///  - `bytes` - slice of bytes to send
 #[inline]
 #[cfg(feature = "clkdiostb")]
-pub fn tm_send_bytes_3wire<DIO, CLK, STB, D>(
+pub fn tm_send_bytes_3wire<DIO, CLK, STB, D, T, E, EC, ES>(
     dio: &mut DIO,
     clk: &mut CLK,
     stb: &mut STB,
     delay_us: &mut D,
-    delay_value: u16,
+    timing: T,
     bytes: &[u8],
-) -> Result<(), TmError>
+) -> Result<(), TmError<E, EC, ES>>
 where
-    DIO: InputPin + OutputPin,
-    CLK: OutputPin,
-    STB: OutputPin,
-    D: FnMut(u16) -> (),
+    DIO: Dio<Error = E>,
+    CLK: OutputPin<Error = EC>,
+    STB: OutputPin<Error = ES>,
+    D: BusDelay,
+    T: Into<BusTiming>,
 {
-    delay_us(delay_value);
-    stb.set_low().map_err(|_| TmError::Stb)?;
-    delay_us(delay_value);
+    let timing = timing.into();
+    let data = [bytes];
+    let mut seq = bus::Sequence::write_3wire(&timing, &data).ok_or(TmError::Input)?;
+    bus::run(&mut bus::Bus3 { dio, clk, stb }, delay_us, &mut seq)
+}
 
-    let mut send = Err(TmError::Input);
-    for bt in bytes {
-        send = tm_bus_send(dio, clk, delay_us, delay_value, bt.clone());
-        if send.is_err() {
-            break;
-        }
-        // Notice: When read data, set instruction from the 8th rising edge of clock
-        // to CLK falling edge to read data that demand a waiting time T wait(min 1μS).
-        //delayer();
-    }
-    delay_us(delay_value);
-    stb.set_high().map_err(|_| TmError::Stb)?;
-    clk.set_high().map_err(|_| TmError::Stb)?;
-    dio.set_high().map_err(|_| TmError::Stb)?;
-    send
+/// Sends read command and fills whole response slice with bytes from MCU using 3 wire interface.
+#[inline]
+#[cfg(all(feature = "keys", feature = "clkdiostb"))]
+fn tm_bus_3wire_read<DIO, CLK, STB, D, E, EC, ES>(
+    dio: &mut DIO,
+    clk: &mut CLK,
+    stb: &mut STB,
+    delay_us: &mut D,
+    timing: &BusTiming,
+    command: u8,
+    response: &mut [u8],
+) -> Result<(), TmError<E, EC, ES>>
+where
+    DIO: Dio<Error = E>,
+    CLK: OutputPin<Error = EC>,
+    STB: OutputPin<Error = ES>,
+    D: BusDelay,
+{
+    let command = [command];
+    let command = [&command[..]];
+    let mut seq = bus::Sequence::read_3wire(timing, &command, response).ok_or(TmError::Input)?;
+    bus::run(&mut bus::Bus3 { dio, clk, stb }, delay_us, &mut seq)
 }
 
 /// Read **read_count** of bytes into response array from MCU using 3 wire interface (DIO,CLK,STB).
 ///
 /// Response array has fixed size of 4, so you can read up to 4 bytes there.
+/// Use [`tm_read_bytes_into_3wire`] for chips with longer response like TM1668.
 ///
 /// Arguments:
 ///  - `dio`, `clk`, `stb` - MCU interface pins
 ///  - `delay_us` - closure that provides delay functionality
-///  - `delay_value` - delay value in us, depends of MCU you are using and circuit features
+///  - `timing` - bus timing or single delay value in us, see [`TM1638_TIMING`]
 ///  - `read_count` - number of bytes to read into output array
 #[inline]
 #[cfg(all(feature = "keys", feature = "clkdiostb"))]
-pub fn tm_read_bytes_3wire<DIO, CLK, STB, D>(
+pub fn tm_read_bytes_3wire<DIO, CLK, STB, D, T, E, EC, ES>(
     dio: &mut DIO,
     clk: &mut CLK,
     stb: &mut STB,
     delay_us: &mut D,
-    delay_value: u16,
+    timing: T,
     read_count: u8,
-) -> Result<[u8; 4], TmError>
+) -> Result<[u8; 4], TmError<E, EC, ES>>
 where
-    DIO: InputPin + OutputPin,
-    CLK: OutputPin,
-    STB: OutputPin,
-    D: FnMut(u16) -> (),
+    DIO: Dio<Error = E>,
+    CLK: OutputPin<Error = EC>,
+    STB: OutputPin<Error = ES>,
+    D: BusDelay,
+    T: Into<BusTiming>,
 {
-    let mut read_err = None;
+    let timing = timing.into();
     let mut response = [0_u8; 4];
 
-    if read_count <= 0 || read_count > response.len() as u8 {
+    if read_count == 0 || read_count > response.len() as u8 {
         return Err(TmError::Input);
     }
 
-    delay_us(delay_value);
-    stb.set_low().map_err(|_| TmError::Stb)?;
-    delay_us(delay_value);
+    tm_bus_3wire_read(
+        dio,
+        clk,
+        stb,
+        delay_us,
+        &timing,
+        COM_DATA_READ,
+        &mut response[..read_count as usize],
+    )?;
+    Ok(response)
+}
 
-    let res_init = tm_bus_send(dio, clk, delay_us, delay_value, COM_DATA_READ);
-    dio.set_high().map_err(|_| TmError::Stb)?;
-    if res_init.is_err() {
-        read_err = Some(res_init.unwrap_err());
+/// Sends read command and fills whole response slice with bytes from MCU using 3 wire interface (DIO,CLK,STB).
+///
+/// Slice length is the number of bytes to read, see chip response size like [`TM1668_RESPONSE_SIZE`].
+///
+/// Arguments:
+///  - `dio`, `clk`, `stb` - MCU interface pins
+///  - `delay_us` - closure that provides delay functionality
+///  - `timing` - bus timing or single delay value in us, see [`TM1638_TIMING`]
+///  - `command` - read command, usually [`COM_DATA_READ`]
+///  - `response` - buffer for received bytes
+#[inline]
+#[cfg(all(feature = "keys", feature = "clkdiostb"))]
+pub fn tm_read_bytes_into_3wire<DIO, CLK, STB, D, T, E, EC, ES>(
+    dio: &mut DIO,
+    clk: &mut CLK,
+    stb: &mut STB,
+    delay_us: &mut D,
+    timing: T,
+    command: u8,
+    response: &mut [u8],
+) -> Result<(), TmError<E, EC, ES>>
+where
+    DIO: Dio<Error = E>,
+    CLK: OutputPin<Error = EC>,
+    STB: OutputPin<Error = ES>,
+    D: BusDelay,
+    T: Into<BusTiming>,
+{
+    let timing = timing.into();
+    tm_bus_3wire_read(dio, clk, stb, delay_us, &timing, command, response)
+}
+
+/// Checks if there is any chip connected to 3 wire interface (DIO,CLK,STB).
+///
+/// Released DIO have to be pulled up, otherwise chip is reported as [`Probe::Absent`].
+/// 3 wire chips have no ACK, so key scan data is read instead.
+/// Some bits of key scan bytes are always zero, while pulled up DIO without chip reads as `0xFF`.
+///
+/// Arguments:
+///  - `dio`, `clk`, `stb` - MCU interface pins
+///  - `delay_us` - closure that provides delay functionality
+///  - `timing` - bus timing or single delay value in us, see [`TM1638_TIMING`]
+#[cfg(all(feature = "keys", feature = "clkdiostb"))]
+pub fn tm_probe_3wire<DIO, CLK, STB, D, T, E, EC, ES>(
+    dio: &mut DIO,
+    clk: &mut CLK,
+    stb: &mut STB,
+    delay_us: &mut D,
+    timing: T,
+) -> Result<Probe, TmError<E, EC, ES>>
+where
+    DIO: Dio<Error = E>,
+    CLK: OutputPin<Error = EC>,
+    STB: OutputPin<Error = ES>,
+    D: BusDelay,
+    T: Into<BusTiming>,
+{
+    let timing = timing.into();
+
+    let mut idle = bus::Sequence::idle(&timing, TM_ACK_POLLS);
+    match bus::run(&mut bus::Bus3 { dio, clk, stb }, delay_us, &mut idle) {
+        Ok(()) => {}
+        Err(TmError::Ack { .. }) => return Ok(Probe::Absent),
+        Err(e) => return Err(e),
+    }
+
+    let mut response = [0_u8; TM1638_RESPONSE_SIZE as usize];
+    tm_bus_3wire_read(
+        dio,
+        clk,
+        stb,
+        delay_us,
+        &timing,
+        COM_DATA_READ,
+        &mut response,
+    )?;
+
+    if response.iter().all(|b| *b == 0xFF) {
+        Ok(Probe::Absent)
     } else {
-        // Notice: When read data, set instruction from the 8th rising edge of clock
-        // to CLK falling edge to read data that demand a waiting time T wait(min 1μS).
-        delay_us(delay_value);
-        for i in 0..(read_count as usize) {
-            match tm_bus_read(dio, clk, delay_us, delay_value) {
-                Ok(b) => {
-                    response[i] = b;
-                }
-                Err(e) => {
-                    read_err = Some(e);
-                    break;
-                }
-            }
+        Ok(Probe::ThreeWire)
+    }
+}
+
+/// Display control command for TM1637/TM1638 command set.
+#[cfg(any(feature = "clkdio", feature = "clkdiostb"))]
+pub(crate) fn tm_display_control(on: bool, brightness: u8) -> u8 {
+    if on {
+        COM_DISPLAY_ON | (brightness & DISPLAY_BRIGHTNESS_MASK)
+    } else {
+        COM_DISPLAY_OFF
+    }
+}
+
+/// Puts address command and display bytes into `buf`.
+/// Returns `None` if bytes do not fit into `displays` starting from `offset`.
+#[cfg(feature = "clkdio")]
+pub(crate) fn tm_address_write<'b>(
+    buf: &'b mut [u8],
+    displays: u8,
+    offset: u8,
+    digits: &[u8],
+) -> Option<&'b [u8]> {
+    if offset as usize + digits.len() > displays as usize || digits.len() >= buf.len() {
+        return None;
+    }
+    buf[0] = COM_ADDRESS | offset;
+    buf[1..=digits.len()].copy_from_slice(digits);
+    Some(&buf[..=digits.len()])
+}
+
+/// Send UART like frame: start bit, 8 data bits LSB first, odd parity bit and stop bit.
+#[inline]
+#[cfg(feature = "dio")]
+fn tm_bus_1wire_send<DIO, D, E>(
+    dio: &mut DIO,
+    delay_us: &mut D,
+    bit_delay_us: u16,
+    byte: u8,
+) -> Result<(), TmError<E>>
+where
+    DIO: OutputPin<Error = E>,
+    D: BusDelay,
+{
+    dio.set_low().map_err(TmError::Dio)?;
+    delay_us.delay_us(bit_delay_us);
+
+    for i in 0..8 {
+        if (byte >> i) & 0b1 != 0 {
+            dio.set_high().map_err(TmError::Dio)?;
+        } else {
+            dio.set_low().map_err(TmError::Dio)?;
         }
+        delay_us.delay_us(bit_delay_us);
+    }
+
+    // Odd parity: total number of ones including parity bit must be odd
+    if byte.count_ones() & 0b1 == 0 {
+        dio.set_high().map_err(TmError::Dio)?;
+    } else {
+        dio.set_low().map_err(TmError::Dio)?;
     }
+    delay_us.delay_us(bit_delay_us);
 
-    stb.set_high().map_err(|_| TmError::Stb)?;
-    clk.set_high().map_err(|_| TmError::Stb)?;
-    dio.set_high().map_err(|_| TmError::Stb)?;
+    dio.set_high().map_err(TmError::Dio)?;
+    delay_us.delay_us(bit_delay_us);
+    Ok(())
+}
 
-    if read_err.is_some() {
-        return Err(read_err.unwrap());
+/// Send bytes via 1 wire UART like interface (DIO) using bit banging.
+///
+/// Each byte is sent as separate frame with odd parity, line stays high between frames.
+/// Use hardware serial peripheral if you can, this function is a fallback.
+///
+/// Arguments:
+///  - `dio` - MCU interface pin
+///  - `delay_us` - closure that provides delay functionality
+///  - `delay_value` - duration of one bit in us, see [`TM1652_BIT_DELAY_US`]
+///  - `bytes` - slice of bytes to send
+#[inline]
+#[cfg(feature = "dio")]
+pub fn tm_send_bytes_1wire<DIO, D, E>(
+    dio: &mut DIO,
+    delay_us: &mut D,
+    delay_value: u16,
+    bytes: &[u8],
+) -> Result<(), TmError<E>>
+where
+    DIO: OutputPin<Error = E>,
+    D: BusDelay,
+{
+    if bytes.is_empty() {
+        return Err(TmError::Input);
     }
-    Ok(response)
+    for bt in bytes {
+        tm_bus_1wire_send(dio, delay_us, delay_value, *bt)?;
+    }
+    Ok(())
 }
 
 /// Number of bytes that can be read from from TM1638 response.
@@ -493,12 +995,83 @@ pub const TM1637_RESPONSE_SIZE: u8 = 1;
 /// Maximum number of display segments supported by this MCU.
 pub const TM1637_MAX_SEGMENTS: u8 = 6;
 
+/// Number of key scan bytes in TM1628 response.
+pub const TM1628_RESPONSE_SIZE: u8 = 5;
+/// Number of key scan bytes in TM1668 response.
+pub const TM1668_RESPONSE_SIZE: u8 = 5;
+/// Number of key scan bytes in TM1629 response.
+pub const TM1629_RESPONSE_SIZE: u8 = 4;
+/// Number of key scan bytes in PT6311 response (12x4 key matrix).
+pub const PT6311_RESPONSE_SIZE: u8 = 6;
+/// Number of key scan bytes in PT6312 response (6x4 key matrix).
+pub const PT6312_RESPONSE_SIZE: u8 = 3;
+/// Number of bytes in TM1650 key code response.
+pub const TM1650_RESPONSE_SIZE: u8 = 1;
+
+/// Maximum number of display segments supported by TM1640.
+pub const TM1640_MAX_SEGMENTS: u8 = 16;
+
+/// Maximum number of display segments supported by TM1650.
+pub const TM1650_MAX_SEGMENTS: u8 = 4;
+
+/// Maximum number of display segments supported by TM1651.
+pub const TM1651_MAX_SEGMENTS: u8 = 4;
+
+/// Maximum number of display segments supported by TM1652.
+pub const TM1652_MAX_SEGMENTS: u8 = 6;
+
 /// Proven working delay for TM1637, it can be lower depending of your pull-up resistor characteristics.
+#[deprecated(note = "use TM1637_TIMING, only DIO rise needs such a long delay")]
 pub const TM1637_BUS_DELAY_US: u16 = 475;
 
 /// Proven working delay for TM1638
+#[deprecated(note = "use TM1638_TIMING")]
 pub const TM1638_BUS_DELAY_US: u16 = 1;
 
+/// TM1637 timing. Clock could run fast (datasheet allows up to 250 kHz),
+/// but DIO is driven by pull-up resistor, so DIO phases keep proven [`TM1637_BUS_DELAY_US`] value.
+pub const TM1637_TIMING: BusTiming = BusTiming {
+    clk_low_us: 5,
+    clk_high_us: 5,
+    data_setup_us: 475,
+    ack_wait_us: 475,
+    start_stop_us: 475,
+    stb_hold_us: 0,
+};
+
+/// TM1638 timing, push-pull lines allow 1 us for every phase.
+pub const TM1638_TIMING: BusTiming = BusTiming::uniform(1);
+
+/// TM1640 timing, it has no ACK and DIN is input only.
+pub const TM1640_TIMING: BusTiming = BusTiming::uniform(2);
+
+/// TM1650 timing, I2C like bus with pull-up on DIO.
+pub const TM1650_TIMING: BusTiming = BusTiming {
+    clk_low_us: 2,
+    clk_high_us: 5,
+    data_setup_us: 5,
+    ack_wait_us: 5,
+    start_stop_us: 5,
+    stb_hold_us: 0,
+};
+
+/// Delay for TM1640. It has no ACK and DIN is input only, thus there is no need to wait for pull-up.
+#[deprecated(note = "use TM1640_TIMING")]
+pub const TM1640_BUS_DELAY_US: u16 = 2;
+
+/// Delay for TM1650, it works with I2C like timings.
+#[deprecated(note = "use TM1650_TIMING")]
+pub const TM1650_BUS_DELAY_US: u16 = 5;
+
+/// TM1652 serial baud rate, frames are 8 data bits with odd parity and 1 stop bit.
+pub const TM1652_BAUD_RATE: u32 = 19200;
+
+/// Duration of one bit for TM1652 bit banged serial interface.
+pub const TM1652_BIT_DELAY_US: u16 = 52;
+
+/// Default number of DIO checks at every 2 wire ACK phase, each check is followed by bus delay.
+pub const TM_ACK_POLLS: u8 = 5;
+
 /// Universal delay for TM serial protocol.
 /// This value should fit all configurations, but you should prefer to use values that fits exact MCU chip version.
 pub const BUS_DELAY_US: u16 = 500;
@@ -513,12 +1086,17 @@ pub const COM_DISPLAY: u8 = 0b10000000;
 pub const COM_ADDRESS: u8 = 0b11000000;
 
 /// Address adding mode (write to display)
+#[allow(clippy::identity_op)]
 pub const COM_DATA_ADDRESS_ADD: u8 = COM_DATA | 0b000000;
 /// Data fix address mode (write to display)
 pub const COM_DATA_ADDRESS_FIXED: u8 = COM_DATA | 0b000100;
 /// Read key scan data
 pub const COM_DATA_READ: u8 = COM_DATA | 0b000010;
 
+/// Display mode setting instruction set (TM1628, TM1668 etc.).
+/// Lowest bits select number of grids and segments, see chip datasheet.
+pub const COM_DISPLAY_MODE: u8 = 0b00000000;
+
 /// Display ON max brightness.
 /// Can be combined with masked bytes to adjust brightness level
 pub const COM_DISPLAY_ON: u8 = 0b10001000;
@@ -605,3 +1183,372 @@ pub const CHAR_BRACKET_RIGHT: u8 = SEG_1 | SEG_2 | SEG_3 | SEG_4;
 pub const DIGITS: [u8; 10] = [
     CHAR_0, CHAR_1, CHAR_2, CHAR_3, CHAR_4, CHAR_5, CHAR_6, CHAR_7, CHAR_8, CHAR_9,
 ];
+
+#[cfg(all(test, any(feature = "clkdio", feature = "clkdiostb")))]
+mod tests {
+    use super::*;
+
+    use core::cell::Cell;
+
+    extern crate std;
+    use std::string::ToString;
+
+    #[derive(Debug, PartialEq)]
+    pub(crate) struct Broken;
+
+    /// Floating line: reads high unless `stuck_low`, fails to switch if `broken`.
+    #[derive(Default)]
+    pub(crate) struct Line {
+        pub(crate) broken: bool,
+        pub(crate) stuck_low: bool,
+        pub(crate) reads: Cell<u32>,
+    }
+
+    impl OutputPin for Line {
+        type Error = Broken;
+
+        fn set_low(&mut self) -> Result<(), Broken> {
+            self.set_high()
+        }
+
+        fn set_high(&mut self) -> Result<(), Broken> {
+            if self.broken {
+                Err(Broken)
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    impl InputPin for Line {
+        type Error = Broken;
+
+        fn is_high(&self) -> Result<bool, Broken> {
+            self.reads.set(self.reads.get() + 1);
+            Ok(!self.stuck_low)
+        }
+
+        fn is_low(&self) -> Result<bool, Broken> {
+            Ok(self.stuck_low)
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "clkdio")]
+    fn no_ack_test() {
+        let mut dio = Line::default();
+        let mut clk = Line::default();
+        let result = tm_send_bytes_2wire(&mut dio, &mut clk, &mut |_| {}, 1, &[COM_DISPLAY_ON]);
+        match result {
+            Err(TmError::Ack { byte, phase, .. }) => {
+                assert_eq!((byte, phase), (0, AckPhase::Falling8));
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "no ACK for byte 0 at 8th falling edge"
+        );
+        let parallel: TmError<Broken> = TmError::Ack {
+            byte: 2,
+            phase: AckPhase::Stop,
+            line: 1,
+        };
+        assert_eq!(
+            parallel.to_string(),
+            "no ACK for byte 2 at stop on DIO line 1"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "clkdio")]
+    fn mixed_errors_test() {
+        /// CLK from another port with its own error type.
+        struct Clk;
+
+        impl OutputPin for Clk {
+            type Error = u8;
+
+            fn set_low(&mut self) -> Result<(), u8> {
+                Err(7)
+            }
+
+            fn set_high(&mut self) -> Result<(), u8> {
+                Ok(())
+            }
+        }
+
+        let mut dio = Line::default();
+        let result: Result<(), TmError<Broken, u8>> =
+            tm_send_bytes_2wire(&mut dio, &mut Clk, &mut |_| {}, 1, &[COM_DISPLAY_ON]);
+        assert!(matches!(result, Err(TmError::Clk(7))));
+        assert_eq!(result.unwrap_err().to_string(), "CLK pin error: 7");
+    }
+
+    #[test]
+    #[cfg(feature = "clkdio")]
+    fn retry_no_ack_test() {
+        let mut dio = Line::default();
+        let mut clk = Line::default();
+        let mut policy = RetryPolicy::new(3, 10);
+        policy.ack_polls = 2;
+        let mut failed = 0;
+        let mut delays = 0_u32;
+        let result = tm_send_bytes_2wire_retry(
+            &mut dio,
+            &mut clk,
+            &mut |us| delays += us as u32,
+            1,
+            &[COM_DISPLAY_ON],
+            &policy,
+            &mut failed,
+        );
+        assert!(matches!(result, Err(TmError::Ack { byte: 0, .. })));
+        assert_eq!(failed, 3);
+        // 8th falling edge and stop checks for every attempt
+        assert_eq!(dio.reads.get(), 3 * (2 + 1));
+        // Backoff grows with attempt number: 10 + 20 us
+        assert!(delays > 30);
+    }
+
+    #[test]
+    #[cfg(feature = "clkdio")]
+    fn retry_recovery_test() {
+        use crate::sim::{SimChip, SimPin};
+        use core::cell::RefCell;
+
+        /// DIO that misses first `lies` ACK checks as if pull-up was too strong.
+        struct Flaky<'a> {
+            pin: SimPin<'a>,
+            lies: Cell<u8>,
+        }
+
+        impl OutputPin for Flaky<'_> {
+            type Error = core::convert::Infallible;
+
+            fn set_low(&mut self) -> Result<(), Self::Error> {
+                self.pin.set_low()
+            }
+
+            fn set_high(&mut self) -> Result<(), Self::Error> {
+                self.pin.set_high()
+            }
+        }
+
+        impl InputPin for Flaky<'_> {
+            type Error = core::convert::Infallible;
+
+            fn is_high(&self) -> Result<bool, Self::Error> {
+                if self.lies.get() > 0 {
+                    self.lies.set(self.lies.get() - 1);
+                    return Ok(true);
+                }
+                self.pin.is_high()
+            }
+
+            fn is_low(&self) -> Result<bool, Self::Error> {
+                self.is_high().map(|high| !high)
+            }
+        }
+
+        let chip = RefCell::new(SimChip::tm1637());
+        let mut clk = SimPin::clk(&chip);
+        let mut dio = Flaky {
+            pin: SimPin::dio(&chip),
+            lies: Cell::new(TM_ACK_POLLS),
+        };
+        let bytes = [COM_ADDRESS, CHAR_1];
+        let mut failed = 0;
+
+        let policy = RetryPolicy::new(2, 0);
+        tm_send_bytes_2wire_retry(
+            &mut dio,
+            &mut clk,
+            &mut |_| {},
+            1,
+            &bytes,
+            &policy,
+            &mut failed,
+        )
+        .unwrap();
+        assert_eq!(failed, 1);
+        assert_eq!(chip.borrow().ram()[0], CHAR_1);
+
+        // One more poll is enough to survive the same glitch without retry
+        dio.lies.set(TM_ACK_POLLS);
+        let policy = RetryPolicy {
+            ack_polls: TM_ACK_POLLS + 1,
+            ..RetryPolicy::default()
+        };
+        let bytes = [COM_ADDRESS, CHAR_2];
+        tm_send_bytes_2wire_retry(
+            &mut dio,
+            &mut clk,
+            &mut |_| {},
+            1,
+            &bytes,
+            &policy,
+            &mut failed,
+        )
+        .unwrap();
+        assert_eq!(failed, 1);
+        assert_eq!(chip.borrow().ram()[0], CHAR_2);
+    }
+
+    #[test]
+    #[cfg(feature = "clkdio")]
+    fn timing_test() {
+        use crate::sim::{SimChip, SimPin};
+        use core::cell::RefCell;
+
+        let chip = RefCell::new(SimChip::tm1637());
+        let bytes = [COM_ADDRESS, CHAR_1, CHAR_2, CHAR_3, CHAR_4];
+        let send = |timing: BusTiming| {
+            let mut total_us = 0_u32;
+            let mut delay = |us: u16| total_us += us as u32;
+            let (mut dio, mut clk) = (SimPin::dio(&chip), SimPin::clk(&chip));
+            tm_send_bytes_2wire(&mut dio, &mut clk, &mut delay, timing, &bytes).unwrap();
+            total_us
+        };
+
+        let uniform_us = send(475.into());
+        let preset_us = send(TM1637_TIMING);
+        assert!(preset_us * 2 < uniform_us);
+        assert_eq!(chip.borrow().ram()[..4], bytes[1..]);
+        assert_eq!(chip.borrow().errors(), 0);
+        assert_eq!(BusTiming::from(7), BusTiming::uniform(7));
+    }
+
+    #[test]
+    #[cfg(feature = "clkdiostb")]
+    fn pin_error_3wire_test() {
+        let mut dio = Line::default();
+        let mut clk = Line {
+            broken: true,
+            ..Line::default()
+        };
+        let mut stb = Line::default();
+        let result = tm_send_bytes_3wire(&mut dio, &mut clk, &mut stb, &mut |_| {}, 1, &[0]);
+        assert!(matches!(result, Err(TmError::Clk(Broken))));
+        assert_eq!(result.unwrap_err().to_string(), "CLK pin error: Broken");
+    }
+
+    #[test]
+    #[cfg(feature = "clkdio")]
+    fn probe_2wire_test() {
+        use crate::sim::{SimChip, SimPin};
+        use core::cell::RefCell;
+
+        let chip = RefCell::new(SimChip::tm1637());
+        let (mut dio, mut clk) = (SimPin::dio(&chip), SimPin::clk(&chip));
+        let probe = tm_probe_2wire(&mut dio, &mut clk, &mut |_| {}, TM1637_TIMING);
+        assert_eq!(probe.unwrap(), Probe::TwoWireAck);
+        assert_eq!(chip.borrow().errors(), 0);
+
+        let mut dio = Line::default();
+        let mut clk = Line::default();
+        let probe = tm_probe_2wire(&mut dio, &mut clk, &mut |_| {}, 1);
+        assert_eq!(probe.unwrap(), Probe::TwoWireNoAck);
+
+        dio.stuck_low = true;
+        let probe = tm_probe_2wire(&mut dio, &mut clk, &mut |_| {}, 1);
+        assert_eq!(probe.unwrap(), Probe::Absent);
+
+        clk.broken = true;
+        let probe = tm_probe_2wire(&mut dio, &mut clk, &mut |_| {}, 1);
+        assert!(matches!(probe, Err(TmError::Clk(Broken))));
+    }
+
+    #[test]
+    #[cfg(all(feature = "keys", feature = "clkdiostb"))]
+    fn probe_3wire_test() {
+        use crate::sim::{SimChip, SimPin};
+        use core::cell::RefCell;
+
+        let chip = RefCell::new(SimChip::tm1638());
+        let (mut dio, mut clk, mut stb) =
+            (SimPin::dio(&chip), SimPin::clk(&chip), SimPin::stb(&chip));
+        let probe = tm_probe_3wire(&mut dio, &mut clk, &mut stb, &mut |_| {}, TM1638_TIMING);
+        assert_eq!(probe.unwrap(), Probe::ThreeWire);
+
+        let (mut dio, mut clk, mut stb) = (Line::default(), Line::default(), Line::default());
+        let probe = tm_probe_3wire(&mut dio, &mut clk, &mut stb, &mut |_| {}, 1);
+        assert_eq!(probe.unwrap(), Probe::Absent);
+
+        // Shorted DIO is found before any key scan data is read
+        let mut dio = Line {
+            stuck_low: true,
+            ..Line::default()
+        };
+        let probe = tm_probe_3wire(&mut dio, &mut clk, &mut stb, &mut |_| {}, 1);
+        assert_eq!(probe.unwrap(), Probe::Absent);
+        assert_eq!(dio.reads.get(), TM_ACK_POLLS as u32);
+    }
+
+    #[test]
+    #[cfg(all(feature = "keys", feature = "clkdiostb"))]
+    fn read_into_3wire_test() {
+        use crate::sim::{SimChip, SimPin};
+        use core::cell::RefCell;
+
+        let chip = RefCell::new(SimChip::tm1638());
+        chip.borrow_mut().set_key_scan(&[1, 2, 3, 4]);
+        let (mut dio, mut clk, mut stb) =
+            (SimPin::dio(&chip), SimPin::clk(&chip), SimPin::stb(&chip));
+        let mut delay = |_| {};
+
+        let mut response = [0_u8; 3];
+        tm_read_bytes_into_3wire(
+            &mut dio,
+            &mut clk,
+            &mut stb,
+            &mut delay,
+            TM1638_TIMING,
+            COM_DATA_READ,
+            &mut response,
+        )
+        .unwrap();
+        // Bus reads MSB first while chip sends LSB first
+        assert_eq!(
+            response,
+            [
+                1_u8.reverse_bits(),
+                2_u8.reverse_bits(),
+                3_u8.reverse_bits()
+            ]
+        );
+
+        let empty = tm_read_bytes_into_3wire(
+            &mut dio,
+            &mut clk,
+            &mut stb,
+            &mut delay,
+            TM1638_TIMING,
+            COM_DATA_READ,
+            &mut [],
+        );
+        assert!(matches!(empty, Err(TmError::Input)));
+        assert_eq!(chip.borrow().errors(), 0);
+    }
+
+    #[test]
+    #[cfg(all(feature = "keys", feature = "clkdio"))]
+    fn read_into_2wire_test() {
+        use crate::sim::{SimChip, SimPin};
+        use core::cell::RefCell;
+
+        let chip = RefCell::new(SimChip::tm1637());
+        chip.borrow_mut().set_key_scan(&[0b1110_1001]);
+        let (mut dio, mut clk) = (SimPin::dio(&chip), SimPin::clk(&chip));
+        let mut delay = |_| {};
+
+        let mut response = [0_u8; TM1637_RESPONSE_SIZE as usize];
+        tm_read_bytes_into_2wire(&mut dio, &mut clk, &mut delay, TM1637_TIMING, &mut response)
+            .unwrap();
+        assert_eq!(response[0].reverse_bits(), 0b1110_1001);
+
+        let empty = tm_read_bytes_into_2wire(&mut dio, &mut clk, &mut delay, 1, &mut []);
+        assert!(matches!(empty, Err(TmError::Input)));
+        assert_eq!(chip.borrow().errors(), 0);
+    }
+}
@@ -0,0 +1,208 @@
+//! Data line adapters for boards where DIO is not a single open drain pin.
+//!
+//! Bus functions accept anything that implements [`Dio`] (or [`DioOut`] for write only chips).
+//! Usual pin with both `InputPin` and `OutputPin` fits as is, here are options for other cases:
+//!  - [`SplitDio`] - separate output and input pins, for example behind level shifter
+//!  - [`OpenDrain`] - pin without open drain mode that switches direction instead
+use super::*;
+
+/// Data line on two pins: one drives the line and another one reads it back.
+///
+/// Pins may have different error types, bus errors come as [`SplitDioError`].
+pub struct SplitDio<OUT, IN> {
+    out: OUT,
+    input: IN,
+}
+
+impl<OUT, IN> SplitDio<OUT, IN> {
+    /// Combines output and input pins into one data line.
+    pub fn new(out: OUT, input: IN) -> SplitDio<OUT, IN> {
+        SplitDio { out, input }
+    }
+
+    /// Gives back output and input pins.
+    pub fn release(self) -> (OUT, IN) {
+        (self.out, self.input)
+    }
+}
+
+/// Error of [`SplitDio`], output and input pins may come from different HALs.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SplitDioError<EO, EI> {
+    /// Output pin failed
+    Out(EO),
+    /// Input pin failed
+    In(EI),
+}
+
+impl<OUT: OutputPin, IN: InputPin> DioOut for SplitDio<OUT, IN> {
+    type Error = SplitDioError<OUT::Error, IN::Error>;
+
+    fn set_dio(&mut self, high: bool) -> Result<(), Self::Error> {
+        self.out.set_dio(high).map_err(SplitDioError::Out)
+    }
+}
+
+impl<OUT: OutputPin, IN: InputPin> Dio for SplitDio<OUT, IN> {
+    fn is_dio_high(&mut self) -> Result<bool, Self::Error> {
+        self.input.is_high().map_err(SplitDioError::In)
+    }
+}
+
+/// Pin that is able to change direction at runtime, see [`OpenDrain`].
+pub trait FlexPin {
+    /// Error type of pin
+    type Error;
+
+    /// Switches pin to input, line is pulled up by resistor.
+    fn set_input(&mut self) -> Result<(), Self::Error>;
+
+    /// Switches pin to output and drives line low.
+    fn set_output_low(&mut self) -> Result<(), Self::Error>;
+
+    /// Reads line level.
+    fn is_line_high(&mut self) -> Result<bool, Self::Error>;
+}
+
+/// Open drain emulation for push-pull pins.
+///
+/// Line is never driven high, pin becomes input instead and pull-up resistor does the rest.
+/// Current direction is cached, so pin is switched only when it is needed.
+pub struct OpenDrain<P> {
+    pin: P,
+    output: Option<bool>,
+}
+
+impl<P: FlexPin> OpenDrain<P> {
+    /// Wraps pin without touching it, direction is set on the first use.
+    pub fn new(pin: P) -> OpenDrain<P> {
+        OpenDrain { pin, output: None }
+    }
+
+    /// Gives back original pin.
+    pub fn release(self) -> P {
+        self.pin
+    }
+}
+
+impl<P: FlexPin> DioOut for OpenDrain<P> {
+    type Error = P::Error;
+
+    fn set_dio(&mut self, high: bool) -> Result<(), P::Error> {
+        if self.output == Some(!high) {
+            return Ok(());
+        }
+        if high {
+            self.pin.set_input()?;
+        } else {
+            self.pin.set_output_low()?;
+        }
+        self.output = Some(!high);
+        Ok(())
+    }
+}
+
+impl<P: FlexPin> Dio for OpenDrain<P> {
+    fn is_dio_high(&mut self) -> Result<bool, P::Error> {
+        self.pin.is_line_high()
+    }
+}
+
+#[cfg(all(test, feature = "clkdio"))]
+mod tests {
+    use super::*;
+    use crate::sim::{SimChip, SimPin};
+    use core::cell::{Cell, RefCell};
+    use core::convert::Infallible;
+
+    #[test]
+    fn split_dio_test() {
+        let chip = RefCell::new(SimChip::tm1637());
+        let mut dio = SplitDio::new(SimPin::dio(&chip), SimPin::dio(&chip));
+        let mut clk = SimPin::clk(&chip);
+        let bytes = [COM_ADDRESS, CHAR_1, CHAR_2];
+        tm_send_bytes_2wire(&mut dio, &mut clk, &mut |_| {}, TM1637_TIMING, &bytes).unwrap();
+        assert_eq!(chip.borrow().ram()[..2], bytes[1..]);
+        assert_eq!(chip.borrow().errors(), 0);
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct ReadFault;
+
+    /// Input pin from another HAL that fails to read.
+    struct BrokenInput;
+
+    impl InputPin for BrokenInput {
+        type Error = ReadFault;
+
+        fn is_high(&self) -> Result<bool, ReadFault> {
+            Err(ReadFault)
+        }
+
+        fn is_low(&self) -> Result<bool, ReadFault> {
+            Err(ReadFault)
+        }
+    }
+
+    #[test]
+    fn split_dio_errors_test() {
+        let chip = RefCell::new(SimChip::tm1637());
+        // Output error is Infallible, input error is ReadFault
+        let mut dio = SplitDio::new(SimPin::dio(&chip), BrokenInput);
+        let mut clk = SimPin::clk(&chip);
+        let res = tm_send_bytes_2wire(&mut dio, &mut clk, &mut |_| {}, 0, &[COM_ADDRESS]);
+        assert!(matches!(
+            res,
+            Err(TmError::Dio(SplitDioError::In(ReadFault)))
+        ));
+        // Nothing is read while writing
+        tm_send_bytes_2wire_noack(&mut dio, &mut clk, &mut |_| {}, 0, &[COM_ADDRESS]).unwrap();
+    }
+
+    /// Push-pull pin that counts direction changes.
+    struct Pin<'a> {
+        pin: SimPin<'a>,
+        switches: &'a Cell<u32>,
+    }
+
+    impl FlexPin for Pin<'_> {
+        type Error = Infallible;
+
+        fn set_input(&mut self) -> Result<(), Infallible> {
+            self.switches.set(self.switches.get() + 1);
+            self.pin.set_high()
+        }
+
+        fn set_output_low(&mut self) -> Result<(), Infallible> {
+            self.switches.set(self.switches.get() + 1);
+            self.pin.set_low()
+        }
+
+        fn is_line_high(&mut self) -> Result<bool, Infallible> {
+            self.pin.is_high()
+        }
+    }
+
+    #[test]
+    fn open_drain_test() {
+        let chip = RefCell::new(SimChip::tm1637());
+        let switches = Cell::new(0);
+        let mut dio = OpenDrain::new(Pin {
+            pin: SimPin::dio(&chip),
+            switches: &switches,
+        });
+        let mut clk = SimPin::clk(&chip);
+        // Same bits in a row keep pin direction
+        let bytes = [COM_ADDRESS, 0xFF, 0x00];
+        tm_send_bytes_2wire(&mut dio, &mut clk, &mut |_| {}, TM1637_TIMING, &bytes).unwrap();
+        assert_eq!(chip.borrow().ram()[..2], bytes[1..]);
+        assert!(switches.get() < 16);
+
+        dio.set_dio(false).unwrap();
+        dio.set_dio(false).unwrap();
+        assert!(!dio.is_dio_high().unwrap());
+        dio.set_dio(true).unwrap();
+        assert!(dio.is_dio_high().unwrap());
+    }
+}
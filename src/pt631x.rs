@@ -0,0 +1,210 @@
+//! Princeton PT6311 and PT6312 VFD controllers.
+//!
+//! This chips use the same STB/CLK/DIO protocol and commands as TM16xx family,
+//! so they are just [`Chip`] profiles for [`Tm16xx`](super::tm16xx::Tm16xx) driver,
+//! e.g. `Tm16xx<Pt6312, _, _, _, _>`.
+//! Besides key matrix they have LED output port and switch inputs, see [`SwitchPort`].
+use super::tm16xx::{Chip, SwitchPort};
+use super::*;
+
+/// Write LED port data setting command.
+pub const PT631X_COM_DATA_LED: u8 = COM_DATA | 0b01;
+/// Read switch inputs data setting command.
+pub const PT631X_COM_DATA_SWITCH: u8 = COM_DATA | 0b11;
+
+/// Display modes for PT6311.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pt6311Mode {
+    Grid8Seg20 = 0b0000,
+    Grid9Seg19 = 0b1000,
+    Grid10Seg18 = 0b1001,
+    Grid11Seg17 = 0b1010,
+    Grid12Seg16 = 0b1011,
+    Grid13Seg15 = 0b1100,
+    Grid14Seg14 = 0b1101,
+    Grid15Seg13 = 0b1110,
+    Grid16Seg12 = 0b1111,
+}
+
+/// Display modes for PT6312.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pt6312Mode {
+    Grid4Seg16 = 0b000,
+    Grid5Seg16 = 0b001,
+    Grid6Seg16 = 0b010,
+    Grid7Seg15 = 0b011,
+    Grid8Seg14 = 0b100,
+    Grid9Seg13 = 0b101,
+    Grid10Seg12 = 0b110,
+    Grid11Seg11 = 0b111,
+}
+
+impl Pt6311Mode {
+    /// Number of grids in this mode.
+    pub fn grids(self) -> u8 {
+        match self {
+            Pt6311Mode::Grid8Seg20 => 8,
+            mode => 9 + (mode as u8 & 0b111),
+        }
+    }
+}
+
+impl Pt6312Mode {
+    /// Number of grids in this mode.
+    pub fn grids(self) -> u8 {
+        4 + self as u8
+    }
+}
+
+/// PT6311: up to 16 grids and 20 segments (3 bytes per grid), 48 keys, 5 LEDs, 4 switches.
+pub struct Pt6311;
+
+/// PT6312: up to 11 grids and 16 segments (2 bytes per grid), 24 keys, 4 LEDs, 4 switches.
+pub struct Pt6312;
+
+impl Chip for Pt6311 {
+    type Mode = Pt6311Mode;
+    const RAM_SIZE: u8 = 48;
+    const BYTES_PER_GRID: u8 = 3;
+    const KEY_BYTES: u8 = PT6311_RESPONSE_SIZE;
+
+    fn mode_command(mode: Pt6311Mode) -> Option<u8> {
+        Some(COM_DISPLAY_MODE | mode as u8)
+    }
+
+    fn grids(mode: Pt6311Mode) -> u8 {
+        mode.grids()
+    }
+}
+
+impl SwitchPort for Pt6311 {
+    const COM_WRITE_LED: u8 = PT631X_COM_DATA_LED;
+    const COM_READ_SWITCH: u8 = PT631X_COM_DATA_SWITCH;
+}
+
+impl Chip for Pt6312 {
+    type Mode = Pt6312Mode;
+    const RAM_SIZE: u8 = 22;
+    const BYTES_PER_GRID: u8 = 2;
+    const KEY_BYTES: u8 = PT6312_RESPONSE_SIZE;
+
+    fn mode_command(mode: Pt6312Mode) -> Option<u8> {
+        Some(COM_DISPLAY_MODE | mode as u8)
+    }
+
+    fn grids(mode: Pt6312Mode) -> u8 {
+        mode.grids()
+    }
+}
+
+impl SwitchPort for Pt6312 {
+    const COM_WRITE_LED: u8 = PT631X_COM_DATA_LED;
+    const COM_READ_SWITCH: u8 = PT631X_COM_DATA_SWITCH;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::{SimChip, SimPin};
+    use crate::tm16xx::Tm16xx;
+    use core::cell::RefCell;
+
+    fn driver<C: Chip>(
+        chip: &RefCell<SimChip>,
+        mode: C::Mode,
+    ) -> Tm16xx<C, SimPin<'_>, SimPin<'_>, SimPin<'_>, impl FnMut(u16)> {
+        Tm16xx::new(
+            SimPin::dio(chip),
+            SimPin::clk(chip),
+            SimPin::stb(chip),
+            |_| {},
+            0,
+            mode,
+        )
+    }
+
+    #[test]
+    fn mode_command_test() {
+        assert_eq!(Pt6311::mode_command(Pt6311Mode::Grid8Seg20), Some(0b0000));
+        assert_eq!(Pt6311::mode_command(Pt6311Mode::Grid12Seg16), Some(0b1011));
+        assert_eq!(Pt6312::mode_command(Pt6312Mode::Grid11Seg11), Some(0b0111));
+
+        assert_eq!(Pt6311Mode::Grid8Seg20.grids(), 8);
+        assert_eq!(Pt6311Mode::Grid9Seg19.grids(), 9);
+        assert_eq!(Pt6311Mode::Grid16Seg12.grids(), 16);
+        assert_eq!(Pt6312Mode::Grid4Seg16.grids(), 4);
+        assert_eq!(Pt6312Mode::Grid11Seg11.grids(), 11);
+    }
+
+    #[test]
+    fn pt6311_ram_test() {
+        let chip = RefCell::new(SimChip::tm16xx::<Pt6311>());
+        let mut tm = driver::<Pt6311>(&chip, Pt6311Mode::Grid16Seg12);
+        tm.init().unwrap();
+        assert_eq!(chip.borrow().display_mode(), 0b1111);
+
+        // Three bytes per grid, segment 1 is the lowest bit of the first byte
+        tm.write_grid(0, 0x0F_8001).unwrap();
+        tm.write_grid(15, 0x0A_0B0C).unwrap();
+        tm.write_digits(&[CHAR_1, CHAR_2]).unwrap();
+
+        tm.set_mode(Pt6311Mode::Grid8Seg20).unwrap();
+        assert!(matches!(tm.write_grid(8, 0), Err(TmError::Input)));
+
+        let chip = chip.borrow();
+        assert_eq!(&chip.ram()[..6], &[CHAR_1, 0, 0, CHAR_2, 0, 0]);
+        assert_eq!(&chip.ram()[45..], &[0x0C, 0x0B, 0x0A]);
+        assert_eq!(chip.display_mode(), 0b0000);
+        assert_eq!(chip.errors(), 0);
+    }
+
+    #[test]
+    fn pt6312_ram_test() {
+        let chip = RefCell::new(SimChip::tm16xx::<Pt6312>());
+        let mut tm = driver::<Pt6312>(&chip, Pt6312Mode::Grid11Seg11);
+        tm.init().unwrap();
+        assert_eq!(chip.borrow().display_mode(), 0b111);
+
+        tm.write_grid(10, 0x0201).unwrap();
+        assert!(matches!(tm.write_grid(11, 0), Err(TmError::Input)));
+        assert_eq!(&chip.borrow().ram()[20..], &[0x01, 0x02]);
+        assert_eq!(chip.borrow().errors(), 0);
+    }
+
+    #[cfg(feature = "keys")]
+    #[test]
+    fn pt6312_read_keys_test() {
+        let chip = RefCell::new(SimChip::tm16xx::<Pt6312>());
+        let mut tm = driver::<Pt6312>(&chip, Pt6312Mode::Grid4Seg16);
+        tm.init().unwrap();
+        chip.borrow_mut()
+            .set_key_scan(&[0b1000_0000, 0, 0b0000_0001]);
+
+        // 6x4 key matrix fits into 3 bytes, nothing is clocked out after them
+        let scan = tm.read_keys().unwrap();
+        assert_eq!(&scan[..], &[0b0000_0001, 0, 0b1000_0000]);
+        assert_eq!(chip.borrow().errors(), 0);
+    }
+
+    #[test]
+    fn switch_port_test() {
+        let chip = RefCell::new(SimChip::tm16xx::<Pt6312>());
+        let mut tm = driver::<Pt6312>(&chip, Pt6312Mode::Grid4Seg16);
+        tm.init().unwrap();
+
+        tm.write_leds(0b1010).unwrap();
+        assert_eq!(chip.borrow().leds(), 0b1010);
+        // Driver switches back to display memory writing
+        tm.write_grid(0, 0x0102).unwrap();
+        assert_eq!(&chip.borrow().ram()[..2], &[0x02, 0x01]);
+        assert_eq!(chip.borrow().leds(), 0b1010);
+
+        #[cfg(feature = "keys")]
+        {
+            chip.borrow_mut().set_switches(0b0011);
+            // Bus reads MSB first while chip sends LSB first
+            assert_eq!(tm.read_switches().unwrap().reverse_bits(), 0b0011);
+        }
+        assert_eq!(chip.borrow().errors(), 0);
+    }
+}
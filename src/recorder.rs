@@ -0,0 +1,304 @@
+//! Bus waveform recorder with Value Change Dump (VCD) export (**sim** feature).
+//!
+//! [`RecordPin`] wraps any pin and writes every `set_high`, `set_low` and `is_high` call into [`Recorder`].
+//! Time is virtual: it is accumulated from delay closure made by [`Recorder::delay`],
+//! so recorded waveform shows exactly what bus functions ask for, regardless of host speed.
+//!
+//! Result could be exported with [`Recorder::write_vcd`] and opened in GTKWave, PulseView (sigrok) etc.
+//!
+//! ```
+//! # #[cfg(feature = "clkdio")] {
+//! # use core::cell::RefCell;
+//! # use tmledkey_hal_drv::recorder::{Recorder, RecordPin};
+//! # use tmledkey_hal_drv::sim::{Line, SimChip, SimPin};
+//! # use tmledkey_hal_drv::*;
+//! let chip = RefCell::new(SimChip::tm1637());
+//! let rec: Recorder<1024> = Recorder::new();
+//! let mut dio = RecordPin::new(&rec, Line::Dio, SimPin::dio(&chip));
+//! let mut clk = RecordPin::new(&rec, Line::Clk, SimPin::clk(&chip));
+//! tm_send_bytes_2wire(&mut dio, &mut clk, &mut rec.delay(|_| {}), 10, &[COM_DATA_ADDRESS_ADD]).unwrap();
+//!
+//! let mut vcd = String::new();
+//! rec.write_vcd(&mut vcd).unwrap();
+//! # }
+//! ```
+use core::cell::{Cell, RefCell};
+use core::fmt;
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+
+use super::sim::Line;
+
+/// What MCU did with the pin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Pin was set to given level
+    Set(bool),
+    /// Pin was read and given level was returned
+    Read(bool),
+}
+
+/// One recorded pin call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    /// Virtual time in us
+    pub time_us: u32,
+    pub line: Line,
+    pub action: Action,
+}
+
+/// Keeps up to `N` events and virtual time.
+pub struct Recorder<const N: usize> {
+    now_us: Cell<u32>,
+    events: RefCell<[Option<Event>; N]>,
+    len: Cell<usize>,
+    dropped: Cell<usize>,
+}
+
+impl<const N: usize> Default for Recorder<N> {
+    fn default() -> Self {
+        Recorder::new()
+    }
+}
+
+impl<const N: usize> Recorder<N> {
+    /// Creates empty recorder with virtual time 0.
+    pub fn new() -> Recorder<N> {
+        Recorder {
+            now_us: Cell::new(0),
+            events: RefCell::new([None; N]),
+            len: Cell::new(0),
+            dropped: Cell::new(0),
+        }
+    }
+
+    /// Current virtual time in us.
+    pub fn now_us(&self) -> u32 {
+        self.now_us.get()
+    }
+
+    /// Moves virtual time forward.
+    pub fn advance(&self, us: u32) {
+        self.now_us.set(self.now_us.get().saturating_add(us));
+    }
+
+    /// Wraps delay closure, every call moves virtual time forward and then calls `inner`.
+    /// Pass `|_| {}` if you do not need real delays.
+    pub fn delay<'a, D: FnMut(u16) + 'a>(&'a self, mut inner: D) -> impl FnMut(u16) + 'a {
+        move |us| {
+            self.advance(us as u32);
+            inner(us);
+        }
+    }
+
+    /// Writes event at current virtual time.
+    /// Events that do not fit into recorder are counted by [`dropped`](Recorder::dropped).
+    pub fn record(&self, line: Line, action: Action) {
+        let len = self.len.get();
+        if len >= N {
+            self.dropped.set(self.dropped.get() + 1);
+            return;
+        }
+        self.events.borrow_mut()[len] = Some(Event {
+            time_us: self.now_us.get(),
+            line,
+            action,
+        });
+        self.len.set(len + 1);
+    }
+
+    /// Number of recorded events.
+    pub fn len(&self) -> usize {
+        self.len.get()
+    }
+
+    /// True if nothing was recorded.
+    pub fn is_empty(&self) -> bool {
+        self.len.get() == 0
+    }
+
+    /// Number of events that did not fit into recorder.
+    pub fn dropped(&self) -> usize {
+        self.dropped.get()
+    }
+
+    /// Recorded event by index.
+    pub fn event(&self, index: usize) -> Option<Event> {
+        if index < self.len.get() {
+            self.events.borrow()[index]
+        } else {
+            None
+        }
+    }
+
+    /// Forgets all events and resets virtual time.
+    pub fn clear(&self) {
+        self.now_us.set(0);
+        self.len.set(0);
+        self.dropped.set(0);
+    }
+
+    /// Writes recorded waveform as VCD with 1 us time scale.
+    ///
+    /// There are `clk`, `dio` and `stb` wires with levels set by MCU
+    /// and `dio_in` wire with levels that MCU read from DIO.
+    pub fn write_vcd<W: fmt::Write>(&self, out: &mut W) -> fmt::Result {
+        writeln!(out, "$timescale 1us $end")?;
+        writeln!(out, "$scope module tm $end")?;
+        for (id, name) in VCD_WIRES.iter() {
+            writeln!(out, "$var wire 1 {} {} $end", id, name)?;
+        }
+        writeln!(out, "$upscope $end")?;
+        writeln!(out, "$enddefinitions $end")?;
+        writeln!(out, "#0")?;
+        writeln!(out, "$dumpvars")?;
+        for (id, _) in VCD_WIRES.iter() {
+            writeln!(out, "x{}", id)?;
+        }
+        writeln!(out, "$end")?;
+
+        let mut time = 0;
+        for index in 0..self.len() {
+            let event = match self.event(index) {
+                Some(e) => e,
+                None => break,
+            };
+            if event.time_us != time {
+                time = event.time_us;
+                writeln!(out, "#{}", time)?;
+            }
+            let (id, level) = match event.action {
+                Action::Set(level) => (vcd_id(event.line), level),
+                Action::Read(level) => (VCD_DIO_IN, level),
+            };
+            writeln!(out, "{}{}", level as u8, id)?;
+        }
+        writeln!(out, "#{}", self.now_us())
+    }
+}
+
+const VCD_DIO_IN: char = '%';
+const VCD_WIRES: [(char, &str); 4] = [
+    ('!', "clk"),
+    ('"', "dio"),
+    ('#', "stb"),
+    (VCD_DIO_IN, "dio_in"),
+];
+
+fn vcd_id(line: Line) -> char {
+    match line {
+        Line::Clk => '!',
+        Line::Dio => '"',
+        Line::Stb => '#',
+    }
+}
+
+/// Pin wrapper that writes every call into [`Recorder`].
+pub struct RecordPin<'a, P, const N: usize> {
+    recorder: &'a Recorder<N>,
+    line: Line,
+    pin: P,
+}
+
+impl<'a, P, const N: usize> RecordPin<'a, P, N> {
+    /// Wraps `pin` connected to given bus `line`.
+    pub fn new(recorder: &'a Recorder<N>, line: Line, pin: P) -> RecordPin<'a, P, N> {
+        RecordPin {
+            recorder,
+            line,
+            pin,
+        }
+    }
+
+    /// Gives back original pin.
+    pub fn release(self) -> P {
+        self.pin
+    }
+}
+
+impl<P: OutputPin, const N: usize> OutputPin for RecordPin<'_, P, N> {
+    type Error = P::Error;
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.recorder.record(self.line, Action::Set(false));
+        self.pin.set_low()
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.recorder.record(self.line, Action::Set(true));
+        self.pin.set_high()
+    }
+}
+
+impl<P: InputPin, const N: usize> InputPin for RecordPin<'_, P, N> {
+    type Error = P::Error;
+
+    fn is_high(&self) -> Result<bool, Self::Error> {
+        let high = self.pin.is_high()?;
+        self.recorder.record(self.line, Action::Read(high));
+        Ok(high)
+    }
+
+    fn is_low(&self) -> Result<bool, Self::Error> {
+        self.is_high().map(|high| !high)
+    }
+}
+
+#[cfg(all(test, feature = "clkdiostb"))]
+mod tests {
+    use super::*;
+    use crate::sim::{SimChip, SimPin};
+    use crate::*;
+
+    extern crate std;
+    use std::string::String;
+
+    #[test]
+    fn record_3wire_test() {
+        let chip = RefCell::new(SimChip::tm1638());
+        let rec: Recorder<64> = Recorder::new();
+        let mut dio = RecordPin::new(&rec, Line::Dio, SimPin::dio(&chip));
+        let mut clk = RecordPin::new(&rec, Line::Clk, SimPin::clk(&chip));
+        let mut stb = RecordPin::new(&rec, Line::Stb, SimPin::stb(&chip));
+        tm_send_bytes_3wire(
+            &mut dio,
+            &mut clk,
+            &mut stb,
+            &mut rec.delay(|_| {}),
+            2,
+            &[COM_DISPLAY_ON],
+        )
+        .unwrap();
+
+        // STB low, 8 x (CLK, DIO, CLK), STB, CLK and DIO high
+        assert_eq!(rec.len(), 1 + 8 * 3 + 3);
+        assert_eq!(rec.dropped(), 0);
+        assert_eq!(rec.now_us(), (2 + 8 * 3 + 1) * 2);
+        assert_eq!(
+            rec.event(0),
+            Some(Event {
+                time_us: 2,
+                line: Line::Stb,
+                action: Action::Set(false)
+            })
+        );
+        assert!(chip.borrow().is_display_on());
+
+        let mut vcd = String::new();
+        rec.write_vcd(&mut vcd).unwrap();
+        assert!(vcd.starts_with("$timescale 1us $end\n"));
+        assert!(vcd.contains("$var wire 1 # stb $end\n"));
+        assert!(vcd.contains("#2\n0#\n#4\n0!\n"));
+        assert!(vcd.ends_with("1!\n1\"\n#54\n"));
+    }
+
+    #[test]
+    fn overflow_test() {
+        let rec: Recorder<2> = Recorder::new();
+        for _ in 0..3 {
+            rec.record(Line::Clk, Action::Read(true));
+        }
+        assert_eq!(rec.len(), 2);
+        assert_eq!(rec.dropped(), 1);
+        assert_eq!(rec.event(2), None);
+    }
+}
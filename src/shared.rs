@@ -0,0 +1,596 @@
+//! Several chips on the same bus lines.
+//!
+//! 3 wire chips could share CLK and DIO while each one has its own STB,
+//! chip ignores everything on the bus until its STB goes low.
+//! [`SharedBus`] owns shared pins and gives out [`SharedPin`] copies of them,
+//! so every chip gets its own driver or [`SharedDevice`] handle.
+//!
+//! ```ignore
+//! let bus = SharedBus::new(dio, clk);
+//! let mut left = Tm1638::new(bus.dio(), bus.clk(), stb1, delay_us, TM1638_TIMING);
+//! let mut right = bus.device(stb2, delay_us, TM1638_TIMING);
+//! ```
+//!
+//! 2 wire chips like TM1637 could share CLK while each one has its own DIO.
+//! Start and stop conditions happen only on DIO that is toggled, other chips just see few clock pulses.
+//! [`SharedClkBus`] owns CLK with all DIO pins and is also able to update all chips at once.
+//!
+//! Pins are kept inside of [`RefCell`], thus all drivers must live in the same execution context.
+//! Bus transaction is not interrupted by other chips as long as you do not call them from interrupt handlers.
+use super::*;
+
+use core::cell::RefCell;
+
+/// Pin that is shared between several drivers, see [`SharedBus`].
+///
+/// It is an output pin for anything that implements [`DioOut`] and DIO line for anything that implements [`Dio`].
+pub struct SharedPin<'a, P> {
+    pin: &'a RefCell<P>,
+}
+
+impl<'a, P> SharedPin<'a, P> {
+    /// Shares pin that is kept in [`RefCell`].
+    pub fn new(pin: &'a RefCell<P>) -> SharedPin<'a, P> {
+        SharedPin { pin }
+    }
+}
+
+impl<P> Clone for SharedPin<'_, P> {
+    fn clone(&self) -> Self {
+        SharedPin { pin: self.pin }
+    }
+}
+
+// Plain pins are `DioOut` and `Dio` as well, so CLK and STB fit here as is
+// while data lines could be `SplitDio`, `OpenDrain` and so on.
+impl<P: DioOut> OutputPin for SharedPin<'_, P> {
+    type Error = P::Error;
+
+    fn set_low(&mut self) -> Result<(), P::Error> {
+        self.pin.borrow_mut().set_dio(false)
+    }
+
+    fn set_high(&mut self) -> Result<(), P::Error> {
+        self.pin.borrow_mut().set_dio(true)
+    }
+}
+
+impl<P: Dio> InputPin for SharedPin<'_, P> {
+    type Error = P::Error;
+
+    fn is_high(&self) -> Result<bool, P::Error> {
+        self.pin.borrow_mut().is_dio_high()
+    }
+
+    fn is_low(&self) -> Result<bool, P::Error> {
+        self.is_high().map(|high| !high)
+    }
+}
+
+/// CLK and DIO lines shared by several 3 wire chips with separate STB lines.
+#[cfg(feature = "clkdiostb")]
+pub struct SharedBus<DIO, CLK> {
+    dio: RefCell<DIO>,
+    clk: RefCell<CLK>,
+}
+
+#[cfg(feature = "clkdiostb")]
+impl<DIO, CLK> SharedBus<DIO, CLK> {
+    /// Takes ownership of shared pins without touching them.
+    pub fn new(dio: DIO, clk: CLK) -> SharedBus<DIO, CLK> {
+        SharedBus {
+            dio: RefCell::new(dio),
+            clk: RefCell::new(clk),
+        }
+    }
+
+    /// DIO pin for one more driver.
+    pub fn dio(&self) -> SharedPin<'_, DIO> {
+        SharedPin::new(&self.dio)
+    }
+
+    /// CLK pin for one more driver.
+    pub fn clk(&self) -> SharedPin<'_, CLK> {
+        SharedPin::new(&self.clk)
+    }
+
+    /// Handle of the chip that is selected by given STB pin.
+    ///
+    /// Arguments:
+    ///  - `stb` - STB pin of this chip
+    ///  - `delay_us` - closure that provides delay functionality
+    ///  - `timing` - bus timing or single delay value in us, see [`TM1638_TIMING`]
+    pub fn device<STB, D, T: Into<BusTiming>>(
+        &self,
+        stb: STB,
+        delay_us: D,
+        timing: T,
+    ) -> SharedDevice<'_, DIO, CLK, STB, D> {
+        SharedDevice {
+            dio: self.dio(),
+            clk: self.clk(),
+            stb,
+            delay_us,
+            timing: timing.into(),
+        }
+    }
+
+    /// Gives back shared pins, all handles have to be dropped before.
+    pub fn release(self) -> (DIO, CLK) {
+        (self.dio.into_inner(), self.clk.into_inner())
+    }
+}
+
+/// One chip on [`SharedBus`], it owns STB pin and delay closure.
+#[cfg(feature = "clkdiostb")]
+pub struct SharedDevice<'a, DIO, CLK, STB, D> {
+    dio: SharedPin<'a, DIO>,
+    clk: SharedPin<'a, CLK>,
+    stb: STB,
+    delay_us: D,
+    timing: BusTiming,
+}
+
+#[cfg(feature = "clkdiostb")]
+impl<'a, DIO, CLK, STB, D, E, EC, ES> SharedDevice<'a, DIO, CLK, STB, D>
+where
+    DIO: Dio<Error = E>,
+    CLK: OutputPin<Error = EC>,
+    STB: OutputPin<Error = ES>,
+    D: BusDelay,
+{
+    /// Send bytes to this chip, see [`tm_send_bytes_3wire`].
+    pub fn send(&mut self, bytes: &[u8]) -> Result<(), TmError<E, EC, ES>> {
+        tm_send_bytes_3wire(
+            &mut self.dio,
+            &mut self.clk,
+            &mut self.stb,
+            &mut self.delay_us,
+            self.timing,
+            bytes,
+        )
+    }
+
+    /// Read key scan bytes from this chip into response slice, see [`tm_read_bytes_into_3wire`].
+    #[cfg(feature = "keys")]
+    pub fn read_bytes(&mut self, response: &mut [u8]) -> Result<(), TmError<E, EC, ES>> {
+        tm_read_bytes_into_3wire(
+            &mut self.dio,
+            &mut self.clk,
+            &mut self.stb,
+            &mut self.delay_us,
+            self.timing,
+            COM_DATA_READ,
+            response,
+        )
+    }
+
+    /// Destroys handle and gives back STB pin and delay closure.
+    pub fn release(self) -> (STB, D) {
+        (self.stb, self.delay_us)
+    }
+}
+
+/// CLK line shared by `N` 2 wire chips with separate DIO lines.
+#[cfg(feature = "clkdio")]
+pub struct SharedClkBus<DIO, CLK, const N: usize> {
+    dios: [RefCell<DIO>; N],
+    clk: RefCell<CLK>,
+}
+
+#[cfg(feature = "clkdio")]
+impl<DIO, CLK, const N: usize> SharedClkBus<DIO, CLK, N> {
+    /// Takes ownership of DIO pins (one for every chip) and shared CLK without touching them.
+    pub fn new(dios: [DIO; N], clk: CLK) -> SharedClkBus<DIO, CLK, N> {
+        SharedClkBus {
+            dios: dios.map(RefCell::new),
+            clk: RefCell::new(clk),
+        }
+    }
+
+    /// DIO pin of chip at `index` for its own driver.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is not less than `N`.
+    pub fn dio(&self, index: usize) -> SharedPin<'_, DIO> {
+        SharedPin::new(&self.dios[index])
+    }
+
+    /// CLK pin for one more driver.
+    pub fn clk(&self) -> SharedPin<'_, CLK> {
+        SharedPin::new(&self.clk)
+    }
+
+    /// Handle of chip at `index`.
+    ///
+    /// Arguments:
+    ///  - `index` - position of chip DIO pin, it panics if `index` is not less than `N`
+    ///  - `delay_us` - closure that provides delay functionality
+    ///  - `timing` - bus timing or single delay value in us, see [`TM1637_TIMING`]
+    pub fn module<D, T: Into<BusTiming>>(
+        &self,
+        index: usize,
+        delay_us: D,
+        timing: T,
+    ) -> SharedModule<'_, DIO, CLK, D> {
+        SharedModule {
+            dio: self.dio(index),
+            clk: self.clk(),
+            delay_us,
+            timing: timing.into(),
+        }
+    }
+
+    /// Gives back DIO pins and CLK, all handles have to be dropped before.
+    pub fn release(self) -> ([DIO; N], CLK) {
+        (self.dios.map(RefCell::into_inner), self.clk.into_inner())
+    }
+}
+
+#[cfg(feature = "clkdio")]
+impl<DIO, CLK, E, EC, const N: usize> SharedClkBus<DIO, CLK, N>
+where
+    DIO: Dio<Error = E>,
+    CLK: OutputPin<Error = EC>,
+{
+    /// Sends bytes to all chips in one clock sequence, every DIO line gets its own bytes.
+    ///
+    /// Use it for writing only, all slices must have the same length.
+    /// ACK is checked on every line, [`TmError::Ack`] tells index of the first chip that failed.
+    ///
+    /// Arguments:
+    ///  - `delay_us` - closure that provides delay functionality
+    ///  - `timing` - bus timing or single delay value in us, see [`TM1637_TIMING`]
+    ///  - `bytes` - slices of bytes to send, one for every chip
+    pub fn send_parallel<D, T>(
+        &self,
+        delay_us: &mut D,
+        timing: T,
+        bytes: [&[u8]; N],
+    ) -> Result<(), TmError<E, EC>>
+    where
+        D: BusDelay,
+        T: Into<BusTiming>,
+    {
+        self.send_parallel_retry(delay_us, timing, bytes, &RetryPolicy::default(), &mut 0)
+    }
+
+    /// Same as [`send_parallel`](SharedClkBus::send_parallel) but repeats transfer
+    /// when some chip does not respond with ACK, see [`tm_send_bytes_2wire_retry`].
+    ///
+    /// Arguments:
+    ///  - `delay_us` - closure that provides delay functionality
+    ///  - `timing` - bus timing or single delay value in us
+    ///  - `bytes` - slices of bytes to send, one for every chip
+    ///  - `policy` - number of attempts, backoff, recovery settings and ACK polls
+    ///  - `failed_attempts` - incremented on every attempt that failed because of ACK
+    pub fn send_parallel_retry<D, T>(
+        &self,
+        delay_us: &mut D,
+        timing: T,
+        bytes: [&[u8]; N],
+        policy: &RetryPolicy,
+        failed_attempts: &mut u32,
+    ) -> Result<(), TmError<E, EC>>
+    where
+        D: BusDelay,
+        T: Into<BusTiming>,
+    {
+        let timing = timing.into();
+        let len = bytes.first().map_or(0, |b| b.len());
+        if len == 0 || bytes.iter().any(|b| b.len() != len) {
+            return Err(TmError::Input);
+        }
+
+        let mut clk = self.clk.borrow_mut();
+        let mut lines = Parallel {
+            dios: &self.dios,
+            clk: &mut *clk,
+        };
+        bus::retry(
+            &mut lines,
+            delay_us,
+            &timing,
+            policy,
+            failed_attempts,
+            |lines, delay_us| {
+                let mut seq = bus::Sequence::write_2wire(&timing, policy.ack_polls, &bytes)
+                    .ok_or(TmError::Input)?;
+                bus::run(lines, delay_us, &mut seq)
+            },
+        )
+    }
+}
+
+/// All DIO lines of [`SharedClkBus`] with CLK, every line is driven with bytes of its own chip.
+#[cfg(feature = "clkdio")]
+struct Parallel<'b, DIO, CLK, const N: usize> {
+    dios: &'b [RefCell<DIO>; N],
+    clk: &'b mut CLK,
+}
+
+#[cfg(feature = "clkdio")]
+impl<DIO, CLK, E, EC, const N: usize> bus::Lines for Parallel<'_, DIO, CLK, N>
+where
+    DIO: Dio<Error = E>,
+    CLK: OutputPin<Error = EC>,
+{
+    type DioError = E;
+    type ClkError = EC;
+    type StbError = E;
+
+    fn count(&self) -> usize {
+        N
+    }
+
+    fn set_clk(&mut self, high: bool) -> Result<(), TmError<E, EC>> {
+        self.clk.set_dio(high).map_err(TmError::Clk)
+    }
+
+    fn set_stb(&mut self, _: bool) -> Result<(), TmError<E, EC>> {
+        Ok(())
+    }
+
+    fn set_dio(&mut self, line: usize, high: bool) -> Result<(), TmError<E, EC>> {
+        self.dios[line]
+            .borrow_mut()
+            .set_dio(high)
+            .map_err(TmError::Dio)
+    }
+
+    fn is_dio_high(&mut self, line: usize) -> Result<bool, TmError<E, EC>> {
+        self.dios[line]
+            .borrow_mut()
+            .is_dio_high()
+            .map_err(TmError::Dio)
+    }
+}
+
+/// One chip on [`SharedClkBus`], it owns delay closure.
+#[cfg(feature = "clkdio")]
+pub struct SharedModule<'a, DIO, CLK, D> {
+    dio: SharedPin<'a, DIO>,
+    clk: SharedPin<'a, CLK>,
+    delay_us: D,
+    timing: BusTiming,
+}
+
+#[cfg(feature = "clkdio")]
+impl<'a, DIO, CLK, D, E, EC> SharedModule<'a, DIO, CLK, D>
+where
+    DIO: Dio<Error = E>,
+    CLK: OutputPin<Error = EC>,
+    D: BusDelay,
+{
+    /// Send bytes to this chip, see [`tm_send_bytes_2wire`].
+    pub fn send(&mut self, bytes: &[u8]) -> Result<(), TmError<E, EC>> {
+        tm_send_bytes_2wire(
+            &mut self.dio,
+            &mut self.clk,
+            &mut self.delay_us,
+            self.timing,
+            bytes,
+        )
+    }
+
+    /// Read key scan byte from this chip, see [`tm_read_byte_2wire`].
+    #[cfg(feature = "keys")]
+    pub fn read_byte(&mut self) -> Result<u8, TmError<E, EC>> {
+        tm_read_byte_2wire(
+            &mut self.dio,
+            &mut self.clk,
+            &mut self.delay_us,
+            self.timing,
+        )
+    }
+
+    /// Destroys handle and gives back delay closure.
+    pub fn release(self) -> D {
+        self.delay_us
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::{SimChip, SimPin};
+    use core::convert::Infallible;
+
+    /// Line that is wired to both chips.
+    struct Both<'a>(SimPin<'a>, SimPin<'a>);
+
+    impl OutputPin for Both<'_> {
+        type Error = Infallible;
+
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            self.0.set_low()?;
+            self.1.set_low()
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            self.0.set_high()?;
+            self.1.set_high()
+        }
+    }
+
+    impl InputPin for Both<'_> {
+        type Error = Infallible;
+
+        fn is_high(&self) -> Result<bool, Infallible> {
+            Ok(self.0.is_high()? && self.1.is_high()?)
+        }
+
+        fn is_low(&self) -> Result<bool, Infallible> {
+            self.is_high().map(|high| !high)
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "clkdiostb")]
+    fn shared_bus_test() {
+        use crate::sim::Line;
+        use crate::tm1638::Tm1638;
+
+        let chips = [
+            RefCell::new(SimChip::tm1638()),
+            RefCell::new(SimChip::tm1638()),
+        ];
+        let line = |l| Both(SimPin::new(&chips[0], l), SimPin::new(&chips[1], l));
+        let bus = SharedBus::new(line(Line::Dio), line(Line::Clk));
+
+        let mut left = Tm1638::new(
+            bus.dio(),
+            bus.clk(),
+            SimPin::stb(&chips[0]),
+            |_| {},
+            TM1638_TIMING,
+        );
+        let mut right = bus.device(SimPin::stb(&chips[1]), |_| {}, TM1638_TIMING);
+
+        left.init().unwrap();
+        right.send(&[COM_ADDRESS | 2, CHAR_2]).unwrap();
+        left.write_digits(&[CHAR_1]).unwrap();
+
+        assert_eq!(chips[0].borrow().ram()[..3], [CHAR_1, 0, 0]);
+        assert_eq!(chips[1].borrow().ram()[..3], [0, 0, CHAR_2]);
+        assert_eq!(chips[0].borrow().errors() + chips[1].borrow().errors(), 0);
+
+        #[cfg(feature = "keys")]
+        {
+            chips[1].borrow_mut().press_key(1, 1);
+            assert_eq!(left.read_keys().unwrap(), 0);
+            let mut response = [0_u8; TM1638_RESPONSE_SIZE as usize];
+            right.read_bytes(&mut response).unwrap();
+            assert_ne!(response, [0; 4]);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "clkdiostb")]
+    fn shared_split_dio_test() {
+        use crate::pins::SplitDio;
+
+        let chip = RefCell::new(SimChip::tm1638());
+        let bus = SharedBus::new(
+            SplitDio::new(SimPin::dio(&chip), SimPin::dio(&chip)),
+            SimPin::clk(&chip),
+        );
+        let mut device = bus.device(SimPin::stb(&chip), |_| {}, TM1638_TIMING);
+        device.send(&[COM_ADDRESS, CHAR_1]).unwrap();
+        assert_eq!(chip.borrow().ram()[0], CHAR_1);
+
+        #[cfg(feature = "keys")]
+        {
+            chip.borrow_mut().press_key(1, 1);
+            let mut response = [0_u8; TM1638_RESPONSE_SIZE as usize];
+            device.read_bytes(&mut response).unwrap();
+            assert_ne!(response, [0; 4]);
+        }
+        assert_eq!(chip.borrow().errors(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "clkdio")]
+    fn shared_module_split_dio_test() {
+        use crate::pins::SplitDio;
+
+        let chips = [
+            RefCell::new(SimChip::tm1637()),
+            RefCell::new(SimChip::tm1637()),
+        ];
+        let dio = |chip| SplitDio::new(SimPin::dio(chip), SimPin::dio(chip));
+        let bus = SharedClkBus::new(
+            [dio(&chips[0]), dio(&chips[1])],
+            Both(SimPin::clk(&chips[0]), SimPin::clk(&chips[1])),
+        );
+        let mut right = bus.module(1, |_| {}, TM1637_TIMING);
+        right.send(&[COM_ADDRESS, CHAR_1]).unwrap();
+        assert_eq!(chips[0].borrow().ram()[0], 0);
+        assert_eq!(chips[1].borrow().ram()[0], CHAR_1);
+
+        #[cfg(feature = "keys")]
+        {
+            chips[1].borrow_mut().set_key_scan(&[0b1110_1001]);
+            assert_eq!(right.read_byte().unwrap().reverse_bits(), 0b1110_1001);
+        }
+        assert_eq!(chips[0].borrow().errors() + chips[1].borrow().errors(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "clkdio")]
+    fn shared_clk_test() {
+        use crate::tm1637::Tm1637;
+
+        let chips = [
+            RefCell::new(SimChip::tm1637()),
+            RefCell::new(SimChip::tm1637()),
+        ];
+        let dios = [SimPin::dio(&chips[0]), SimPin::dio(&chips[1])];
+        let bus = SharedClkBus::new(dios, Both(SimPin::clk(&chips[0]), SimPin::clk(&chips[1])));
+
+        let mut left = Tm1637::new(bus.dio(0), bus.clk(), |_| {}, TM1637_TIMING);
+        let mut right = bus.module(1, |_| {}, TM1637_TIMING);
+        left.init().unwrap();
+        right.send(&[COM_ADDRESS | 1, CHAR_2]).unwrap();
+        assert_eq!(chips[0].borrow().ram()[..2], [0, 0]);
+        assert_eq!(chips[1].borrow().ram()[..2], [0, CHAR_2]);
+
+        let mut sequential_us = 0_u32;
+        let mut delay = |us: u16| sequential_us += us as u32;
+        let frames = [[COM_ADDRESS, CHAR_3, CHAR_4], [COM_ADDRESS, CHAR_5, CHAR_6]];
+        for (index, frame) in frames.iter().enumerate() {
+            bus.module(index, &mut delay, TM1637_TIMING)
+                .send(frame)
+                .unwrap();
+        }
+
+        let mut parallel_us = 0_u32;
+        let mut delay = |us: u16| parallel_us += us as u32;
+        let frames = [[COM_ADDRESS, CHAR_1, CHAR_2], [COM_ADDRESS, CHAR_7, CHAR_8]];
+        bus.send_parallel(&mut delay, TM1637_TIMING, [&frames[0], &frames[1]])
+            .unwrap();
+        assert_eq!(chips[0].borrow().ram()[..2], frames[0][1..]);
+        assert_eq!(chips[1].borrow().ram()[..2], frames[1][1..]);
+        assert_eq!(chips[0].borrow().errors() + chips[1].borrow().errors(), 0);
+        assert_eq!(parallel_us * 2, sequential_us);
+
+        let result = bus.send_parallel(&mut |_| {}, 1, [&frames[0], &frames[1][..1]]);
+        assert!(matches!(result, Err(TmError::Input)));
+
+        #[cfg(feature = "keys")]
+        {
+            chips[1].borrow_mut().set_key_scan(&[0b1110_1001]);
+            assert_eq!(left.read_keys().unwrap(), 0xFF);
+            assert_eq!(right.read_byte().unwrap().reverse_bits(), 0b1110_1001);
+        }
+
+        // Second module has no ACK, error tells which one it is
+        let chips = [
+            RefCell::new(SimChip::tm1637()),
+            RefCell::new(SimChip::tm1640()),
+        ];
+        let dios = [SimPin::dio(&chips[0]), SimPin::dio(&chips[1])];
+        let clk = Both(SimPin::clk(&chips[0]), SimPin::clk(&chips[1]));
+        let bus = SharedClkBus::new(dios, clk);
+        let result = bus.send_parallel(&mut |_| {}, 1, [&[COM_DISPLAY_ON], &[COM_DISPLAY_ON]]);
+        assert!(matches!(
+            result,
+            Err(TmError::Ack {
+                byte: 0,
+                phase: AckPhase::Falling8,
+                line: 1
+            })
+        ));
+
+        let policy = RetryPolicy {
+            ack_polls: 1,
+            ..RetryPolicy::new(2, 0)
+        };
+        let mut failed = 0;
+        let frames: [&[u8]; 2] = [&[COM_DISPLAY_ON], &[COM_DISPLAY_ON]];
+        let result = bus.send_parallel_retry(&mut |_| {}, 1, frames, &policy, &mut failed);
+        assert!(matches!(result, Err(TmError::Ack { line: 1, .. })));
+        assert_eq!(failed, 2);
+    }
+}
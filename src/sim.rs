@@ -0,0 +1,568 @@
+//! Virtual TM1637 / TM1638 / TM1640 / TM16xx chip for testing your code on host without hardware (**sim** feature).
+//!
+//! [`SimChip`] watches pin levels and decodes bus framing the same way real chip does:
+//! start/stop conditions and ACK for 2 wire interface, STB framing for 3 wire interface.
+//! It keeps display memory, brightness and on/off state, and answers key scan requests
+//! with key state that you inject from your test.
+//! LED port and switch inputs of PT6311/PT6312 are simulated as well.
+//!
+//! Chip is shared through [`RefCell`], so one chip could serve several [`SimPin`]s at once:
+//!
+//! ```
+//! # #[cfg(feature = "clkdio")] {
+//! # use core::cell::RefCell;
+//! # use tmledkey_hal_drv::sim::{SimChip, SimPin};
+//! # use tmledkey_hal_drv::tm1637::Tm1637;
+//! # use tmledkey_hal_drv::*;
+//! let chip = RefCell::new(SimChip::tm1637());
+//! let mut tm = Tm1637::new(SimPin::dio(&chip), SimPin::clk(&chip), |_| {}, 0);
+//! tm.init().unwrap();
+//! tm.write_digits(&[CHAR_1, CHAR_2]).unwrap();
+//! assert_eq!(&chip.borrow().ram()[..2], &[CHAR_1, CHAR_2]);
+//! # }
+//! ```
+use core::cell::RefCell;
+use core::convert::Infallible;
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+
+#[cfg(feature = "clkdiostb")]
+use super::tm16xx::Chip;
+use super::{COM_ADDRESS, COM_DATA, COM_DISPLAY, DISPLAY_BRIGHTNESS_MASK};
+
+/// Largest display memory among simulated chips.
+const SIM_RAM_SIZE: usize = 48;
+/// Largest key scan response among simulated chips.
+const SIM_KEY_BYTES: usize = 6;
+/// Data setting command low bits that select LED port writing (PT6311, PT6312).
+const PORT_LED: u8 = 0b01;
+/// Data setting command low bits that select switch inputs reading (PT6311, PT6312).
+const PORT_SWITCH: u8 = 0b11;
+
+/// Bus line that [`SimPin`] is connected to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Line {
+    Dio,
+    Clk,
+    Stb,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Waiting for start condition or STB going low
+    Idle,
+    /// Receiving byte bits on CLK rising edges
+    Receive,
+    /// Byte received, ACK starts at next CLK falling edge (2 wire only)
+    AckWait { read: bool },
+    /// Chip holds DIO low till next CLK falling edge (2 wire only)
+    AckHold { read: bool },
+    /// Chip sends key scan bits on CLK falling edges
+    Transmit { sent: usize },
+    /// Key scan byte sent, chip holds DIO low till next CLK falling edge (2 wire only)
+    TransmitAck,
+}
+
+/// Virtual chip state machine, see [module documentation](self).
+#[derive(Debug, Clone)]
+pub struct SimChip {
+    two_wire: bool,
+    ack: bool,
+    ram_size: usize,
+    key_bytes: usize,
+    ram: [u8; SIM_RAM_SIZE],
+    key_scan: [u8; SIM_KEY_BYTES],
+    leds: u8,
+    switches: u8,
+    port: u8,
+    brightness: u8,
+    display_on: bool,
+    display_mode: u8,
+    fixed_address: bool,
+    address: u8,
+    clk: bool,
+    stb: bool,
+    mcu_dio: bool,
+    chip_dio: bool,
+    state: State,
+    byte: u8,
+    bits: u8,
+    command_expected: bool,
+    address_set: bool,
+    transactions: u32,
+    errors: u32,
+}
+
+impl SimChip {
+    fn new(two_wire: bool, ram_size: usize, key_bytes: usize) -> SimChip {
+        SimChip {
+            two_wire,
+            ack: two_wire,
+            ram_size,
+            key_bytes,
+            ram: [0; SIM_RAM_SIZE],
+            key_scan: [0; SIM_KEY_BYTES],
+            leds: 0,
+            switches: 0,
+            port: 0,
+            brightness: 0,
+            display_on: false,
+            display_mode: 0,
+            fixed_address: false,
+            address: 0,
+            clk: true,
+            stb: true,
+            mcu_dio: true,
+            chip_dio: true,
+            state: State::Idle,
+            byte: 0,
+            bits: 0,
+            command_expected: false,
+            address_set: false,
+            transactions: 0,
+            errors: 0,
+        }
+    }
+
+    /// TM1637: 2 wire interface with ACK, 6 bytes of display memory, 1 key scan byte.
+    /// No pressed keys are reported as `0xFF` key code.
+    pub fn tm1637() -> SimChip {
+        let mut chip = SimChip::new(true, 6, 1);
+        chip.key_scan[0] = 0xFF;
+        chip
+    }
+
+    /// TM1640: 2 wire interface without ACK, 16 bytes of display memory, no keys.
+    pub fn tm1640() -> SimChip {
+        let mut chip = SimChip::new(true, 16, 0);
+        chip.ack = false;
+        chip
+    }
+
+    /// TM1638: 3 wire interface, 16 bytes of display memory, 4 key scan bytes.
+    pub fn tm1638() -> SimChip {
+        SimChip::new(false, 16, 4)
+    }
+
+    /// 3 wire chip described by [`Chip`] profile: TM1628, TM1629, PT6312 and so on.
+    #[cfg(feature = "clkdiostb")]
+    pub fn tm16xx<C: Chip>() -> SimChip {
+        SimChip::new(false, C::RAM_SIZE as usize, C::KEY_BYTES as usize)
+    }
+
+    /// Display memory content.
+    pub fn ram(&self) -> &[u8] {
+        &self.ram[..self.ram_size]
+    }
+
+    /// Brightness level from the last display control command.
+    pub fn brightness(&self) -> u8 {
+        self.brightness
+    }
+
+    /// Display on/off state from the last display control command.
+    pub fn is_display_on(&self) -> bool {
+        self.display_on
+    }
+
+    /// Low bits of the last display mode setting command.
+    pub fn display_mode(&self) -> u8 {
+        self.display_mode
+    }
+
+    /// Number of completed bus transactions (stop condition or STB going high).
+    pub fn transactions(&self) -> u32 {
+        self.transactions
+    }
+
+    /// Number of protocol violations: incomplete bytes, writes outside of display memory etc.
+    pub fn errors(&self) -> u32 {
+        self.errors
+    }
+
+    /// Sets key scan bytes that chip would send back, in datasheet bit order.
+    /// Missing bytes are treated as zeros, extra bytes are ignored.
+    pub fn set_key_scan(&mut self, bytes: &[u8]) {
+        self.key_scan = [0; SIM_KEY_BYTES];
+        let len = bytes.len().min(self.key_bytes);
+        self.key_scan[..len].copy_from_slice(&bytes[..len]);
+    }
+
+    /// LED port state from the last LED port write.
+    pub fn leds(&self) -> u8 {
+        self.leds
+    }
+
+    /// Sets switch inputs byte that chip would send back, SW1 is the lowest bit.
+    pub fn set_switches(&mut self, switches: u8) {
+        self.switches = switches;
+    }
+
+    /// Marks TM1638 key at K`k` (1-3) and KS`ks` (1-8) lines as pressed.
+    /// Result is what [`decode_keys`](super::tm1638::decode_keys) expects to see.
+    pub fn press_key(&mut self, k: u8, ks: u8) {
+        if !(1..=3).contains(&k) || !(1..=8).contains(&ks) {
+            return;
+        }
+        let index = ((ks - 1) / 2) as usize;
+        let shift = ((ks - 1) % 2) * 4 + (3 - k);
+        if index < self.key_bytes {
+            self.key_scan[index] |= 1 << shift;
+        }
+    }
+
+    /// Current DIO line level: low if either MCU or chip pulls it down.
+    pub fn dio_level(&self) -> bool {
+        self.mcu_dio && self.chip_dio
+    }
+
+    /// Applies pin level change made by MCU.
+    pub fn drive(&mut self, line: Line, high: bool) {
+        match line {
+            Line::Clk => self.set_clk(high),
+            Line::Dio => self.set_dio(high),
+            Line::Stb => self.set_stb(high),
+        }
+    }
+
+    /// Line level as MCU would read it.
+    pub fn level(&self, line: Line) -> bool {
+        match line {
+            Line::Clk => self.clk,
+            Line::Dio => self.dio_level(),
+            Line::Stb => self.stb,
+        }
+    }
+
+    fn set_clk(&mut self, high: bool) {
+        if self.clk == high {
+            return;
+        }
+        self.clk = high;
+        if high {
+            self.clk_rising();
+        } else {
+            self.clk_falling();
+        }
+    }
+
+    fn set_dio(&mut self, high: bool) {
+        let before = self.dio_level();
+        self.mcu_dio = high;
+        let after = self.dio_level();
+
+        // Start and stop conditions are DIO edges while CLK is high
+        let framing = matches!(self.state, State::Idle | State::Receive) && self.bits < 8;
+        if self.two_wire && self.clk && before != after && framing {
+            if !after {
+                self.begin();
+            } else {
+                self.end();
+            }
+        }
+    }
+
+    fn set_stb(&mut self, high: bool) {
+        if self.two_wire || self.stb == high {
+            return;
+        }
+        self.stb = high;
+        if high {
+            self.chip_dio = true;
+            self.end();
+        } else {
+            self.begin();
+        }
+    }
+
+    fn begin(&mut self) {
+        if self.state != State::Idle {
+            // Repeated start, previous transaction is not finished
+            self.errors += 1;
+        }
+        self.state = State::Receive;
+        self.bits = 0;
+        self.byte = 0;
+        self.command_expected = true;
+        self.address_set = false;
+    }
+
+    fn end(&mut self) {
+        if self.state == State::Idle {
+            return;
+        }
+        // Stop right after the last ACK has one stray clock, that's fine
+        if self.bits > 1 || !matches!(self.state, State::Receive | State::Transmit { .. }) {
+            self.errors += 1;
+        }
+        self.state = State::Idle;
+        self.transactions += 1;
+    }
+
+    fn clk_rising(&mut self) {
+        if self.state != State::Receive || self.bits >= 8 {
+            return;
+        }
+        if self.dio_level() {
+            self.byte |= 1 << self.bits;
+        }
+        self.bits += 1;
+        if self.bits < 8 {
+            return;
+        }
+
+        let read = self.process_byte(self.byte);
+        if self.ack {
+            self.state = State::AckWait { read };
+        } else {
+            self.bits = 0;
+            self.byte = 0;
+            if read {
+                self.state = State::Transmit { sent: 0 };
+            }
+        }
+    }
+
+    fn clk_falling(&mut self) {
+        match self.state {
+            State::AckWait { read } => {
+                self.chip_dio = false;
+                self.state = State::AckHold { read };
+            }
+            State::AckHold { read } => {
+                self.bits = 0;
+                self.byte = 0;
+                if read {
+                    // First key scan bit replaces ACK right at 9th falling edge
+                    self.send_bit(0);
+                    self.state = State::Transmit { sent: 1 };
+                } else {
+                    self.chip_dio = true;
+                    self.state = State::Receive;
+                }
+            }
+            State::Transmit { sent } => {
+                if sent < self.response_len() * 8 {
+                    self.send_bit(sent);
+                    self.state = State::Transmit { sent: sent + 1 };
+                } else if self.two_wire {
+                    self.chip_dio = false;
+                    self.state = State::TransmitAck;
+                } else {
+                    self.chip_dio = true;
+                }
+            }
+            State::TransmitAck => {
+                self.chip_dio = true;
+                self.state = State::Receive;
+            }
+            State::Idle | State::Receive => {}
+        }
+    }
+
+    fn response_len(&self) -> usize {
+        if self.port == PORT_SWITCH {
+            1
+        } else {
+            self.key_bytes
+        }
+    }
+
+    fn send_bit(&mut self, index: usize) {
+        let byte = if self.port == PORT_SWITCH {
+            self.switches
+        } else {
+            self.key_scan[index / 8]
+        };
+        self.chip_dio = (byte >> (index % 8)) & 0b1 != 0;
+    }
+
+    /// Returns true if key scan data should be sent back.
+    fn process_byte(&mut self, byte: u8) -> bool {
+        if !self.command_expected {
+            if self.port == PORT_LED {
+                self.leds = byte;
+                return false;
+            }
+            if !self.address_set || self.address as usize >= self.ram_size {
+                self.errors += 1;
+                return false;
+            }
+            self.ram[self.address as usize] = byte;
+            if !self.fixed_address {
+                self.address += 1;
+            }
+            return false;
+        }
+
+        self.command_expected = false;
+        match byte & 0b1100_0000 {
+            COM_DATA => {
+                self.fixed_address = byte & 0b100 != 0;
+                self.port = byte & 0b11;
+                return byte & 0b10 != 0;
+            }
+            COM_DISPLAY => {
+                self.display_on = byte & 0b1000 != 0;
+                self.brightness = byte & DISPLAY_BRIGHTNESS_MASK;
+            }
+            COM_ADDRESS => {
+                self.address = byte & 0b0011_1111;
+                self.address_set = true;
+            }
+            _ => self.display_mode = byte & 0b1111,
+        }
+        false
+    }
+}
+
+/// Pin connected to one line of [`SimChip`].
+/// It implements both input and output traits, so it fits DIO as well as CLK and STB.
+pub struct SimPin<'a> {
+    chip: &'a RefCell<SimChip>,
+    line: Line,
+}
+
+impl<'a> SimPin<'a> {
+    /// Pin connected to given line of chip.
+    pub fn new(chip: &'a RefCell<SimChip>, line: Line) -> SimPin<'a> {
+        SimPin { chip, line }
+    }
+
+    /// Pin connected to DIO line.
+    pub fn dio(chip: &'a RefCell<SimChip>) -> SimPin<'a> {
+        SimPin::new(chip, Line::Dio)
+    }
+
+    /// Pin connected to CLK line.
+    pub fn clk(chip: &'a RefCell<SimChip>) -> SimPin<'a> {
+        SimPin::new(chip, Line::Clk)
+    }
+
+    /// Pin connected to STB line.
+    pub fn stb(chip: &'a RefCell<SimChip>) -> SimPin<'a> {
+        SimPin::new(chip, Line::Stb)
+    }
+}
+
+impl OutputPin for SimPin<'_> {
+    type Error = Infallible;
+
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        self.chip.borrow_mut().drive(self.line, false);
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        self.chip.borrow_mut().drive(self.line, true);
+        Ok(())
+    }
+}
+
+impl InputPin for SimPin<'_> {
+    type Error = Infallible;
+
+    fn is_high(&self) -> Result<bool, Infallible> {
+        Ok(self.chip.borrow().level(self.line))
+    }
+
+    fn is_low(&self) -> Result<bool, Infallible> {
+        Ok(!self.chip.borrow().level(self.line))
+    }
+}
+
+#[cfg(all(test, any(feature = "clkdio", feature = "clkdiostb")))]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    #[test]
+    #[cfg(feature = "clkdio")]
+    fn tm1637_write_test() {
+        let chip = RefCell::new(SimChip::tm1637());
+        let mut tm = tm1637::Tm1637::new(SimPin::dio(&chip), SimPin::clk(&chip), |_| {}, 0);
+        tm.init().unwrap();
+        assert!(chip.borrow().is_display_on());
+        assert_eq!(chip.borrow().brightness(), 7);
+
+        tm.write_digits_at(2, &[CHAR_1, CHAR_2, CHAR_3]).unwrap();
+        tm.set_brightness(3).unwrap();
+        assert_eq!(chip.borrow().brightness(), 3);
+        tm.display_off().unwrap();
+
+        let chip = chip.borrow();
+        assert_eq!(chip.ram(), &[0, 0, CHAR_1, CHAR_2, CHAR_3, 0]);
+        assert!(!chip.is_display_on());
+        assert_eq!(chip.transactions(), 6);
+        assert_eq!(chip.errors(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "clkdio")]
+    fn tm1637_overflow_test() {
+        let chip = RefCell::new(SimChip::tm1637());
+        let (mut dio, mut clk) = (SimPin::dio(&chip), SimPin::clk(&chip));
+        tm_send_bytes_2wire(&mut dio, &mut clk, &mut |_| {}, 0, &[COM_ADDRESS | 5, 1, 2]).unwrap();
+        assert_eq!(chip.borrow().ram()[5], 1);
+        assert_eq!(chip.borrow().errors(), 1);
+    }
+
+    #[test]
+    #[cfg(all(feature = "clkdio", feature = "keys"))]
+    fn tm1637_read_test() {
+        let chip = RefCell::new(SimChip::tm1637());
+        let (mut dio, mut clk) = (SimPin::dio(&chip), SimPin::clk(&chip));
+        let mut delay = |_| {};
+        assert_eq!(
+            tm_read_byte_2wire(&mut dio, &mut clk, &mut delay, 0).unwrap(),
+            0xFF
+        );
+
+        chip.borrow_mut().set_key_scan(&[0b1110_1001]);
+        let read = tm_read_byte_2wire(&mut dio, &mut clk, &mut delay, 0).unwrap();
+        // Bus reads MSB first while chip sends LSB first
+        assert_eq!(read.reverse_bits(), 0b1110_1001);
+        assert_eq!(chip.borrow().errors(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "clkdiostb")]
+    fn tm1638_write_test() {
+        let chip = RefCell::new(SimChip::tm1638());
+        let mut tm = tm1638::Tm1638::new(
+            SimPin::dio(&chip),
+            SimPin::clk(&chip),
+            SimPin::stb(&chip),
+            |_| {},
+            0,
+        );
+        tm.init().unwrap();
+        tm.write_grid(7, CHAR_8, SEG_9).unwrap();
+        tm.send(&[COM_DATA_ADDRESS_FIXED]).unwrap();
+        tm.send(&[COM_ADDRESS | 3, 0x55]).unwrap();
+
+        let chip = chip.borrow();
+        assert_eq!(&chip.ram()[..4], &[0, 0, 0, 0x55]);
+        assert_eq!(&chip.ram()[14..], &[CHAR_8, SEG_9]);
+        assert_eq!(chip.errors(), 0);
+    }
+
+    #[test]
+    #[cfg(all(feature = "clkdiostb", feature = "keys"))]
+    fn tm1638_read_test() {
+        let chip = RefCell::new(SimChip::tm1638());
+        let mut tm = tm1638::Tm1638::new(
+            SimPin::dio(&chip),
+            SimPin::clk(&chip),
+            SimPin::stb(&chip),
+            |_| {},
+            0,
+        );
+        tm.init().unwrap();
+        assert_eq!(tm.read_keys().unwrap(), 0);
+
+        chip.borrow_mut().press_key(1, 2);
+        chip.borrow_mut().press_key(3, 8);
+        assert_eq!(tm.read_keys().unwrap(), 1 << 1 | 1 << 23);
+        assert_eq!(chip.borrow().errors(), 0);
+    }
+}
@@ -0,0 +1,279 @@
+//! 3 wire interface over hardware SPI peripheral.
+//!
+//! TM1638, TM1628 and similar chips shift data LSB first on rising CLK edge while STB is low,
+//! which is SPI mode 3 with STB used as chip select.
+//! Peripheral does the clocking, thus there are no delay calls for every bit like in [`tm_send_bytes_3wire`].
+//!
+//! DIO is half-duplex: connect MOSI to DIO through a resistor (about 1k) and MISO directly to DIO,
+//! or use peripheral that supports bidirectional mode.
+//! While reading MOSI sends `0xFF` so chip is able to drive the line.
+//!
+//! SPI peripheral and STB pin keep their own error types, SPI errors are reported as [`TmError::Bus`]
+//! and STB errors as [`TmError::Stb`].
+use super::*;
+
+use embedded_hal::blocking::spi::{Transfer, Write};
+
+/// Bit order that SPI peripheral is configured for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitOrder {
+    /// Chips expect this one, bytes are sent as is
+    LsbFirst,
+    /// For peripherals without LSB first mode, every byte is reversed in software
+    MsbFirst,
+}
+
+/// Size of stack buffer used to reverse bytes before sending.
+const CHUNK_SIZE: usize = 16;
+
+/// 3 wire interface (DIO,CLK,STB) on top of SPI peripheral in mode 3 and STB pin.
+pub struct TmSpi<SPI, STB, D> {
+    spi: SPI,
+    stb: STB,
+    delay_us: D,
+    timing: BusTiming,
+    bit_order: BitOrder,
+}
+
+impl<SPI, STB, D, E, ES> TmSpi<SPI, STB, D>
+where
+    SPI: Write<u8, Error = E> + Transfer<u8, Error = E>,
+    STB: OutputPin<Error = ES>,
+    D: BusDelay,
+{
+    /// Creates interface without touching the bus.
+    ///
+    /// Arguments:
+    ///  - `spi` - SPI peripheral in mode 3 (CPOL=1, CPHA=1), up to 1 MHz for TM1638
+    ///  - `stb` - MCU pin used as chip select
+    ///  - `delay_us` - closure that provides delay functionality, used around STB and before reading
+    ///  - `timing` - bus timing, only `stb_hold_us` and `ack_wait_us` are used here
+    ///  - `bit_order` - bit order that peripheral is configured for
+    pub fn new<T: Into<BusTiming>>(
+        spi: SPI,
+        stb: STB,
+        delay_us: D,
+        timing: T,
+        bit_order: BitOrder,
+    ) -> TmSpi<SPI, STB, D> {
+        TmSpi {
+            spi,
+            stb,
+            delay_us,
+            timing: timing.into(),
+            bit_order,
+        }
+    }
+
+    /// Send bytes, same as [`tm_send_bytes_3wire`] does.
+    ///
+    /// According to datasheet it can be single command byte or a sequence starting with command byte followed by several data bytes.
+    pub fn send(&mut self, bytes: &[u8]) -> Result<(), TmError<E, E, ES>> {
+        if bytes.is_empty() {
+            return Err(TmError::Input);
+        }
+
+        self.select()?;
+        let send = self.write(bytes);
+        let deselect = self.deselect();
+        send.and(deselect)
+    }
+
+    /// Sends read command and fills whole response slice with bytes from MCU,
+    /// same as [`tm_read_bytes_3wire`] does for [`COM_DATA_READ`].
+    #[cfg(feature = "keys")]
+    pub fn read(&mut self, command: u8, response: &mut [u8]) -> Result<(), TmError<E, E, ES>> {
+        if response.is_empty() {
+            return Err(TmError::Input);
+        }
+
+        self.select()?;
+        let mut read = self.write(&[command]);
+        if read.is_ok() {
+            // Twait between command and the first read clock
+            self.delay_us.delay_us(self.timing.ack_wait_us);
+            for byte in response.iter_mut() {
+                *byte = 0xFF;
+            }
+            read = self
+                .spi
+                .transfer(response)
+                .map(|_| ())
+                .map_err(TmError::Bus);
+        }
+        let deselect = self.deselect();
+        read.and(deselect)?;
+
+        // Keep bit banged bus order, key decoders expect MSB first bytes
+        if self.bit_order == BitOrder::LsbFirst {
+            for byte in response.iter_mut() {
+                *byte = byte.reverse_bits();
+            }
+        }
+        Ok(())
+    }
+
+    /// Destroys interface and gives back peripheral, STB pin and delay closure.
+    pub fn release(self) -> (SPI, STB, D) {
+        (self.spi, self.stb, self.delay_us)
+    }
+
+    fn select(&mut self) -> Result<(), TmError<E, E, ES>> {
+        self.delay_us.delay_us(self.timing.stb_hold_us);
+        self.stb.set_low().map_err(TmError::Stb)?;
+        self.delay_us.delay_us(self.timing.stb_hold_us);
+        Ok(())
+    }
+
+    fn deselect(&mut self) -> Result<(), TmError<E, E, ES>> {
+        self.delay_us.delay_us(self.timing.stb_hold_us);
+        self.stb.set_high().map_err(TmError::Stb)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), TmError<E, E, ES>> {
+        if self.bit_order == BitOrder::LsbFirst {
+            return self.spi.write(bytes).map_err(TmError::Bus);
+        }
+
+        let mut chunk = [0_u8; CHUNK_SIZE];
+        for part in bytes.chunks(CHUNK_SIZE) {
+            for (dst, src) in chunk.iter_mut().zip(part) {
+                *dst = src.reverse_bits();
+            }
+            self.spi.write(&chunk[..part.len()]).map_err(TmError::Bus)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::{SimChip, SimPin};
+    use core::cell::RefCell;
+    use core::convert::Infallible;
+
+    /// SPI peripheral in mode 3 that clocks virtual chip with its own bit order.
+    struct SimSpi<'a> {
+        dio: SimPin<'a>,
+        clk: SimPin<'a>,
+        bit_order: BitOrder,
+    }
+
+    impl SimSpi<'_> {
+        fn shift(&mut self, out: u8) -> u8 {
+            let mut input = 0;
+            for i in 0..8 {
+                let bit = match self.bit_order {
+                    BitOrder::LsbFirst => i,
+                    BitOrder::MsbFirst => 7 - i,
+                };
+                self.clk.set_low().unwrap();
+                if out & (1 << bit) != 0 {
+                    self.dio.set_high().unwrap();
+                } else {
+                    self.dio.set_low().unwrap();
+                }
+                self.clk.set_high().unwrap();
+                if self.dio.is_high().unwrap() {
+                    input |= 1 << bit;
+                }
+            }
+            self.dio.set_high().unwrap();
+            input
+        }
+    }
+
+    impl Write<u8> for SimSpi<'_> {
+        type Error = Infallible;
+
+        fn write(&mut self, words: &[u8]) -> Result<(), Infallible> {
+            for word in words {
+                self.shift(*word);
+            }
+            Ok(())
+        }
+    }
+
+    impl Transfer<u8> for SimSpi<'_> {
+        type Error = Infallible;
+
+        fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], Infallible> {
+            for word in words.iter_mut() {
+                *word = self.shift(*word);
+            }
+            Ok(words)
+        }
+    }
+
+    fn spi_test(bit_order: BitOrder) {
+        let chip = RefCell::new(SimChip::tm1638());
+        let spi = SimSpi {
+            dio: SimPin::dio(&chip),
+            clk: SimPin::clk(&chip),
+            bit_order,
+        };
+        let mut tm = TmSpi::new(spi, SimPin::stb(&chip), |_| {}, TM1638_TIMING, bit_order);
+
+        let bytes = [COM_ADDRESS, CHAR_1, 0, CHAR_2];
+        tm.send(&[COM_DATA_ADDRESS_ADD]).unwrap();
+        tm.send(&bytes).unwrap();
+        tm.send(&[COM_DISPLAY_ON | 3]).unwrap();
+        assert_eq!(chip.borrow().ram()[..3], bytes[1..]);
+        assert_eq!(chip.borrow().brightness(), 3);
+        assert_eq!(chip.borrow().errors(), 0);
+        assert!(matches!(tm.send(&[]), Err(TmError::Input)));
+
+        #[cfg(feature = "keys")]
+        {
+            chip.borrow_mut()
+                .set_key_scan(&[0b0001_0001, 0, 0b0010_0000, 0]);
+            let mut response = [0_u8; TM1638_RESPONSE_SIZE as usize];
+            tm.read(COM_DATA_READ, &mut response).unwrap();
+            let (mut dio, mut clk, mut stb) =
+                (SimPin::dio(&chip), SimPin::clk(&chip), SimPin::stb(&chip));
+            assert_eq!(
+                response,
+                tm_read_bytes_3wire(&mut dio, &mut clk, &mut stb, &mut |_| {}, 1, 4).unwrap()
+            );
+        }
+    }
+
+    /// STB pin from another port with its own error type.
+    struct BrokenStb;
+
+    impl OutputPin for BrokenStb {
+        type Error = u8;
+
+        fn set_low(&mut self) -> Result<(), u8> {
+            Err(7)
+        }
+
+        fn set_high(&mut self) -> Result<(), u8> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn stb_error_test() {
+        let chip = RefCell::new(SimChip::tm1638());
+        let spi = SimSpi {
+            dio: SimPin::dio(&chip),
+            clk: SimPin::clk(&chip),
+            bit_order: BitOrder::LsbFirst,
+        };
+        let mut tm = TmSpi::new(spi, BrokenStb, |_| {}, TM1638_TIMING, BitOrder::LsbFirst);
+        assert!(matches!(tm.send(&[COM_DISPLAY_ON]), Err(TmError::Stb(7))));
+        assert_eq!(chip.borrow().brightness(), 0);
+    }
+
+    #[test]
+    fn lsb_first_test() {
+        spi_test(BitOrder::LsbFirst);
+    }
+
+    #[test]
+    fn msb_first_test() {
+        spi_test(BitOrder::MsbFirst);
+    }
+}
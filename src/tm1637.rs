@@ -0,0 +1,229 @@
+//! High level driver for TM1637 (2 wire interface, 6 displays, 16 keys).
+//!
+//! Driver owns interface pins and delay closure and wraps datasheet command sequences,
+//! so you do not have to build raw command bytes by hand.
+use super::*;
+
+/// TM1637 driver that owns its DIO, CLK pins and delay closure.
+pub struct Tm1637<DIO, CLK, D> {
+    dio: DIO,
+    clk: CLK,
+    delay_us: D,
+    timing: BusTiming,
+    brightness: u8,
+    display_on: bool,
+    retry: RetryPolicy,
+    failed_attempts: u32,
+}
+
+impl<DIO, CLK, D, E, EC> Tm1637<DIO, CLK, D>
+where
+    DIO: Dio<Error = E>,
+    CLK: OutputPin<Error = EC>,
+    D: BusDelay,
+{
+    /// Creates new driver instance without touching the bus.
+    /// Call [`init`](Tm1637::init) before writing anything.
+    ///
+    /// Arguments:
+    ///  - `dio`, `clk` - MCU interface pins
+    ///  - `delay_us` - closure that provides delay functionality
+    ///  - `timing` - bus timing or single delay value in us, see [`TM1637_TIMING`]
+    pub fn new<T: Into<BusTiming>>(
+        dio: DIO,
+        clk: CLK,
+        delay_us: D,
+        timing: T,
+    ) -> Tm1637<DIO, CLK, D> {
+        Tm1637 {
+            dio,
+            clk,
+            delay_us,
+            timing: timing.into(),
+            brightness: DISPLAY_BRIGHTNESS_MASK,
+            display_on: true,
+            retry: RetryPolicy::default(),
+            failed_attempts: 0,
+        }
+    }
+
+    /// Power-on initialization.
+    ///
+    /// Puts bus into idle state, selects address auto increment mode,
+    /// clears all displays and turns display on with current brightness.
+    pub fn init(&mut self) -> Result<(), TmError<E, EC>> {
+        self.bus_idle()?;
+        self.send(&[COM_DATA_ADDRESS_ADD])?;
+        self.clear()?;
+        self.send_display_control()
+    }
+
+    /// Writes display bytes starting from the first display.
+    /// Up to [`TM1637_MAX_SEGMENTS`] bytes are allowed.
+    pub fn write_digits(&mut self, digits: &[u8]) -> Result<(), TmError<E, EC>> {
+        self.write_digits_at(0, digits)
+    }
+
+    /// Writes display bytes starting from display with given `offset`.
+    pub fn write_digits_at(&mut self, offset: u8, digits: &[u8]) -> Result<(), TmError<E, EC>> {
+        let mut buf = [0_u8; 1 + TM1637_MAX_SEGMENTS as usize];
+        let bytes = tm_address_write(&mut buf, TM1637_MAX_SEGMENTS, offset, digits)
+            .ok_or(TmError::Input)?;
+        self.send(bytes)
+    }
+
+    /// Turns all displays off by writing empty bytes.
+    pub fn clear(&mut self) -> Result<(), TmError<E, EC>> {
+        self.write_digits(&[0; TM1637_MAX_SEGMENTS as usize])
+    }
+
+    /// Set brightness level from 0 (dimmest) to 7 (brightest).
+    pub fn set_brightness(&mut self, level: u8) -> Result<(), TmError<E, EC>> {
+        if level > DISPLAY_BRIGHTNESS_MASK {
+            return Err(TmError::Input);
+        }
+        self.brightness = level;
+        self.send_display_control()
+    }
+
+    /// Turns display on keeping current brightness.
+    pub fn display_on(&mut self) -> Result<(), TmError<E, EC>> {
+        self.display_on = true;
+        self.send_display_control()
+    }
+
+    /// Turns display off. Display memory is kept intact.
+    pub fn display_off(&mut self) -> Result<(), TmError<E, EC>> {
+        self.display_on = false;
+        self.send_display_control()
+    }
+
+    /// Reads raw key scan byte.
+    #[cfg(feature = "keys")]
+    pub fn read_keys(&mut self) -> Result<u8, TmError<E, EC>> {
+        tm_read_byte_2wire_retry(
+            &mut self.dio,
+            &mut self.clk,
+            &mut self.delay_us,
+            self.timing,
+            &self.retry,
+            &mut self.failed_attempts,
+        )
+    }
+
+    /// Sends raw bytes to MCU, see [`tm_send_bytes_2wire_retry`].
+    pub fn send(&mut self, bytes: &[u8]) -> Result<(), TmError<E, EC>> {
+        tm_send_bytes_2wire_retry(
+            &mut self.dio,
+            &mut self.clk,
+            &mut self.delay_us,
+            self.timing,
+            bytes,
+            &self.retry,
+            &mut self.failed_attempts,
+        )
+    }
+
+    /// Sets how transfers are repeated when chip misses ACK.
+    /// By default there is only one attempt.
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry = policy;
+    }
+
+    /// Number of transfer attempts failed because of missing ACK since driver was created.
+    pub fn failed_attempts(&self) -> u32 {
+        self.failed_attempts
+    }
+
+    /// Destroys driver and gives back pins and delay closure.
+    pub fn release(self) -> (DIO, CLK, D) {
+        (self.dio, self.clk, self.delay_us)
+    }
+
+    /// Puts bus lines into idle state (both high).
+    pub(crate) fn bus_idle(&mut self) -> Result<(), TmError<E, EC>> {
+        self.clk.set_high().map_err(TmError::Clk)?;
+        self.dio.set_dio(true).map_err(TmError::Dio)
+    }
+
+    fn send_display_control(&mut self) -> Result<(), TmError<E, EC>> {
+        self.send(&[tm_display_control(self.display_on, self.brightness)])
+    }
+}
+
+#[cfg(feature = "eh1")]
+impl<DIO, CLK, DL> Tm1637<eh1::Pin<DIO>, eh1::Pin<CLK>, eh1::Delay<DL>>
+where
+    DIO: embedded_hal_1::digital::InputPin + embedded_hal_1::digital::OutputPin,
+    CLK: embedded_hal_1::digital::OutputPin,
+    DL: embedded_hal_1::delay::DelayNs,
+{
+    /// Same as [`new`](Tm1637::new) but takes embedded-hal 1.0 pins and `DelayNs` as is.
+    pub fn new_eh1<T: Into<BusTiming>>(dio: DIO, clk: CLK, delay: DL, timing: T) -> Self {
+        Tm1637::new(
+            eh1::Pin::new(dio),
+            eh1::Pin::new(clk),
+            eh1::Delay::new(delay),
+            timing,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::{SimChip, SimPin};
+    use core::cell::RefCell;
+
+    #[test]
+    fn init_write_test() {
+        let chip = RefCell::new(SimChip::tm1637());
+        let mut tm = Tm1637::new(SimPin::dio(&chip), SimPin::clk(&chip), |_| {}, 0);
+        tm.init().unwrap();
+        assert_eq!(chip.borrow().ram(), &[0; 6]);
+        assert!(chip.borrow().is_display_on());
+        assert_eq!(chip.borrow().brightness(), DISPLAY_BRIGHTNESS_MASK);
+
+        tm.write_digits(&[CHAR_1, CHAR_2]).unwrap();
+        tm.write_digits_at(4, &[CHAR_3, CHAR_4]).unwrap();
+        assert_eq!(chip.borrow().ram(), &[CHAR_1, CHAR_2, 0, 0, CHAR_3, CHAR_4]);
+
+        assert!(matches!(
+            tm.write_digits_at(5, &[CHAR_5, CHAR_6]),
+            Err(TmError::Input)
+        ));
+        assert!(matches!(tm.write_digits(&[0; 7]), Err(TmError::Input)));
+        assert_eq!(chip.borrow().errors(), 0);
+    }
+
+    #[test]
+    fn display_control_test() {
+        let chip = RefCell::new(SimChip::tm1637());
+        let mut tm = Tm1637::new(SimPin::dio(&chip), SimPin::clk(&chip), |_| {}, 0);
+        tm.init().unwrap();
+
+        tm.set_brightness(2).unwrap();
+        assert_eq!(chip.borrow().brightness(), 2);
+        assert!(matches!(tm.set_brightness(8), Err(TmError::Input)));
+        assert_eq!(chip.borrow().brightness(), 2);
+
+        tm.display_off().unwrap();
+        assert!(!chip.borrow().is_display_on());
+        tm.display_on().unwrap();
+        assert!(chip.borrow().is_display_on());
+        assert_eq!(chip.borrow().brightness(), 2);
+    }
+
+    #[test]
+    #[cfg(feature = "keys")]
+    fn read_keys_test() {
+        let chip = RefCell::new(SimChip::tm1637());
+        let mut tm = Tm1637::new(SimPin::dio(&chip), SimPin::clk(&chip), |_| {}, 0);
+        tm.init().unwrap();
+
+        chip.borrow_mut().set_key_scan(&[0b1110_1111]);
+        assert_eq!(tm.read_keys().unwrap(), 0b1111_0111);
+        assert_eq!(tm.failed_attempts(), 0);
+        assert_eq!(chip.borrow().errors(), 0);
+    }
+}
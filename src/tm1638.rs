@@ -0,0 +1,234 @@
+//! High level driver for TM1638 (3 wire interface, 8 displays with 10 segments, 24 keys).
+//!
+//! TM1638 display memory uses two bytes for each grid (display).
+//! First byte holds segments 1-8 and second one holds segments 9-10,
+//! driver takes care about that so no allocator is needed.
+use super::*;
+
+/// Number of grids (displays) supported by this MCU.
+pub const TM1638_GRIDS: u8 = 8;
+
+/// TM1638 driver that owns its DIO, CLK, STB pins and delay closure.
+pub struct Tm1638<DIO, CLK, STB, D> {
+    dio: DIO,
+    clk: CLK,
+    stb: STB,
+    delay_us: D,
+    timing: BusTiming,
+    brightness: u8,
+    display_on: bool,
+}
+
+impl<DIO, CLK, STB, D, E, EC, ES> Tm1638<DIO, CLK, STB, D>
+where
+    DIO: Dio<Error = E>,
+    CLK: OutputPin<Error = EC>,
+    STB: OutputPin<Error = ES>,
+    D: BusDelay,
+{
+    /// Creates new driver instance without touching the bus.
+    /// Call [`init`](Tm1638::init) before writing anything.
+    ///
+    /// Arguments:
+    ///  - `dio`, `clk`, `stb` - MCU interface pins
+    ///  - `delay_us` - closure that provides delay functionality
+    ///  - `timing` - bus timing or single delay value in us, see [`TM1638_TIMING`]
+    pub fn new<T: Into<BusTiming>>(
+        dio: DIO,
+        clk: CLK,
+        stb: STB,
+        delay_us: D,
+        timing: T,
+    ) -> Tm1638<DIO, CLK, STB, D> {
+        Tm1638 {
+            dio,
+            clk,
+            stb,
+            delay_us,
+            timing: timing.into(),
+            brightness: DISPLAY_BRIGHTNESS_MASK,
+            display_on: true,
+        }
+    }
+
+    /// Power-on initialization.
+    ///
+    /// Puts bus into idle state, selects address auto increment mode,
+    /// clears all displays and turns display on with current brightness.
+    pub fn init(&mut self) -> Result<(), TmError<E, EC, ES>> {
+        self.clk.set_high().map_err(TmError::Clk)?;
+        self.dio.set_dio(true).map_err(TmError::Dio)?;
+        self.stb.set_high().map_err(TmError::Stb)?;
+        self.send(&[COM_DATA_ADDRESS_ADD])?;
+        self.clear()?;
+        self.send_display_control()
+    }
+
+    /// Writes both bytes of one grid.
+    ///
+    /// Arguments:
+    ///  - `index` - grid index starting from 0
+    ///  - `seg_lo` - segments 1-8
+    ///  - `seg_hi` - segments 9-10 (see [`SEG_9`], [`SEG_10`])
+    pub fn write_grid(
+        &mut self,
+        index: u8,
+        seg_lo: u8,
+        seg_hi: u8,
+    ) -> Result<(), TmError<E, EC, ES>> {
+        if index >= TM1638_GRIDS {
+            return Err(TmError::Input);
+        }
+        self.send(&[COM_ADDRESS | (index * 2), seg_lo, seg_hi])
+    }
+
+    /// Writes display bytes (segments 1-8) starting from the first grid.
+    /// Segments 9-10 are cleared for every written grid.
+    pub fn write_digits(&mut self, digits: &[u8]) -> Result<(), TmError<E, EC, ES>> {
+        if digits.len() > TM1638_GRIDS as usize {
+            return Err(TmError::Input);
+        }
+        let mut bytes = [0_u8; 1 + 2 * TM1638_GRIDS as usize];
+        bytes[0] = COM_ADDRESS;
+        for (i, d) in digits.iter().enumerate() {
+            bytes[1 + i * 2] = *d;
+        }
+        self.send(&bytes[..1 + 2 * digits.len()])
+    }
+
+    /// Turns all displays off by writing empty bytes.
+    pub fn clear(&mut self) -> Result<(), TmError<E, EC, ES>> {
+        let mut bytes = [0_u8; 1 + 2 * TM1638_GRIDS as usize];
+        bytes[0] = COM_ADDRESS;
+        self.send(&bytes)
+    }
+
+    /// Set brightness level from 0 (dimmest) to 7 (brightest).
+    pub fn set_brightness(&mut self, level: u8) -> Result<(), TmError<E, EC, ES>> {
+        if level > DISPLAY_BRIGHTNESS_MASK {
+            return Err(TmError::Input);
+        }
+        self.brightness = level;
+        self.send_display_control()
+    }
+
+    /// Turns display on keeping current brightness.
+    pub fn display_on(&mut self) -> Result<(), TmError<E, EC, ES>> {
+        self.display_on = true;
+        self.send_display_control()
+    }
+
+    /// Turns display off. Display memory is kept intact.
+    pub fn display_off(&mut self) -> Result<(), TmError<E, EC, ES>> {
+        self.display_on = false;
+        self.send_display_control()
+    }
+
+    /// Reads key scan data and decodes it with [`decode_keys`].
+    #[cfg(feature = "keys")]
+    pub fn read_keys(&mut self) -> Result<u32, TmError<E, EC, ES>> {
+        self.read_key_scan().map(|scan| decode_keys(&scan))
+    }
+
+    /// Reads raw 4 bytes of key scan data.
+    #[cfg(feature = "keys")]
+    pub fn read_key_scan(&mut self) -> Result<[u8; 4], TmError<E, EC, ES>> {
+        tm_read_bytes_3wire(
+            &mut self.dio,
+            &mut self.clk,
+            &mut self.stb,
+            &mut self.delay_us,
+            self.timing,
+            TM1638_RESPONSE_SIZE,
+        )
+    }
+
+    /// Sends raw bytes to MCU, see [`tm_send_bytes_3wire`].
+    pub fn send(&mut self, bytes: &[u8]) -> Result<(), TmError<E, EC, ES>> {
+        tm_send_bytes_3wire(
+            &mut self.dio,
+            &mut self.clk,
+            &mut self.stb,
+            &mut self.delay_us,
+            self.timing,
+            bytes,
+        )
+    }
+
+    /// Destroys driver and gives back pins and delay closure.
+    pub fn release(self) -> (DIO, CLK, STB, D) {
+        (self.dio, self.clk, self.stb, self.delay_us)
+    }
+
+    fn send_display_control(&mut self) -> Result<(), TmError<E, EC, ES>> {
+        self.send(&[tm_display_control(self.display_on, self.brightness)])
+    }
+}
+
+#[cfg(feature = "eh1")]
+impl<DIO, CLK, STB, DL> Tm1638<eh1::Pin<DIO>, eh1::Pin<CLK>, eh1::Pin<STB>, eh1::Delay<DL>>
+where
+    DIO: embedded_hal_1::digital::InputPin + embedded_hal_1::digital::OutputPin,
+    CLK: embedded_hal_1::digital::OutputPin,
+    STB: embedded_hal_1::digital::OutputPin,
+    DL: embedded_hal_1::delay::DelayNs,
+{
+    /// Same as [`new`](Tm1638::new) but takes embedded-hal 1.0 pins and `DelayNs` as is.
+    pub fn new_eh1<T: Into<BusTiming>>(dio: DIO, clk: CLK, stb: STB, delay: DL, timing: T) -> Self {
+        Tm1638::new(
+            eh1::Pin::new(dio),
+            eh1::Pin::new(clk),
+            eh1::Pin::new(stb),
+            eh1::Delay::new(delay),
+            timing,
+        )
+    }
+}
+
+/// Decodes raw key scan bytes into bit mask of pressed keys.
+///
+/// Key at KS`n` line (1-8) and K`m` line (1-3) is stored at bit `(m - 1) * 8 + (n - 1)`.
+/// Thus K1 keys occupy bits 0-7, K2 keys bits 8-15 and K3 keys bits 16-23.
+///
+/// Raw bytes are expected as returned from [`tm_read_bytes_3wire`]
+/// which stores first received bit as the highest one.
+pub fn decode_keys(scan: &[u8; 4]) -> u32 {
+    let mut keys = 0_u32;
+    for (i, raw) in scan.iter().enumerate() {
+        // Restore datasheet bit order
+        let byte = raw.reverse_bits();
+        for half in 0..2 {
+            let ks = i * 2 + half;
+            let bits = byte >> (half * 4);
+            for k in 0..3 {
+                // K3 is bit 0, K2 is bit 1, K1 is bit 2
+                if bits & (0b100 >> k) != 0 {
+                    keys |= 1 << (k * 8 + ks);
+                }
+            }
+        }
+    }
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_keys_test() {
+        assert_eq!(decode_keys(&[0, 0, 0, 0]), 0);
+
+        // K3 at KS1
+        assert_eq!(decode_keys(&[0b1000_0000, 0, 0, 0]), 1 << 16);
+        // K1 at KS2
+        assert_eq!(decode_keys(&[0b0000_0010, 0, 0, 0]), 1 << 1);
+        // K2 at KS8
+        assert_eq!(decode_keys(&[0, 0, 0, 0b0000_0100]), 1 << 15);
+
+        assert_eq!(
+            decode_keys(&[0b1110_1110, 0b1110_1110, 0b1110_1110, 0b1110_1110]),
+            0x00FF_FFFF
+        );
+    }
+}
@@ -0,0 +1,178 @@
+//! High level driver for TM1640 (2 wire interface without ACK, 16 displays, no keys).
+//!
+//! TM1640 shares command set with TM1637, but its bus is different:
+//!  - DIN is input only, chip never pulls it down, so there is no ACK and no key scan
+//!  - both lines could be driven push-pull, which allows clock up to 1 MHz (see [`TM1640_TIMING`])
+//!  - display memory has 16 bytes, one for each grid (GRID1-GRID16)
+//!
+//! Data is sent with [`tm_send_bytes_2wire_noack`], so the only errors are pin errors
+//! and invalid arguments. Driver can not tell if chip is actually connected.
+use super::*;
+
+/// TM1640 driver that owns its DIN (DIO), SCLK (CLK) pins and delay closure.
+pub struct Tm1640<DIO, CLK, D> {
+    dio: DIO,
+    clk: CLK,
+    delay_us: D,
+    timing: BusTiming,
+    brightness: u8,
+    display_on: bool,
+}
+
+impl<DIO, CLK, D, E, EC> Tm1640<DIO, CLK, D>
+where
+    DIO: DioOut<Error = E>,
+    CLK: OutputPin<Error = EC>,
+    D: BusDelay,
+{
+    /// Creates new driver instance without touching the bus.
+    /// Call [`init`](Tm1640::init) before writing anything.
+    ///
+    /// Arguments:
+    ///  - `dio`, `clk` - MCU pins connected to DIN and SCLK, DIN is never read
+    ///  - `delay_us` - closure that provides delay functionality
+    ///  - `timing` - bus timing or single delay value in us, see [`TM1640_TIMING`]
+    pub fn new<T: Into<BusTiming>>(
+        dio: DIO,
+        clk: CLK,
+        delay_us: D,
+        timing: T,
+    ) -> Tm1640<DIO, CLK, D> {
+        Tm1640 {
+            dio,
+            clk,
+            delay_us,
+            timing: timing.into(),
+            brightness: DISPLAY_BRIGHTNESS_MASK,
+            display_on: true,
+        }
+    }
+
+    /// Power-on initialization.
+    ///
+    /// Puts bus into idle state, selects address auto increment mode,
+    /// clears all 16 grids and turns display on with current brightness.
+    pub fn init(&mut self) -> Result<(), TmError<E, EC>> {
+        self.clk.set_high().map_err(TmError::Clk)?;
+        self.dio.set_dio(true).map_err(TmError::Dio)?;
+        self.send(&[COM_DATA_ADDRESS_ADD])?;
+        self.clear()?;
+        self.send_display_control()
+    }
+
+    /// Writes display bytes starting from GRID1.
+    /// Up to [`TM1640_MAX_SEGMENTS`] bytes are allowed.
+    pub fn write_digits(&mut self, digits: &[u8]) -> Result<(), TmError<E, EC>> {
+        self.write_digits_at(0, digits)
+    }
+
+    /// Writes display bytes starting from grid with given `offset` (0 is GRID1).
+    pub fn write_digits_at(&mut self, offset: u8, digits: &[u8]) -> Result<(), TmError<E, EC>> {
+        let mut buf = [0_u8; 1 + TM1640_MAX_SEGMENTS as usize];
+        let bytes = tm_address_write(&mut buf, TM1640_MAX_SEGMENTS, offset, digits)
+            .ok_or(TmError::Input)?;
+        self.send(bytes)
+    }
+
+    /// Turns all displays off by writing empty bytes.
+    pub fn clear(&mut self) -> Result<(), TmError<E, EC>> {
+        self.write_digits(&[0; TM1640_MAX_SEGMENTS as usize])
+    }
+
+    /// Set brightness level from 0 (dimmest) to 7 (brightest).
+    pub fn set_brightness(&mut self, level: u8) -> Result<(), TmError<E, EC>> {
+        if level > DISPLAY_BRIGHTNESS_MASK {
+            return Err(TmError::Input);
+        }
+        self.brightness = level;
+        self.send_display_control()
+    }
+
+    /// Turns display on keeping current brightness.
+    pub fn display_on(&mut self) -> Result<(), TmError<E, EC>> {
+        self.display_on = true;
+        self.send_display_control()
+    }
+
+    /// Turns display off. Display memory is kept intact.
+    pub fn display_off(&mut self) -> Result<(), TmError<E, EC>> {
+        self.display_on = false;
+        self.send_display_control()
+    }
+
+    /// Sends raw bytes to MCU without waiting for ACK, see [`tm_send_bytes_2wire_noack`].
+    pub fn send(&mut self, bytes: &[u8]) -> Result<(), TmError<E, EC>> {
+        tm_send_bytes_2wire_noack(
+            &mut self.dio,
+            &mut self.clk,
+            &mut self.delay_us,
+            self.timing,
+            bytes,
+        )
+    }
+
+    /// Destroys driver and gives back pins and delay closure.
+    pub fn release(self) -> (DIO, CLK, D) {
+        (self.dio, self.clk, self.delay_us)
+    }
+
+    fn send_display_control(&mut self) -> Result<(), TmError<E, EC>> {
+        self.send(&[tm_display_control(self.display_on, self.brightness)])
+    }
+}
+
+#[cfg(feature = "eh1")]
+impl<DIO, CLK, DL> Tm1640<eh1::Pin<DIO>, eh1::Pin<CLK>, eh1::Delay<DL>>
+where
+    DIO: embedded_hal_1::digital::OutputPin,
+    CLK: embedded_hal_1::digital::OutputPin,
+    DL: embedded_hal_1::delay::DelayNs,
+{
+    /// Same as [`new`](Tm1640::new) but takes embedded-hal 1.0 pins and `DelayNs` as is.
+    pub fn new_eh1<T: Into<BusTiming>>(dio: DIO, clk: CLK, delay: DL, timing: T) -> Self {
+        Tm1640::new(
+            eh1::Pin::new(dio),
+            eh1::Pin::new(clk),
+            eh1::Delay::new(delay),
+            timing,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::{SimChip, SimPin};
+    use core::cell::RefCell;
+
+    #[test]
+    fn init_write_test() {
+        let chip = RefCell::new(SimChip::tm1640());
+        let mut tm = Tm1640::new(SimPin::dio(&chip), SimPin::clk(&chip), |_| {}, 0);
+        tm.init().unwrap();
+        assert_eq!(chip.borrow().ram(), &[0; 16]);
+        assert!(chip.borrow().is_display_on());
+
+        tm.write_digits(&[CHAR_1, CHAR_2]).unwrap();
+        tm.write_digits_at(14, &[CHAR_3, CHAR_4]).unwrap();
+        let mut ram = [0; 16];
+        ram.copy_from_slice(chip.borrow().ram());
+        assert_eq!(&ram[..2], &[CHAR_1, CHAR_2]);
+        assert_eq!(&ram[14..], &[CHAR_3, CHAR_4]);
+        assert!(matches!(
+            tm.write_digits_at(15, &[CHAR_5, CHAR_6]),
+            Err(TmError::Input)
+        ));
+
+        tm.set_brightness(3).unwrap();
+        tm.display_off().unwrap();
+        assert_eq!(chip.borrow().brightness(), 0);
+        assert!(!chip.borrow().is_display_on());
+        tm.display_on().unwrap();
+        assert_eq!(chip.borrow().brightness(), 3);
+
+        // Chip never pulls DIO down, transfers are still well framed
+        assert_eq!(chip.borrow().errors(), 0);
+        assert_eq!(chip.borrow().transactions(), 8);
+    }
+}
@@ -0,0 +1,676 @@
+//! High level driver for TM1650 and clones like AiP650 (2 wire I2C like interface, 4 displays, 28 keys).
+//!
+//! TM1650 does not share framing with TM1637.
+//! Every transfer is a command byte followed by one data byte, bits are sent MSB first,
+//! and every digit has its own command (address).
+//!
+//! Transfers are done through [`Tm1650Interface`], so driver could work either with
+//! bit banged pins ([`Tm1650BitBang`]) or with hardware I2C peripheral ([`Tm1650I2c`]).
+use super::*;
+
+use embedded_hal::blocking::i2c;
+
+/// System (display control) command, followed by display control byte.
+pub const TM1650_COM_CONTROL: u8 = 0x48;
+/// First digit command, following digits are at 0x6A, 0x6C, 0x6E.
+pub const TM1650_COM_DIGIT: u8 = 0x68;
+/// Read key scan code command.
+pub const TM1650_COM_READ_KEY: u8 = 0x4F;
+
+/// Display control: display on.
+pub const TM1650_DISPLAY_ON: u8 = 0b0000_0001;
+/// Display control: 7 segment mode, otherwise 8 segment mode is used.
+pub const TM1650_DISPLAY_7SEG: u8 = 0b0000_1000;
+/// Display control: brightness bits mask.
+pub const TM1650_BRIGHTNESS_MASK: u8 = 0b0111_0000;
+
+/// Data transfer layer for TM1650.
+///
+/// Each call is one complete bus transaction with command byte as first byte.
+pub trait Tm1650Interface {
+    /// Error type of underlying DIO pin or peripheral.
+    type Error;
+    /// Error type of CLK pin, same as `Error` for peripheral.
+    type ClkError;
+
+    /// Sends command byte followed by data byte.
+    fn write(&mut self, command: u8, data: u8) -> Result<(), TmError<Self::Error, Self::ClkError>>;
+
+    /// Sends command byte and reads one byte back.
+    #[cfg(feature = "keys")]
+    fn read(&mut self, command: u8) -> Result<u8, TmError<Self::Error, Self::ClkError>>;
+}
+
+/// Bit banged TM1650 interface built on top of 2 wire bus primitives.
+pub struct Tm1650BitBang<DIO, CLK, D> {
+    dio: DIO,
+    clk: CLK,
+    delay_us: D,
+    timing: BusTiming,
+}
+
+impl<DIO, CLK, D, E, EC> Tm1650BitBang<DIO, CLK, D>
+where
+    DIO: Dio<Error = E>,
+    CLK: OutputPin<Error = EC>,
+    D: BusDelay,
+{
+    /// Creates bit banged interface.
+    ///
+    /// Arguments:
+    ///  - `dio`, `clk` - MCU interface pins
+    ///  - `delay_us` - closure that provides delay functionality
+    ///  - `timing` - bus timing or single delay value in us, see [`TM1650_TIMING`]
+    pub fn new<T: Into<BusTiming>>(
+        dio: DIO,
+        clk: CLK,
+        delay_us: D,
+        timing: T,
+    ) -> Tm1650BitBang<DIO, CLK, D> {
+        Tm1650BitBang {
+            dio,
+            clk,
+            delay_us,
+            timing: timing.into(),
+        }
+    }
+
+    /// Destroys interface and gives back pins and delay closure.
+    pub fn release(self) -> (DIO, CLK, D) {
+        (self.dio, self.clk, self.delay_us)
+    }
+
+    fn run(&mut self, seq: &mut bus::Sequence) -> Result<(), TmError<E, EC>> {
+        let mut lines = bus::Bus2 {
+            dio: &mut self.dio,
+            clk: &mut self.clk,
+        };
+        bus::run(&mut lines, &mut self.delay_us, seq)
+    }
+
+    /// Sends byte MSB first and waits for ACK.
+    fn send_byte(&mut self, byte: u8, byte_index: usize) -> Result<(), TmError<E, EC>> {
+        let data = [byte.reverse_bits()];
+        let data = [&data[..]];
+        self.run(&mut bus::Sequence::bits(&self.timing, &data, &mut []))?;
+
+        // Release DIO only when CLK is low, otherwise it would be a stop condition
+        self.clk.set_low().map_err(TmError::Clk)?;
+        self.dio.set_dio(true).map_err(TmError::Dio)?;
+        self.delay_us.delay_us(self.timing.clk_low_us);
+
+        self.clk.set_high().map_err(TmError::Clk)?;
+        self.delay_us.delay_us(self.timing.clk_high_us);
+        let ack = bus::Wait {
+            high: false,
+            polls: TM_ACK_POLLS,
+            delay_us: self.timing.ack_wait_us,
+            byte: byte_index,
+            phase: AckPhase::Rising9,
+        };
+        let mut lines = bus::Bus2 {
+            dio: &mut self.dio,
+            clk: &mut self.clk,
+        };
+        bus::wait(&mut lines, &mut self.delay_us, &ack)?;
+
+        self.clk.set_low().map_err(TmError::Clk)?;
+        self.delay_us.delay_us(self.timing.ack_wait_us);
+        Ok(())
+    }
+
+    /// Runs `body` between start and stop, `len` is a number of bytes it transfers.
+    fn transaction<F, R>(&mut self, len: usize, body: F) -> Result<R, TmError<E, EC>>
+    where
+        F: FnOnce(&mut Self) -> Result<R, TmError<E, EC>>,
+    {
+        self.run(&mut bus::Sequence::start_2wire(&self.timing))?;
+        let result = body(self);
+        let stop = self.run(&mut bus::Sequence::stop_2wire(
+            &self.timing,
+            TM_ACK_POLLS,
+            len,
+        ));
+        match (result, stop) {
+            (Ok(r), Ok(_)) => Ok(r),
+            (Ok(_), Err(e)) => Err(e),
+            (Err(e), _) => Err(e),
+        }
+    }
+}
+
+#[cfg(feature = "eh1")]
+impl<DIO, CLK, DL> Tm1650BitBang<eh1::Pin<DIO>, eh1::Pin<CLK>, eh1::Delay<DL>>
+where
+    DIO: embedded_hal_1::digital::InputPin + embedded_hal_1::digital::OutputPin,
+    CLK: embedded_hal_1::digital::OutputPin,
+    DL: embedded_hal_1::delay::DelayNs,
+{
+    /// Same as [`new`](Tm1650BitBang::new) but takes embedded-hal 1.0 pins and `DelayNs` as is.
+    pub fn new_eh1<T: Into<BusTiming>>(dio: DIO, clk: CLK, delay: DL, timing: T) -> Self {
+        Tm1650BitBang::new(
+            eh1::Pin::new(dio),
+            eh1::Pin::new(clk),
+            eh1::Delay::new(delay),
+            timing,
+        )
+    }
+}
+
+impl<DIO, CLK, D, E, EC> Tm1650Interface for Tm1650BitBang<DIO, CLK, D>
+where
+    DIO: Dio<Error = E>,
+    CLK: OutputPin<Error = EC>,
+    D: BusDelay,
+{
+    type Error = E;
+    type ClkError = EC;
+
+    fn write(&mut self, command: u8, data: u8) -> Result<(), TmError<E, EC>> {
+        self.transaction(2, |bus| {
+            bus.send_byte(command, 0)?;
+            bus.send_byte(data, 1)
+        })
+    }
+
+    #[cfg(feature = "keys")]
+    fn read(&mut self, command: u8) -> Result<u8, TmError<E, EC>> {
+        self.transaction(2, |bus| {
+            bus.send_byte(command, 0)?;
+            // TM1650 sends MSB first, exactly how bus read works
+            let mut byte = [0];
+            bus.run(&mut bus::Sequence::bits(&bus.timing, &[], &mut byte))?;
+
+            // 9th clock with DIO released (NACK)
+            bus.clk.set_low().map_err(TmError::Clk)?;
+            bus.dio.set_dio(true).map_err(TmError::Dio)?;
+            bus.delay_us.delay_us(bus.timing.clk_low_us);
+            bus.clk.set_high().map_err(TmError::Clk)?;
+            bus.delay_us.delay_us(bus.timing.clk_high_us);
+            bus.clk.set_low().map_err(TmError::Clk)?;
+            bus.delay_us.delay_us(bus.timing.ack_wait_us);
+            Ok(byte[0])
+        })
+    }
+}
+
+/// TM1650 interface over hardware I2C peripheral.
+///
+/// Command byte is used as I2C address shifted by 1 bit (e.g. 0x48 is 0x24 7 bit address).
+pub struct Tm1650I2c<I2C> {
+    i2c: I2C,
+}
+
+impl<I2C> Tm1650I2c<I2C> {
+    /// Creates interface on top of I2C peripheral.
+    pub fn new(i2c: I2C) -> Tm1650I2c<I2C> {
+        Tm1650I2c { i2c }
+    }
+
+    /// Destroys interface and gives back I2C peripheral.
+    pub fn release(self) -> I2C {
+        self.i2c
+    }
+}
+
+impl<I2C, E> Tm1650Interface for Tm1650I2c<I2C>
+where
+    I2C: i2c::Write<Error = E> + i2c::Read<Error = E>,
+{
+    type Error = E;
+    type ClkError = E;
+
+    fn write(&mut self, command: u8, data: u8) -> Result<(), TmError<E>> {
+        self.i2c.write(command >> 1, &[data]).map_err(TmError::Bus)
+    }
+
+    #[cfg(feature = "keys")]
+    fn read(&mut self, command: u8) -> Result<u8, TmError<E>> {
+        let mut buf = [0_u8; TM1650_RESPONSE_SIZE as usize];
+        self.i2c
+            .read(command >> 1, &mut buf)
+            .map_err(TmError::Bus)?;
+        Ok(buf[0])
+    }
+}
+
+/// Key decoded from TM1650 key scan code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tm1650Key {
+    /// Key input line index KI1-KI7 as 0-6
+    pub row: u8,
+    /// Digit line index DIG1-DIG4 as 0-3
+    pub column: u8,
+    /// Key is pressed right now
+    pub pressed: bool,
+}
+
+/// Decodes TM1650 key scan code, returns `None` if code is not valid.
+pub fn decode_key(code: u8) -> Option<Tm1650Key> {
+    let row = (code >> 3) & 0b111;
+    if code & 0b1000_0100 != 0b0000_0100 || row > 6 {
+        return None;
+    }
+    Some(Tm1650Key {
+        row,
+        column: code & 0b11,
+        pressed: code & 0b0100_0000 != 0,
+    })
+}
+
+/// TM1650 driver working through [`Tm1650Interface`].
+pub struct Tm1650<I> {
+    iface: I,
+    brightness: u8,
+    seven_segment: bool,
+    display_on: bool,
+}
+
+impl<I> Tm1650<I>
+where
+    I: Tm1650Interface,
+{
+    /// Creates new driver instance without touching the bus.
+    /// Call [`init`](Tm1650::init) before writing anything.
+    pub fn new(iface: I) -> Tm1650<I> {
+        Tm1650 {
+            iface,
+            brightness: DISPLAY_BRIGHTNESS_MASK,
+            seven_segment: false,
+            display_on: true,
+        }
+    }
+
+    /// Power-on initialization, clears all displays and applies display control.
+    pub fn init(&mut self) -> Result<(), TmError<I::Error, I::ClkError>> {
+        self.clear()?;
+        self.send_display_control()
+    }
+
+    /// Writes one display byte.
+    pub fn write_digit(
+        &mut self,
+        index: u8,
+        byte: u8,
+    ) -> Result<(), TmError<I::Error, I::ClkError>> {
+        if index >= TM1650_MAX_SEGMENTS {
+            return Err(TmError::Input);
+        }
+        self.iface.write(TM1650_COM_DIGIT + index * 2, byte)
+    }
+
+    /// Writes display bytes starting from the first display.
+    /// Up to [`TM1650_MAX_SEGMENTS`] bytes are allowed.
+    pub fn write_digits(&mut self, digits: &[u8]) -> Result<(), TmError<I::Error, I::ClkError>> {
+        if digits.len() > TM1650_MAX_SEGMENTS as usize {
+            return Err(TmError::Input);
+        }
+        for (i, d) in digits.iter().enumerate() {
+            self.write_digit(i as u8, *d)?;
+        }
+        Ok(())
+    }
+
+    /// Turns all displays off by writing empty bytes.
+    pub fn clear(&mut self) -> Result<(), TmError<I::Error, I::ClkError>> {
+        self.write_digits(&[0; TM1650_MAX_SEGMENTS as usize])
+    }
+
+    /// Set brightness level from 0 (dimmest) to 7 (brightest).
+    pub fn set_brightness(&mut self, level: u8) -> Result<(), TmError<I::Error, I::ClkError>> {
+        if level > DISPLAY_BRIGHTNESS_MASK {
+            return Err(TmError::Input);
+        }
+        self.brightness = level;
+        self.send_display_control()
+    }
+
+    /// Switch between 7 segment mode (DP segment is not driven) and 8 segment mode.
+    pub fn set_seven_segment(
+        &mut self,
+        seven_segment: bool,
+    ) -> Result<(), TmError<I::Error, I::ClkError>> {
+        self.seven_segment = seven_segment;
+        self.send_display_control()
+    }
+
+    /// Turns display on keeping current brightness.
+    pub fn display_on(&mut self) -> Result<(), TmError<I::Error, I::ClkError>> {
+        self.display_on = true;
+        self.send_display_control()
+    }
+
+    /// Turns display off. Display memory is kept intact.
+    pub fn display_off(&mut self) -> Result<(), TmError<I::Error, I::ClkError>> {
+        self.display_on = false;
+        self.send_display_control()
+    }
+
+    /// Reads raw key scan code.
+    #[cfg(feature = "keys")]
+    pub fn read_key_code(&mut self) -> Result<u8, TmError<I::Error, I::ClkError>> {
+        self.iface.read(TM1650_COM_READ_KEY)
+    }
+
+    /// Reads key scan code and decodes it with [`decode_key`].
+    #[cfg(feature = "keys")]
+    pub fn read_key(&mut self) -> Result<Option<Tm1650Key>, TmError<I::Error, I::ClkError>> {
+        self.read_key_code().map(decode_key)
+    }
+
+    /// Destroys driver and gives back interface.
+    pub fn release(self) -> I {
+        self.iface
+    }
+
+    fn send_display_control(&mut self) -> Result<(), TmError<I::Error, I::ClkError>> {
+        self.iface.write(
+            TM1650_COM_CONTROL,
+            display_control(self.brightness, self.seven_segment, self.display_on),
+        )
+    }
+}
+
+/// Builds display control byte.
+///
+/// TM1650 brightness levels goes as 1..7 and 0 stands for the max level 8,
+/// here `level` is from 0 (dimmest) to 7 (brightest) like for other chips.
+fn display_control(level: u8, seven_segment: bool, on: bool) -> u8 {
+    let mut byte = (((level & DISPLAY_BRIGHTNESS_MASK) + 1) << 4) & TM1650_BRIGHTNESS_MASK;
+    if seven_segment {
+        byte |= TM1650_DISPLAY_7SEG;
+    }
+    if on {
+        byte |= TM1650_DISPLAY_ON;
+    }
+    byte
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::RefCell;
+    use core::convert::Infallible;
+
+    /// What chip saw on the bus.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    enum Event {
+        Start,
+        /// Byte received by chip, chip answers with ACK
+        Write(u8),
+        /// Byte sent by chip and DIO level on the 9th clock (high is NACK)
+        Read(u8, bool),
+        Stop,
+    }
+
+    /// TM1650 bus model: samples DIO on rising CLK edges MSB first, pulls DIO down on the 9th clock,
+    /// and sends `key_code` after command with the lowest bit set.
+    struct Chip {
+        ack: bool,
+        key_code: u8,
+        clk: bool,
+        dio: bool,
+        pull_down: bool,
+        framed: bool,
+        reading: bool,
+        bit: u8,
+        byte: u8,
+        events: [Option<Event>; 16],
+        len: usize,
+    }
+
+    impl Chip {
+        fn new(ack: bool, key_code: u8) -> RefCell<Chip> {
+            RefCell::new(Chip {
+                ack,
+                key_code,
+                clk: true,
+                dio: true,
+                pull_down: false,
+                framed: false,
+                reading: false,
+                bit: 0,
+                byte: 0,
+                events: [None; 16],
+                len: 0,
+            })
+        }
+
+        fn log(&mut self, event: Event) {
+            self.events[self.len] = Some(event);
+            self.len += 1;
+        }
+
+        fn events(&self) -> &[Option<Event>] {
+            &self.events[..self.len]
+        }
+
+        fn level(&self) -> bool {
+            self.dio && !self.pull_down
+        }
+
+        fn set_dio(&mut self, high: bool) {
+            if self.clk && self.dio && !high {
+                self.framed = true;
+                self.reading = false;
+                self.bit = 0;
+                self.log(Event::Start);
+            } else if self.clk && !self.dio && high {
+                self.framed = false;
+                self.log(Event::Stop);
+            }
+            self.dio = high;
+        }
+
+        fn set_clk(&mut self, high: bool) {
+            let edge = self.clk != high;
+            self.clk = high;
+            if !edge || !self.framed {
+                return;
+            }
+            if high {
+                if self.bit < 8 && !self.reading {
+                    self.byte = self.byte << 1 | self.level() as u8;
+                } else if self.bit == 8 && self.reading {
+                    let nack = self.level();
+                    self.log(Event::Read(self.key_code, nack));
+                }
+                self.bit += 1;
+                return;
+            }
+            match self.bit {
+                8 if self.reading => self.pull_down = false,
+                8 => {
+                    self.log(Event::Write(self.byte));
+                    self.pull_down = self.ack;
+                }
+                9 => {
+                    self.bit = 0;
+                    // Read command is acknowledged, chip starts sending
+                    let read = !self.reading && self.byte & 1 != 0;
+                    self.reading = read;
+                    self.pull_down = read && self.key_code & 0x80 == 0;
+                }
+                bit if self.reading => self.pull_down = self.key_code << bit & 0x80 == 0,
+                _ => {}
+            }
+        }
+    }
+
+    struct Pin<'a> {
+        chip: &'a RefCell<Chip>,
+        clk: bool,
+    }
+
+    impl OutputPin for Pin<'_> {
+        type Error = Infallible;
+
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            self.set(false);
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            self.set(true);
+            Ok(())
+        }
+    }
+
+    impl InputPin for Pin<'_> {
+        type Error = Infallible;
+
+        fn is_high(&self) -> Result<bool, Self::Error> {
+            Ok(self.chip.borrow().level())
+        }
+
+        fn is_low(&self) -> Result<bool, Self::Error> {
+            self.is_high().map(|high| !high)
+        }
+    }
+
+    impl Pin<'_> {
+        fn set(&mut self, high: bool) {
+            let mut chip = self.chip.borrow_mut();
+            if self.clk {
+                chip.set_clk(high);
+            } else {
+                chip.set_dio(high);
+            }
+        }
+    }
+
+    fn bit_bang(chip: &RefCell<Chip>) -> Tm1650BitBang<Pin<'_>, Pin<'_>, impl FnMut(u16)> {
+        let dio = Pin { chip, clk: false };
+        let clk = Pin { chip, clk: true };
+        Tm1650BitBang::new(dio, clk, |_| {}, TM1650_TIMING)
+    }
+
+    #[test]
+    fn bit_bang_write_test() {
+        let chip = Chip::new(true, 0);
+        bit_bang(&chip)
+            .write(TM1650_COM_DIGIT, 0b1000_0110)
+            .unwrap();
+        assert_eq!(
+            chip.borrow().events(),
+            [
+                Some(Event::Start),
+                Some(Event::Write(TM1650_COM_DIGIT)),
+                Some(Event::Write(0b1000_0110)),
+                Some(Event::Stop),
+            ]
+        );
+
+        let chip = Chip::new(false, 0);
+        let res = bit_bang(&chip).write(TM1650_COM_CONTROL, 0x11);
+        assert!(matches!(
+            res,
+            Err(TmError::Ack {
+                byte: 0,
+                phase: AckPhase::Rising9,
+                ..
+            })
+        ));
+        // Bus is still put into idle state
+        assert_eq!(chip.borrow().events().last(), Some(&Some(Event::Stop)));
+    }
+
+    #[cfg(feature = "keys")]
+    #[test]
+    fn bit_bang_read_test() {
+        let chip = Chip::new(true, 0b0100_1101);
+        assert_eq!(
+            bit_bang(&chip).read(TM1650_COM_READ_KEY).unwrap(),
+            0b0100_1101
+        );
+        assert_eq!(
+            chip.borrow().events(),
+            [
+                Some(Event::Start),
+                Some(Event::Write(TM1650_COM_READ_KEY)),
+                Some(Event::Read(0b0100_1101, true)),
+                Some(Event::Stop),
+            ]
+        );
+    }
+
+    /// I2C peripheral that remembers the last transfer.
+    #[derive(Default)]
+    struct I2cBus {
+        address: u8,
+        data: u8,
+    }
+
+    impl i2c::Write for I2cBus {
+        type Error = Infallible;
+
+        fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+            self.address = address;
+            self.data = bytes[0];
+            Ok(())
+        }
+    }
+
+    impl i2c::Read for I2cBus {
+        type Error = Infallible;
+
+        fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
+            self.address = address;
+            buffer[0] = self.data;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn i2c_address_test() {
+        let mut iface = Tm1650I2c::new(I2cBus::default());
+        iface.write(TM1650_COM_DIGIT + 2, CHAR_7).unwrap();
+        assert_eq!(iface.i2c.address, 0x35);
+        assert_eq!(iface.i2c.data, CHAR_7);
+        iface.write(TM1650_COM_CONTROL, 0x11).unwrap();
+        assert_eq!(iface.i2c.address, 0x24);
+
+        #[cfg(feature = "keys")]
+        {
+            assert_eq!(iface.read(TM1650_COM_READ_KEY).unwrap(), 0x11);
+            assert_eq!(iface.i2c.address, 0x27);
+        }
+    }
+
+    #[test]
+    fn display_control_test() {
+        assert_eq!(display_control(0, false, true), 0b0001_0001);
+        assert_eq!(display_control(6, true, true), 0b0111_1001);
+        assert_eq!(display_control(7, false, false), 0b0000_0000);
+    }
+
+    #[test]
+    fn decode_key_test() {
+        assert_eq!(
+            decode_key(0x44),
+            Some(Tm1650Key {
+                row: 0,
+                column: 0,
+                pressed: true
+            })
+        );
+        assert_eq!(
+            decode_key(0x77),
+            Some(Tm1650Key {
+                row: 6,
+                column: 3,
+                pressed: true
+            })
+        );
+        assert_eq!(
+            decode_key(0x0D),
+            Some(Tm1650Key {
+                row: 1,
+                column: 1,
+                pressed: false
+            })
+        );
+        assert_eq!(decode_key(0x00), None);
+        assert_eq!(decode_key(0x7C), None);
+    }
+}
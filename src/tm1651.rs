@@ -0,0 +1,209 @@
+//! High level driver for TM1651 (2 wire interface, 4 grids with 7 segments).
+//!
+//! TM1651 talks exactly like TM1637, thus this driver is built on top of [`Tm1637`].
+//! Usually it is wired to LED bar graphs and battery level indicators,
+//! so driver treats all LEDs as one bar: first grid segments 1-7, then second grid and so on.
+use super::tm1637::Tm1637;
+use super::*;
+
+/// Maximum number of bar graph LEDs (4 grids x 7 segments).
+pub const TM1651_BAR_LEVELS: u8 = 28;
+
+/// TM1651 bar graph driver that owns its DIO, CLK pins and delay closure.
+pub struct Tm1651<DIO, CLK, D> {
+    tm: Tm1637<DIO, CLK, D>,
+    levels: u8,
+}
+
+impl<DIO, CLK, D, E, EC> Tm1651<DIO, CLK, D>
+where
+    DIO: Dio<Error = E>,
+    CLK: OutputPin<Error = EC>,
+    D: BusDelay,
+{
+    /// Creates new driver instance without touching the bus.
+    /// Call [`init`](Tm1651::init) before writing anything.
+    ///
+    /// Arguments:
+    ///  - `dio`, `clk` - MCU interface pins
+    ///  - `delay_us` - closure that provides delay functionality
+    ///  - `timing` - bus timing or single delay value in us, see [`TM1637_TIMING`]
+    ///  - `levels` - number of LEDs in bar graph, values above [`TM1651_BAR_LEVELS`] are cut
+    pub fn new<T: Into<BusTiming>>(
+        dio: DIO,
+        clk: CLK,
+        delay_us: D,
+        timing: T,
+        levels: u8,
+    ) -> Tm1651<DIO, CLK, D> {
+        Tm1651 {
+            tm: Tm1637::new(dio, clk, delay_us, timing),
+            levels: levels.min(TM1651_BAR_LEVELS),
+        }
+    }
+
+    /// Power-on initialization.
+    ///
+    /// Puts bus into idle state, selects address auto increment mode,
+    /// clears all grids and turns display on with current brightness.
+    pub fn init(&mut self) -> Result<(), TmError<E, EC>> {
+        self.tm.bus_idle()?;
+        self.tm.send(&[COM_DATA_ADDRESS_ADD])?;
+        self.write_grids(&[0; TM1651_MAX_SEGMENTS as usize])?;
+        self.tm.display_on()
+    }
+
+    /// Number of LEDs in bar graph.
+    pub fn levels(&self) -> u8 {
+        self.levels
+    }
+
+    /// Lights first `level` LEDs of bar graph, 0 turns all of them off.
+    pub fn set_level(&mut self, level: u8) -> Result<(), TmError<E, EC>> {
+        if level > self.levels {
+            return Err(TmError::Input);
+        }
+        self.write_grids(&level_mask(level))
+    }
+
+    /// Lights bar graph proportionally to `percent` value (0-100).
+    pub fn set_percent(&mut self, percent: u8) -> Result<(), TmError<E, EC>> {
+        if percent > 100 {
+            return Err(TmError::Input);
+        }
+        self.set_level(percent_to_level(percent, self.levels))
+    }
+
+    /// Writes raw grid bytes, up to [`TM1651_MAX_SEGMENTS`] bytes are allowed.
+    pub fn write_grids(&mut self, grids: &[u8]) -> Result<(), TmError<E, EC>> {
+        if grids.len() > TM1651_MAX_SEGMENTS as usize {
+            return Err(TmError::Input);
+        }
+        self.tm.write_digits(grids)
+    }
+
+    /// Set brightness level from 0 (dimmest) to 7 (brightest).
+    pub fn set_brightness(&mut self, level: u8) -> Result<(), TmError<E, EC>> {
+        self.tm.set_brightness(level)
+    }
+
+    /// Turns display on keeping current brightness.
+    pub fn display_on(&mut self) -> Result<(), TmError<E, EC>> {
+        self.tm.display_on()
+    }
+
+    /// Turns display off. Display memory is kept intact.
+    pub fn display_off(&mut self) -> Result<(), TmError<E, EC>> {
+        self.tm.display_off()
+    }
+
+    /// Sets how transfers are repeated when chip misses ACK, see [`Tm1637::set_retry_policy`].
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.tm.set_retry_policy(policy)
+    }
+
+    /// Number of transfer attempts failed because of missing ACK.
+    pub fn failed_attempts(&self) -> u32 {
+        self.tm.failed_attempts()
+    }
+
+    /// Destroys driver and gives back pins and delay closure.
+    pub fn release(self) -> (DIO, CLK, D) {
+        self.tm.release()
+    }
+}
+
+#[cfg(feature = "eh1")]
+impl<DIO, CLK, DL> Tm1651<eh1::Pin<DIO>, eh1::Pin<CLK>, eh1::Delay<DL>>
+where
+    DIO: embedded_hal_1::digital::InputPin + embedded_hal_1::digital::OutputPin,
+    CLK: embedded_hal_1::digital::OutputPin,
+    DL: embedded_hal_1::delay::DelayNs,
+{
+    /// Same as [`new`](Tm1651::new) but takes embedded-hal 1.0 pins and `DelayNs` as is.
+    pub fn new_eh1<T: Into<BusTiming>>(
+        dio: DIO,
+        clk: CLK,
+        delay: DL,
+        timing: T,
+        levels: u8,
+    ) -> Self {
+        Tm1651::new(
+            eh1::Pin::new(dio),
+            eh1::Pin::new(clk),
+            eh1::Delay::new(delay),
+            timing,
+            levels,
+        )
+    }
+}
+
+/// Builds grid bytes with first `level` bar graph LEDs turned on.
+pub fn level_mask(level: u8) -> [u8; TM1651_MAX_SEGMENTS as usize] {
+    let mut grids = [0_u8; TM1651_MAX_SEGMENTS as usize];
+    for n in 0..level.min(TM1651_BAR_LEVELS) {
+        grids[(n / 7) as usize] |= 1 << (n % 7);
+    }
+    grids
+}
+
+/// Converts percent value (0-100) to number of lit LEDs rounding to the nearest one.
+pub fn percent_to_level(percent: u8, levels: u8) -> u8 {
+    let percent = percent.min(100) as u16;
+    ((percent * levels as u16 + 50) / 100) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::{SimChip, SimPin};
+    use core::cell::RefCell;
+
+    #[test]
+    fn init_level_test() {
+        let chip = RefCell::new(SimChip::tm1637());
+        let mut tm = Tm1651::new(SimPin::dio(&chip), SimPin::clk(&chip), |_| {}, 0, 10);
+        tm.write_grids(&[0xFF; 4]).unwrap();
+        tm.init().unwrap();
+        assert_eq!(&chip.borrow().ram()[..4], &[0; 4]);
+        assert!(chip.borrow().is_display_on());
+
+        tm.set_level(9).unwrap();
+        assert_eq!(&chip.borrow().ram()[..4], &[0x7F, SEG_1 | SEG_2, 0, 0]);
+        assert!(matches!(tm.set_level(11), Err(TmError::Input)));
+
+        // 45% of 10 LEDs is rounded up to 5
+        tm.set_percent(45).unwrap();
+        assert_eq!(&chip.borrow().ram()[..4], &[0b1_1111, 0, 0, 0]);
+        tm.set_percent(100).unwrap();
+        assert_eq!(
+            &chip.borrow().ram()[..4],
+            &[0x7F, SEG_1 | SEG_2 | SEG_3, 0, 0]
+        );
+        tm.set_percent(0).unwrap();
+        assert_eq!(&chip.borrow().ram()[..4], &[0; 4]);
+        assert!(matches!(tm.set_percent(101), Err(TmError::Input)));
+
+        assert_eq!(chip.borrow().errors(), 0);
+    }
+
+    #[test]
+    fn level_mask_test() {
+        assert_eq!(level_mask(0), [0, 0, 0, 0]);
+        assert_eq!(level_mask(1), [SEG_1, 0, 0, 0]);
+        assert_eq!(level_mask(7), [0x7F, 0, 0, 0]);
+        assert_eq!(level_mask(9), [0x7F, SEG_1 | SEG_2, 0, 0]);
+        assert_eq!(level_mask(28), [0x7F; 4]);
+        assert_eq!(level_mask(100), [0x7F; 4]);
+    }
+
+    #[test]
+    fn percent_to_level_test() {
+        assert_eq!(percent_to_level(0, 10), 0);
+        assert_eq!(percent_to_level(4, 10), 0);
+        assert_eq!(percent_to_level(5, 10), 1);
+        assert_eq!(percent_to_level(50, 7), 4);
+        assert_eq!(percent_to_level(100, 28), 28);
+        assert_eq!(percent_to_level(200, 28), 28);
+    }
+}
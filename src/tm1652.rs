@@ -0,0 +1,331 @@
+//! High level driver for TM1652 (1 wire UART like interface, 6 displays, no keys).
+//!
+//! TM1652 listens to a single data line with UART frames at 19200 baud:
+//! start bit, 8 data bits LSB first, odd parity and stop bit.
+//! Thus driver works through [`Tm1652Interface`] either with serial peripheral ([`Tm1652Serial`])
+//! or with bit banged pin ([`Tm1652BitBang`]).
+//!
+//! **Keep in mind** that serial peripheral must be configured for 19200 baud, 8 bits and odd parity on your side.
+use super::*;
+
+use embedded_hal::serial;
+
+/// Display address command, followed by display bytes starting from the first grid.
+pub const TM1652_COM_ADDRESS: u8 = 0x08;
+/// Display control command, followed by display control byte.
+pub const TM1652_COM_CONTROL: u8 = 0x18;
+
+/// Minimal pause between two commands.
+pub const TM1652_FRAME_DELAY_US: u16 = 3000;
+
+/// Data transfer layer for TM1652.
+pub trait Tm1652Interface {
+    /// Error type of underlying pin or peripheral.
+    type Error;
+
+    /// Sends one command: command byte followed by data bytes.
+    /// Implementation must keep line idle for [`TM1652_FRAME_DELAY_US`] after that.
+    fn send(&mut self, bytes: &[u8]) -> Result<(), TmError<Self::Error>>;
+}
+
+/// TM1652 interface over serial peripheral.
+pub struct Tm1652Serial<S, D> {
+    serial: S,
+    delay_us: D,
+}
+
+impl<S, D> Tm1652Serial<S, D>
+where
+    S: serial::Write<u8>,
+    D: BusDelay,
+{
+    /// Creates interface on top of serial peripheral.
+    ///
+    /// Arguments:
+    ///  - `serial` - serial peripheral configured for 19200 baud, 8 bits and odd parity
+    ///  - `delay_us` - closure that provides delay functionality, used for pauses between commands
+    pub fn new(serial: S, delay_us: D) -> Tm1652Serial<S, D> {
+        Tm1652Serial { serial, delay_us }
+    }
+
+    /// Destroys interface and gives back serial peripheral and delay closure.
+    pub fn release(self) -> (S, D) {
+        (self.serial, self.delay_us)
+    }
+}
+
+impl<S, D> Tm1652Interface for Tm1652Serial<S, D>
+where
+    S: serial::Write<u8>,
+    D: BusDelay,
+{
+    type Error = S::Error;
+
+    fn send(&mut self, bytes: &[u8]) -> Result<(), TmError<S::Error>> {
+        for bt in bytes {
+            nb::block!(self.serial.write(*bt)).map_err(TmError::Bus)?;
+        }
+        nb::block!(self.serial.flush()).map_err(TmError::Bus)?;
+        self.delay_us.delay_us(TM1652_FRAME_DELAY_US);
+        Ok(())
+    }
+}
+
+/// Bit banged TM1652 interface, see [`tm_send_bytes_1wire`].
+///
+/// Before the first command DIO is set high for one frame time, so the chip sees idle line.
+pub struct Tm1652BitBang<DIO, D> {
+    dio: DIO,
+    delay_us: D,
+    delay_value: u16,
+    idle: bool,
+}
+
+impl<DIO, D> Tm1652BitBang<DIO, D>
+where
+    DIO: OutputPin,
+    D: BusDelay,
+{
+    /// Creates bit banged interface.
+    ///
+    /// Arguments:
+    ///  - `dio` - MCU interface pin
+    ///  - `delay_us` - closure that provides delay functionality
+    ///  - `delay_value` - duration of one bit in us, see [`TM1652_BIT_DELAY_US`]
+    pub fn new(dio: DIO, delay_us: D, delay_value: u16) -> Tm1652BitBang<DIO, D> {
+        Tm1652BitBang {
+            dio,
+            delay_us,
+            delay_value,
+            idle: false,
+        }
+    }
+
+    /// Destroys interface and gives back pin and delay closure.
+    pub fn release(self) -> (DIO, D) {
+        (self.dio, self.delay_us)
+    }
+}
+
+#[cfg(feature = "eh1")]
+impl<DIO, DL> Tm1652BitBang<eh1::Pin<DIO>, eh1::Delay<DL>>
+where
+    DIO: embedded_hal_1::digital::OutputPin,
+    DL: embedded_hal_1::delay::DelayNs,
+{
+    /// Same as [`new`](Tm1652BitBang::new) but takes embedded-hal 1.0 pin and `DelayNs` as is.
+    pub fn new_eh1(dio: DIO, delay: DL, delay_value: u16) -> Self {
+        Tm1652BitBang::new(eh1::Pin::new(dio), eh1::Delay::new(delay), delay_value)
+    }
+}
+
+impl<DIO, D> Tm1652Interface for Tm1652BitBang<DIO, D>
+where
+    DIO: OutputPin,
+    D: BusDelay,
+{
+    type Error = DIO::Error;
+
+    fn send(&mut self, bytes: &[u8]) -> Result<(), TmError<DIO::Error>> {
+        if !self.idle {
+            // Line could be low after power-up, chip must see it idle before the first start bit
+            self.dio.set_high().map_err(TmError::Dio)?;
+            self.delay_us.delay_us(self.delay_value.saturating_mul(11));
+            self.idle = true;
+        }
+        tm_send_bytes_1wire(&mut self.dio, &mut self.delay_us, self.delay_value, bytes)?;
+        self.delay_us.delay_us(TM1652_FRAME_DELAY_US);
+        Ok(())
+    }
+}
+
+/// TM1652 driver working through [`Tm1652Interface`].
+pub struct Tm1652<I> {
+    iface: I,
+    duty: u8,
+    current: u8,
+    six_grids: bool,
+    display_on: bool,
+}
+
+impl<I> Tm1652<I>
+where
+    I: Tm1652Interface,
+{
+    /// Creates new driver instance without touching the bus.
+    /// Call [`init`](Tm1652::init) before writing anything.
+    pub fn new(iface: I) -> Tm1652<I> {
+        Tm1652 {
+            iface,
+            duty: 15,
+            current: 7,
+            six_grids: true,
+            display_on: true,
+        }
+    }
+
+    /// Power-on initialization, clears all displays and applies display control.
+    pub fn init(&mut self) -> Result<(), TmError<I::Error>> {
+        self.clear()?;
+        self.send_display_control()
+    }
+
+    /// Writes display bytes starting from the first display.
+    /// Up to [`TM1652_MAX_SEGMENTS`] bytes are allowed.
+    pub fn write_digits(&mut self, digits: &[u8]) -> Result<(), TmError<I::Error>> {
+        if digits.len() > TM1652_MAX_SEGMENTS as usize {
+            return Err(TmError::Input);
+        }
+        let mut bytes = [0_u8; 1 + TM1652_MAX_SEGMENTS as usize];
+        bytes[0] = TM1652_COM_ADDRESS;
+        bytes[1..=digits.len()].copy_from_slice(digits);
+        self.iface.send(&bytes[..=digits.len()])
+    }
+
+    /// Turns all displays off by writing empty bytes.
+    pub fn clear(&mut self) -> Result<(), TmError<I::Error>> {
+        self.write_digits(&[0; TM1652_MAX_SEGMENTS as usize])
+    }
+
+    /// Set brightness level from 0 (dimmest) to 7 (brightest).
+    /// It is a shortcut for [`set_duty`](Tm1652::set_duty) with odd duty values.
+    pub fn set_brightness(&mut self, level: u8) -> Result<(), TmError<I::Error>> {
+        if level > DISPLAY_BRIGHTNESS_MASK {
+            return Err(TmError::Input);
+        }
+        self.set_duty(level * 2 + 1)
+    }
+
+    /// Set display duty cycle from 1/16 to 15/16 as 1-15, 0 would turn display off.
+    pub fn set_duty(&mut self, duty: u8) -> Result<(), TmError<I::Error>> {
+        if duty > 15 {
+            return Err(TmError::Input);
+        }
+        self.duty = duty;
+        self.send_display_control()
+    }
+
+    /// Set segment drive current from 1/8 to 8/8 as 0-7.
+    pub fn set_drive_current(&mut self, current: u8) -> Result<(), TmError<I::Error>> {
+        if current > 7 {
+            return Err(TmError::Input);
+        }
+        self.current = current;
+        self.send_display_control()
+    }
+
+    /// Switch between 6 grids (true) and 5 grids (false) mode.
+    pub fn set_six_grids(&mut self, six_grids: bool) -> Result<(), TmError<I::Error>> {
+        self.six_grids = six_grids;
+        self.send_display_control()
+    }
+
+    /// Turns display on keeping current duty.
+    pub fn display_on(&mut self) -> Result<(), TmError<I::Error>> {
+        self.display_on = true;
+        self.send_display_control()
+    }
+
+    /// Turns display off. Display memory is kept intact.
+    pub fn display_off(&mut self) -> Result<(), TmError<I::Error>> {
+        self.display_on = false;
+        self.send_display_control()
+    }
+
+    /// Destroys driver and gives back interface.
+    pub fn release(self) -> I {
+        self.iface
+    }
+
+    fn send_display_control(&mut self) -> Result<(), TmError<I::Error>> {
+        let duty = if self.display_on { self.duty } else { 0 };
+        self.iface.send(&[
+            TM1652_COM_CONTROL,
+            display_control(duty, self.current, self.six_grids),
+        ])
+    }
+}
+
+/// Builds display control byte.
+///
+/// TM1652 expects duty (bits 7-4) and drive current (bits 3-1) in reversed bit order,
+/// bit 0 selects 6 grids mode.
+fn display_control(duty: u8, current: u8, six_grids: bool) -> u8 {
+    let duty = (duty & 0b1111).reverse_bits() >> 4;
+    let current = (current & 0b111).reverse_bits() >> 5;
+    (duty << 4) | (current << 1) | six_grids as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::{Cell, RefCell};
+    use core::convert::Infallible;
+
+    struct Pin<'a>(&'a Cell<bool>);
+
+    impl OutputPin for Pin<'_> {
+        type Error = Infallible;
+
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            self.0.set(false);
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            self.0.set(true);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn bit_bang_frame_test() {
+        let level = Cell::new(false);
+        // DIO level for every delay and delay value
+        let trace = RefCell::new([(false, 0_u16); 64]);
+        let len = Cell::new(0);
+        let delay = |us| {
+            trace.borrow_mut()[len.get()] = (level.get(), us);
+            len.set(len.get() + 1);
+        };
+        let mut iface = Tm1652BitBang::new(Pin(&level), delay, TM1652_BIT_DELAY_US);
+        iface.send(&[0x00, 0xFF, 0x01, 0x35]).unwrap();
+
+        {
+            let trace = trace.borrow();
+            let trace = &trace[..len.get()];
+            // Line goes idle for one frame before the first start bit
+            assert_eq!(trace[0], (true, TM1652_BIT_DELAY_US * 11));
+            assert_eq!(trace[trace.len() - 1], (true, TM1652_FRAME_DELAY_US));
+
+            let bits = &trace[1..trace.len() - 1];
+            assert!(bits.iter().all(|(_, us)| *us == TM1652_BIT_DELAY_US));
+            let frames: [[u8; 11]; 4] = [
+                // start, data LSB first, odd parity, stop
+                [0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1],
+                [0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1],
+                [0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+                [0, 1, 0, 1, 0, 1, 1, 0, 0, 1, 1],
+            ];
+            assert_eq!(bits.len(), frames.len() * 11);
+            for (frame, expected) in bits.chunks(11).zip(frames.iter()) {
+                for ((high, _), bit) in frame.iter().zip(expected.iter()) {
+                    assert_eq!(*high, *bit == 1);
+                }
+            }
+        }
+
+        // Idle pause is done only once
+        len.set(0);
+        iface.send(&[0x08]).unwrap();
+        assert_eq!(len.get(), 12);
+    }
+
+    #[test]
+    fn display_control_test() {
+        assert_eq!(display_control(0, 0, false), 0);
+        assert_eq!(display_control(15, 7, true), 0xFF);
+        assert_eq!(display_control(1, 0, false), 0b1000_0000);
+        assert_eq!(display_control(0b0011, 0b001, true), 0b1100_1001);
+    }
+}
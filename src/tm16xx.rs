@@ -0,0 +1,474 @@
+//! High level driver for 3 wire chips with display mode setting command: TM1628, TM1668, TM1629.
+//! Princeton PT6311 and PT6312 VFD controllers are described in [`pt631x`] module.
+//!
+//! This chips share 3 wire protocol and commands with TM1638,
+//! but display memory layout and number of grids depends on display mode
+//! and key scan response has chip specific length.
+//! Everything that differs is described by [`Chip`] implementations,
+//! so one [`Tm16xx`] driver serves all of them.
+use super::*;
+
+use core::marker::PhantomData;
+use core::ops::Deref;
+
+/// Maximum number of key scan bytes supported by [`KeyScan`].
+pub const KEY_SCAN_MAX_SIZE: usize = 6;
+
+/// Maximum display memory size among supported chips.
+const RAM_MAX_SIZE: usize = 48;
+
+/// Describes chip specific details for [`Tm16xx`] driver.
+pub trait Chip {
+    /// Display mode (number of grids and segments).
+    type Mode: Copy;
+    /// Size of display memory in bytes, valid addresses are `0..RAM_SIZE`.
+    const RAM_SIZE: u8;
+    /// Number of display memory bytes used by one grid.
+    const BYTES_PER_GRID: u8;
+    /// Number of key scan bytes returned by chip.
+    const KEY_BYTES: u8;
+
+    /// Display mode setting command for given mode, `None` if chip has no such command.
+    fn mode_command(mode: Self::Mode) -> Option<u8>;
+
+    /// Number of grids driven in given mode.
+    /// By default every grid that fits into display memory is counted.
+    fn grids(mode: Self::Mode) -> u8 {
+        let _ = mode;
+        Self::RAM_SIZE / Self::BYTES_PER_GRID
+    }
+}
+
+/// Chips that have LED output port and switch inputs besides key scan matrix.
+pub trait SwitchPort: Chip {
+    /// Data setting command for writing LED port.
+    const COM_WRITE_LED: u8;
+    /// Data setting command for reading switch inputs.
+    const COM_READ_SWITCH: u8;
+}
+
+/// Display modes for TM1628 and TM1668.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tm1628Mode {
+    Grid4Seg13 = 0b00,
+    Grid5Seg12 = 0b01,
+    Grid6Seg11 = 0b10,
+    Grid7Seg10 = 0b11,
+}
+
+impl Tm1628Mode {
+    /// Number of grids in this mode.
+    pub fn grids(self) -> u8 {
+        4 + self as u8
+    }
+}
+
+/// Display modes for TM1629, it has only one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tm1629Mode {
+    Grid8Seg16,
+}
+
+/// TM1628: up to 7 grids and 13 segments, 20 keys (5 key scan bytes).
+pub struct Tm1628;
+
+/// TM1668: up to 7 grids and 13 segments, 20 keys (5 key scan bytes).
+pub struct Tm1668;
+
+/// TM1629: 8 grids and 16 segments, 32 keys (4 key scan bytes).
+pub struct Tm1629;
+
+impl Chip for Tm1628 {
+    type Mode = Tm1628Mode;
+    const RAM_SIZE: u8 = 14;
+    const BYTES_PER_GRID: u8 = 2;
+    const KEY_BYTES: u8 = TM1628_RESPONSE_SIZE;
+
+    fn mode_command(mode: Tm1628Mode) -> Option<u8> {
+        Some(COM_DISPLAY_MODE | mode as u8)
+    }
+
+    fn grids(mode: Tm1628Mode) -> u8 {
+        mode.grids()
+    }
+}
+
+impl Chip for Tm1668 {
+    type Mode = Tm1628Mode;
+    const RAM_SIZE: u8 = 14;
+    const BYTES_PER_GRID: u8 = 2;
+    const KEY_BYTES: u8 = TM1668_RESPONSE_SIZE;
+
+    fn mode_command(mode: Tm1628Mode) -> Option<u8> {
+        Some(COM_DISPLAY_MODE | mode as u8)
+    }
+
+    fn grids(mode: Tm1628Mode) -> u8 {
+        mode.grids()
+    }
+}
+
+impl Chip for Tm1629 {
+    type Mode = Tm1629Mode;
+    const RAM_SIZE: u8 = 16;
+    const BYTES_PER_GRID: u8 = 2;
+    const KEY_BYTES: u8 = TM1629_RESPONSE_SIZE;
+
+    fn mode_command(_: Tm1629Mode) -> Option<u8> {
+        None
+    }
+}
+
+/// Raw key scan bytes with chip specific length.
+/// You could deref this structure as slice.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyScan {
+    len: usize,
+    bytes: [u8; KEY_SCAN_MAX_SIZE],
+}
+
+impl Deref for KeyScan {
+    type Target = [u8];
+    fn deref(&self) -> &Self::Target {
+        &self.bytes[..self.len]
+    }
+}
+
+/// Driver for 3 wire chips described by [`Chip`], owns its DIO, CLK, STB pins and delay closure.
+pub struct Tm16xx<C: Chip, DIO, CLK, STB, D> {
+    dio: DIO,
+    clk: CLK,
+    stb: STB,
+    delay_us: D,
+    timing: BusTiming,
+    mode: C::Mode,
+    brightness: u8,
+    display_on: bool,
+    chip: PhantomData<C>,
+}
+
+impl<C, DIO, CLK, STB, D, E, EC, ES> Tm16xx<C, DIO, CLK, STB, D>
+where
+    C: Chip,
+    DIO: Dio<Error = E>,
+    CLK: OutputPin<Error = EC>,
+    STB: OutputPin<Error = ES>,
+    D: BusDelay,
+{
+    /// Creates new driver instance without touching the bus.
+    /// Call [`init`](Tm16xx::init) before writing anything.
+    ///
+    /// Arguments:
+    ///  - `dio`, `clk`, `stb` - MCU interface pins
+    ///  - `delay_us` - closure that provides delay functionality
+    ///  - `timing` - bus timing or single delay value in us, see [`TM1638_TIMING`]
+    ///  - `mode` - display mode that would be applied on init
+    pub fn new<T: Into<BusTiming>>(
+        dio: DIO,
+        clk: CLK,
+        stb: STB,
+        delay_us: D,
+        timing: T,
+        mode: C::Mode,
+    ) -> Tm16xx<C, DIO, CLK, STB, D> {
+        Tm16xx {
+            dio,
+            clk,
+            stb,
+            delay_us,
+            timing: timing.into(),
+            mode,
+            brightness: DISPLAY_BRIGHTNESS_MASK,
+            display_on: true,
+            chip: PhantomData,
+        }
+    }
+
+    /// Power-on initialization.
+    ///
+    /// Puts bus into idle state, sets display mode, selects address auto increment mode,
+    /// clears display memory and turns display on with current brightness.
+    pub fn init(&mut self) -> Result<(), TmError<E, EC, ES>> {
+        self.clk.set_high().map_err(TmError::Clk)?;
+        self.dio.set_dio(true).map_err(TmError::Dio)?;
+        self.stb.set_high().map_err(TmError::Stb)?;
+        self.send_mode()?;
+        self.send(&[COM_DATA_ADDRESS_ADD])?;
+        self.clear()?;
+        self.send_display_control()
+    }
+
+    /// Changes display mode (number of grids and segments).
+    pub fn set_mode(&mut self, mode: C::Mode) -> Result<(), TmError<E, EC, ES>> {
+        self.mode = mode;
+        self.send_mode()
+    }
+
+    /// Writes raw bytes into display memory starting from `address`.
+    /// Whole write must fit into chip memory, see [`Chip::RAM_SIZE`].
+    pub fn write_ram(&mut self, address: u8, bytes: &[u8]) -> Result<(), TmError<E, EC, ES>> {
+        if address as usize + bytes.len() > Self::ram_size() {
+            return Err(TmError::Input);
+        }
+        let mut buf = [0_u8; 1 + RAM_MAX_SIZE];
+        buf[0] = COM_ADDRESS | address;
+        buf[1..=bytes.len()].copy_from_slice(bytes);
+        self.send(&buf[..=bytes.len()])
+    }
+
+    /// Number of grids driven in current display mode.
+    pub fn grids(&self) -> u8 {
+        C::grids(self.mode)
+    }
+
+    /// Writes segments of one grid, segment 1 is the lowest bit.
+    /// Only grids driven in current display mode could be written.
+    pub fn write_grid(&mut self, index: u8, segments: u32) -> Result<(), TmError<E, EC, ES>> {
+        if index >= self.grids() {
+            return Err(TmError::Input);
+        }
+        let bytes = segments.to_le_bytes();
+        self.write_ram(
+            index * C::BYTES_PER_GRID,
+            &bytes[..C::BYTES_PER_GRID as usize],
+        )
+    }
+
+    /// Writes display bytes (segments 1-8) starting from the first grid.
+    /// Other segments are cleared for every written grid.
+    /// Up to [`grids`](Tm16xx::grids) bytes are allowed.
+    pub fn write_digits(&mut self, digits: &[u8]) -> Result<(), TmError<E, EC, ES>> {
+        let stride = C::BYTES_PER_GRID as usize;
+        if digits.len() > self.grids() as usize || digits.len() * stride > Self::ram_size() {
+            return Err(TmError::Input);
+        }
+        let mut bytes = [0_u8; RAM_MAX_SIZE];
+        for (i, d) in digits.iter().enumerate() {
+            bytes[i * stride] = *d;
+        }
+        self.write_ram(0, &bytes[..digits.len() * stride])
+    }
+
+    /// Clears whole display memory.
+    pub fn clear(&mut self) -> Result<(), TmError<E, EC, ES>> {
+        let bytes = [0_u8; RAM_MAX_SIZE];
+        self.write_ram(0, &bytes[..Self::ram_size()])
+    }
+
+    /// Set brightness level from 0 (dimmest) to 7 (brightest).
+    pub fn set_brightness(&mut self, level: u8) -> Result<(), TmError<E, EC, ES>> {
+        if level > DISPLAY_BRIGHTNESS_MASK {
+            return Err(TmError::Input);
+        }
+        self.brightness = level;
+        self.send_display_control()
+    }
+
+    /// Turns display on keeping current brightness.
+    pub fn display_on(&mut self) -> Result<(), TmError<E, EC, ES>> {
+        self.display_on = true;
+        self.send_display_control()
+    }
+
+    /// Turns display off. Display memory is kept intact.
+    pub fn display_off(&mut self) -> Result<(), TmError<E, EC, ES>> {
+        self.display_on = false;
+        self.send_display_control()
+    }
+
+    /// Reads raw key scan bytes, number of bytes is [`Chip::KEY_BYTES`].
+    #[cfg(feature = "keys")]
+    pub fn read_keys(&mut self) -> Result<KeyScan, TmError<E, EC, ES>> {
+        let mut scan = KeyScan {
+            len: C::KEY_BYTES as usize,
+            bytes: [0; KEY_SCAN_MAX_SIZE],
+        };
+        tm_bus_3wire_read(
+            &mut self.dio,
+            &mut self.clk,
+            &mut self.stb,
+            &mut self.delay_us,
+            &self.timing,
+            COM_DATA_READ,
+            &mut scan.bytes[..scan.len],
+        )?;
+        Ok(scan)
+    }
+
+    /// Sends raw bytes to MCU, see [`tm_send_bytes_3wire`].
+    pub fn send(&mut self, bytes: &[u8]) -> Result<(), TmError<E, EC, ES>> {
+        tm_send_bytes_3wire(
+            &mut self.dio,
+            &mut self.clk,
+            &mut self.stb,
+            &mut self.delay_us,
+            self.timing,
+            bytes,
+        )
+    }
+
+    /// Destroys driver and gives back pins and delay closure.
+    pub fn release(self) -> (DIO, CLK, STB, D) {
+        (self.dio, self.clk, self.stb, self.delay_us)
+    }
+
+    fn ram_size() -> usize {
+        (C::RAM_SIZE as usize).min(RAM_MAX_SIZE)
+    }
+
+    fn send_mode(&mut self) -> Result<(), TmError<E, EC, ES>> {
+        match C::mode_command(self.mode) {
+            Some(command) => self.send(&[command]),
+            None => Ok(()),
+        }
+    }
+
+    fn send_display_control(&mut self) -> Result<(), TmError<E, EC, ES>> {
+        self.send(&[tm_display_control(self.display_on, self.brightness)])
+    }
+}
+
+#[cfg(feature = "eh1")]
+impl<C, DIO, CLK, STB, DL> Tm16xx<C, eh1::Pin<DIO>, eh1::Pin<CLK>, eh1::Pin<STB>, eh1::Delay<DL>>
+where
+    C: Chip,
+    DIO: embedded_hal_1::digital::InputPin + embedded_hal_1::digital::OutputPin,
+    CLK: embedded_hal_1::digital::OutputPin,
+    STB: embedded_hal_1::digital::OutputPin,
+    DL: embedded_hal_1::delay::DelayNs,
+{
+    /// Same as [`new`](Tm16xx::new) but takes embedded-hal 1.0 pins and `DelayNs` as is.
+    pub fn new_eh1<T: Into<BusTiming>>(
+        dio: DIO,
+        clk: CLK,
+        stb: STB,
+        delay: DL,
+        timing: T,
+        mode: C::Mode,
+    ) -> Self {
+        Tm16xx::new(
+            eh1::Pin::new(dio),
+            eh1::Pin::new(clk),
+            eh1::Pin::new(stb),
+            eh1::Delay::new(delay),
+            timing,
+            mode,
+        )
+    }
+}
+
+impl<C, DIO, CLK, STB, D, E, EC, ES> Tm16xx<C, DIO, CLK, STB, D>
+where
+    C: SwitchPort,
+    DIO: Dio<Error = E>,
+    CLK: OutputPin<Error = EC>,
+    STB: OutputPin<Error = ES>,
+    D: BusDelay,
+{
+    /// Writes LED output port, LED1 is the lowest bit.
+    pub fn write_leds(&mut self, leds: u8) -> Result<(), TmError<E, EC, ES>> {
+        self.send(&[C::COM_WRITE_LED, leds])?;
+        // Return back to display memory writing
+        self.send(&[COM_DATA_ADDRESS_ADD])
+    }
+
+    /// Reads raw switch inputs byte.
+    #[cfg(feature = "keys")]
+    pub fn read_switches(&mut self) -> Result<u8, TmError<E, EC, ES>> {
+        let mut response = [0_u8; 1];
+        tm_bus_3wire_read(
+            &mut self.dio,
+            &mut self.clk,
+            &mut self.stb,
+            &mut self.delay_us,
+            &self.timing,
+            C::COM_READ_SWITCH,
+            &mut response,
+        )?;
+        Ok(response[0])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::{SimChip, SimPin};
+    use core::cell::RefCell;
+
+    fn driver<C: Chip>(
+        chip: &RefCell<SimChip>,
+        mode: C::Mode,
+    ) -> Tm16xx<C, SimPin<'_>, SimPin<'_>, SimPin<'_>, impl FnMut(u16)> {
+        Tm16xx::new(
+            SimPin::dio(chip),
+            SimPin::clk(chip),
+            SimPin::stb(chip),
+            |_| {},
+            0,
+            mode,
+        )
+    }
+
+    #[test]
+    fn chip_profile_test() {
+        assert_eq!(Tm1628::mode_command(Tm1628Mode::Grid6Seg11), Some(0b10));
+        assert_eq!(Tm1668::grids(Tm1628Mode::Grid4Seg13), 4);
+        assert_eq!(Tm1668::grids(Tm1628Mode::Grid7Seg10), 7);
+        assert_eq!(Tm1629::mode_command(Tm1629Mode::Grid8Seg16), None);
+        assert_eq!(Tm1629::grids(Tm1629Mode::Grid8Seg16), 8);
+    }
+
+    #[test]
+    fn mode_grids_test() {
+        let chip = RefCell::new(SimChip::tm16xx::<Tm1628>());
+        let mut tm = driver::<Tm1628>(&chip, Tm1628Mode::Grid4Seg13);
+        tm.init().unwrap();
+        assert_eq!(chip.borrow().display_mode(), Tm1628Mode::Grid4Seg13 as u8);
+        assert_eq!(tm.grids(), 4);
+
+        tm.write_grid(3, 0x1_0203).unwrap();
+        assert!(matches!(tm.write_grid(4, 0), Err(TmError::Input)));
+        assert!(matches!(tm.write_digits(&[CHAR_1; 5]), Err(TmError::Input)));
+
+        tm.set_mode(Tm1628Mode::Grid7Seg10).unwrap();
+        assert_eq!(chip.borrow().display_mode(), Tm1628Mode::Grid7Seg10 as u8);
+        tm.write_grid(6, 0x0201).unwrap();
+        assert!(matches!(tm.write_grid(7, 0), Err(TmError::Input)));
+
+        let chip = chip.borrow();
+        // Two bytes per grid, bits above 16th segment are dropped
+        assert_eq!(&chip.ram()[6..8], &[0x03, 0x02]);
+        assert_eq!(&chip.ram()[12..], &[0x01, 0x02]);
+        assert_eq!(chip.errors(), 0);
+    }
+
+    #[test]
+    fn write_digits_test() {
+        let chip = RefCell::new(SimChip::tm16xx::<Tm1629>());
+        let mut tm = driver::<Tm1629>(&chip, Tm1629Mode::Grid8Seg16);
+        tm.init().unwrap();
+        tm.write_ram(0, &[0xFF; 16]).unwrap();
+        tm.write_digits(&[CHAR_1, CHAR_2]).unwrap();
+        assert!(matches!(tm.write_ram(15, &[0, 0]), Err(TmError::Input)));
+
+        let chip = chip.borrow();
+        assert_eq!(&chip.ram()[..5], &[CHAR_1, 0, CHAR_2, 0, 0xFF]);
+        // No display mode command for TM1629
+        assert_eq!(chip.display_mode(), 0);
+        assert_eq!(chip.errors(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "keys")]
+    fn read_keys_test() {
+        let chip = RefCell::new(SimChip::tm16xx::<Tm1628>());
+        let mut tm = driver::<Tm1628>(&chip, Tm1628Mode::Grid7Seg10);
+        tm.init().unwrap();
+        chip.borrow_mut()
+            .set_key_scan(&[0b1000_0000, 0, 0, 0, 0b0000_0001]);
+
+        let scan = tm.read_keys().unwrap();
+        assert_eq!(&scan[..], &[0b0000_0001, 0, 0, 0, 0b1000_0000]);
+        assert_eq!(chip.borrow().errors(), 0);
+    }
+}
@@ -102,6 +102,7 @@ pub struct DoubleConvertResult {
 }
 
 impl DoubleConvertResult {
+    #[allow(clippy::needless_range_loop, clippy::redundant_field_names)]
     fn new(head: &[u8], tail: &[u8]) -> DoubleConvertResult {
         let mut offset = INT_CONVERT_MAX_SIZE * 2;
         let mut bytes = [0; INT_CONVERT_MAX_SIZE * 2];
@@ -135,6 +136,7 @@ impl Deref for DoubleConvertResult {
 
 /// Convert given integer value to appropriate bytes vector.
 /// Adds minus sign for negative values
+#[allow(clippy::neg_multiply)]
 pub fn int_to_bytes(value: i32) -> IntConvertResult {
     let mut result = IntConvertResult::new();
     let mut v = if value < 0 { value * -1 } else { value };
@@ -182,6 +184,7 @@ pub fn float_to_bytes_ex(value: f32, precision: u8, zero_pad: bool) -> DoubleCon
     DoubleConvertResult::new(&wresult, &fract_part)
 }
 
+#[allow(clippy::neg_multiply)]
 fn fractional_part_to_bytes(value: f32, precision: u8) -> IntConvertResult {
     let mut v = if value < 0.0 { value * -1.0 } else { value };
     let whole = v as i32;
@@ -224,6 +227,7 @@ fn fractional_part_to_bytes(value: f32, precision: u8) -> IntConvertResult {
 ///
 /// This method **require "galloc"** feature to be enabled.
 #[cfg(feature = "galloc")]
+#[allow(clippy::needless_range_loop)]
 pub fn double_bytes(input: &[u8]) -> Vec<u8> {
     let mut double_byte = Vec::<u8>::new();
     for i in 0..input.len() {
@@ -264,6 +268,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::excessive_precision)]
     fn float_to_bytes_test() {
         assert_eq!(float_to_bytes(0.0).deref(), &[DIGITS[0] | SEG_8, DIGITS[0]]);
 